@@ -0,0 +1,257 @@
+// ------------------------------------------------------------------------------------------------
+// Anomaly detection over message templates: rate spikes and templates that newly appear.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+use yblp::bucket_start;
+
+use crate::patterns::TemplateMiner;
+use crate::processes::without_severity_copies;
+use crate::YBLogLine;
+
+/// Buckets with fewer lines of a template than this are never reported as spikes, however quiet
+/// the baseline was.
+const MIN_SPIKE_COUNT: u64 = 5;
+
+pub(crate) struct AnomalyOptions {
+    pub(crate) bucket: Duration,
+    /// A bucket is a spike if its count exceeds the baseline by more than this factor.
+    pub(crate) spike_factor: f64,
+    /// Number of preceding buckets averaged into the rolling baseline. Templates first seen
+    /// within this many buckets from the start are not reported as new.
+    pub(crate) baseline_buckets: usize,
+    pub(crate) top: usize,
+}
+
+struct Spike {
+    bucket_ts: NaiveDateTime,
+    template_id: usize,
+    count: u64,
+    baseline: f64,
+}
+
+impl Spike {
+    fn ratio(&self) -> f64 {
+        self.count as f64 / self.baseline.max(1.0)
+    }
+}
+
+struct Anomalies {
+    miner: TemplateMiner,
+    /// The `top` strongest spikes, in time order.
+    spikes: Vec<Spike>,
+    /// The bucket each new template was first seen in, and its id, in time order.
+    new_templates: Vec<(NaiveDateTime, usize)>,
+}
+
+/// Mines templates from the lines and finds their rate spikes and the templates that newly
+/// appear. Returns None if there are no lines.
+fn find_anomalies<I: Iterator<Item = YBLogLine>>(
+        lines: I, options: &AnomalyOptions) -> Option<Anomalies> {
+    let mut miner = TemplateMiner::new();
+    let mut counts: BTreeMap<(usize, NaiveDateTime), u64> = BTreeMap::new();
+    let mut first_bucket: Option<NaiveDateTime> = None;
+    for line in lines {
+        let template_id = miner.add(&line);
+        let bucket_ts = bucket_start(line.timestamp, options.bucket);
+        // Lines arrive in timestamp order, so the first line is in the earliest bucket.
+        if first_bucket.is_none() {
+            first_bucket = Some(bucket_ts);
+        }
+        *counts.entry((template_id, bucket_ts)).or_insert(0) += 1;
+    }
+    let first_bucket = first_bucket?;
+    let bucket_index = |bucket_ts: NaiveDateTime| -> usize {
+        ((bucket_ts - first_bucket).num_milliseconds() / options.bucket.num_milliseconds()) as usize
+    };
+
+    // Counts are ordered by template, then bucket, so each template's buckets come in time order.
+    let mut spikes: Vec<Spike> = Vec::new();
+    let mut new_templates: Vec<(NaiveDateTime, usize)> = Vec::new();
+    let mut template_buckets: Vec<(usize, u64)> = Vec::new();
+    let mut entries = counts.iter().peekable();
+    while let Some((&(template_id, _), _)) = entries.peek() {
+        template_buckets.clear();
+        while let Some((&(next_template_id, bucket_ts), &count)) = entries.peek() {
+            if next_template_id != template_id {
+                break;
+            }
+            template_buckets.push((bucket_index(bucket_ts), count));
+            entries.next();
+        }
+
+        let (first_index, _) = template_buckets[0];
+        if first_index >= options.baseline_buckets {
+            new_templates.push((
+                first_bucket + options.bucket * (first_index as i32), template_id));
+        }
+        for (i, &(index, count)) in template_buckets.iter().enumerate().skip(1) {
+            let window_start = index.saturating_sub(options.baseline_buckets);
+            let window_len = index - window_start;
+            let window_sum: u64 = template_buckets[..i].iter()
+                .filter(|(earlier_index, _)| *earlier_index >= window_start)
+                .map(|(_, earlier_count)| earlier_count)
+                .sum();
+            let baseline = window_sum as f64 / window_len as f64;
+            if count >= MIN_SPIKE_COUNT && count as f64 > options.spike_factor * baseline.max(1.0) {
+                spikes.push(Spike {
+                    bucket_ts: first_bucket + options.bucket * (index as i32),
+                    template_id,
+                    count,
+                    baseline,
+                });
+            }
+        }
+    }
+
+    // Keep the strongest spikes, then show them in time order.
+    spikes.sort_by(|a, b| b.ratio().partial_cmp(&a.ratio()).unwrap());
+    spikes.truncate(options.top);
+    spikes.sort_by(|a, b| a.bucket_ts.cmp(&b.bucket_ts).then(a.template_id.cmp(&b.template_id)));
+    new_templates.sort();
+    Some(Anomalies { miner, spikes, new_templates })
+}
+
+/// Lines that glog copies into the log files of lower severity are counted once, so that a
+/// template's rate does not depend on which severity files were collected.
+pub(crate) fn print_anomalies_report<I: Iterator<Item = YBLogLine>>(
+        lines: I, options: &AnomalyOptions) {
+    let mut lines = without_severity_copies(lines);
+    let Anomalies { miner, spikes, new_templates } = match find_anomalies(&mut lines, options) {
+        Some(anomalies) => anomalies,
+        None => {
+            println!("No lines to look for anomalies in");
+            return;
+        }
+    };
+    lines.print_note();
+    println!("Rate spikes: buckets where a template's count exceeds {}x the average of the \
+              previous {} buckets (showing up to {})",
+             options.spike_factor, options.baseline_buckets, options.top);
+    println!("{:<21}  {:>8}  {:>10}  {:>7}  template", "bucket", "count", "baseline", "ratio");
+    for spike in &spikes {
+        println!("{:<21}  {:>8}  {:>10.2}  {:>6.1}x  {}",
+                 spike.bucket_ts.to_string(),
+                 spike.count,
+                 spike.baseline,
+                 spike.ratio(),
+                 miner.template(spike.template_id));
+    }
+
+    println!();
+    println!("New templates: first seen {} or more buckets after the start of the window \
+              (showing up to {} of {})",
+             options.baseline_buckets, options.top, new_templates.len());
+    println!("{:<21}  {:>8}  {:<20}  {:<20}  template", "first seen", "count", "levels", "nodes");
+    for (first_seen, template_id) in new_templates.iter().take(options.top) {
+        let stats = miner.stats(*template_id);
+        println!("{:<21}  {:>8}  {:<20}  {:<20}  {}",
+                 first_seen.to_string(),
+                 stats.count,
+                 stats.levels_str(),
+                 stats.nodes.iter().cloned().collect::<Vec<_>>().join(","),
+                 miner.template(*template_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    fn options(top: usize) -> AnomalyOptions {
+        AnomalyOptions {
+            bucket: Duration::seconds(10),
+            spike_factor: 2.0,
+            baseline_buckets: 3,
+            top,
+        }
+    }
+
+    /// Lines of templates with the given counts per 10 s bucket, in timestamp order.
+    fn lines_with_counts(templates: &[(&str, &[u64])]) -> Vec<YBLogLine> {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let mut lines = Vec::new();
+        for bucket_index in 0..5 {
+            for (template_index, (message, counts)) in templates.iter().enumerate() {
+                let count = counts.get(bucket_index).cloned().unwrap_or(0);
+                for i in 0..count {
+                    lines.push(glog_line(&n1, &format!(
+                        "I0408 10:00:{:02}.{:06}  1234 tablet.cc:{}] {}",
+                        bucket_index * 10 + template_index, i, 100 + template_index,
+                        message.replace("{}", &i.to_string()))));
+                }
+            }
+        }
+        lines.sort_by_key(|line| line.timestamp);
+        lines
+    }
+
+    fn spikes(anomalies: &Anomalies) -> Vec<(String, String, u64)> {
+        anomalies.spikes.iter()
+            .map(|spike| (spike.bucket_ts.time().to_string(),
+                          anomalies.miner.template(spike.template_id),
+                          spike.count))
+            .collect()
+    }
+
+    #[test]
+    fn spike_threshold() {
+        let lines = lines_with_counts(&[
+            // 5 > 2 x 2: a spike.
+            ("Rolled over log segment to {}", &[2, 2, 2, 5]),
+            // 4 is not more than 2 x 2, and fewer than MIN_SPIKE_COUNT.
+            ("Flushing memtable of tablet {}", &[2, 2, 2, 4]),
+            // Empty buckets count into the baseline, which is at least 1.
+            ("Write to tablet {} rejected", &[1, 0, 0, 6]),
+            // More than 2 x 1, but fewer than MIN_SPIKE_COUNT.
+            ("Leader lease expired after {} ms", &[1, 1, 1, 4]),
+        ]);
+        let anomalies = find_anomalies(lines.into_iter(), &options(10)).unwrap();
+        assert_eq!(spikes(&anomalies), [
+            (String::from("10:00:30"), String::from("Rolled over log segment to <num>"), 5),
+            (String::from("10:00:30"), String::from("Write to tablet <num> rejected"), 6),
+        ]);
+        let baselines: Vec<f64> = anomalies.spikes.iter().map(|spike| spike.baseline).collect();
+        assert_eq!(baselines, [2.0, 1.0 / 3.0]);
+        assert_eq!(anomalies.spikes[1].ratio(), 6.0);
+    }
+
+    #[test]
+    fn strongest_spikes_are_kept() {
+        let lines = lines_with_counts(&[
+            ("Rolled over log segment to {}", &[2, 2, 2, 5]),
+            ("Write to tablet {} rejected", &[1, 0, 0, 6]),
+        ]);
+        let anomalies = find_anomalies(lines.into_iter(), &options(1)).unwrap();
+        assert_eq!(spikes(&anomalies), [
+            (String::from("10:00:30"), String::from("Write to tablet <num> rejected"), 6),
+        ]);
+    }
+
+    #[test]
+    fn templates_first_seen_after_the_baseline_are_new() {
+        let lines = lines_with_counts(&[
+            ("Rolled over log segment to {}", &[1, 1, 1, 1, 1]),
+            ("Write to tablet {} rejected", &[0, 0, 1]),
+            ("Leader lease expired after {} ms", &[0, 0, 0, 1, 1]),
+        ]);
+        let anomalies = find_anomalies(lines.into_iter(), &options(10)).unwrap();
+        assert!(anomalies.spikes.is_empty());
+        let new_templates: Vec<(String, String)> = anomalies.new_templates.iter()
+            .map(|(first_seen, template_id)| (first_seen.time().to_string(),
+                                              anomalies.miner.template(*template_id)))
+            .collect();
+        assert_eq!(new_templates, [
+            (String::from("10:00:30"), String::from("Leader lease expired after <duration>")),
+        ]);
+    }
+
+    #[test]
+    fn no_anomalies_without_lines() {
+        assert!(find_anomalies(Vec::new().into_iter(), &options(10)).is_none());
+    }
+}
@@ -0,0 +1,414 @@
+// ------------------------------------------------------------------------------------------------
+// Load balancer timeline: replica and leader moves decided by the master, followed through the
+// tserver logs of the nodes they move between.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDateTime;
+use regex::Regex;
+
+use yblp::parse_regex;
+
+use crate::tablet_lifecycle::remote_bootstrap_events;
+use crate::YBLogLine;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ActionKind {
+    ReplicaMove,
+    LeaderMove,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum StepKind {
+    AddServer,
+    RemoveServer,
+    RemoteBootstrapStarted,
+    RemoteBootstrapDone,
+    RemoteBootstrapFailed,
+    LeaderElected,
+    TabletDeleted,
+    MasterFailure,
+}
+
+/// A line about a tablet that shows progress on a balancer action.
+struct Step {
+    timestamp: NaiveDateTime,
+    kind: StepKind,
+    /// The node that logged the step.
+    node: String,
+    /// The tserver the step happened on, if the line names it.
+    tserver_uuid: Option<String>,
+    message: String,
+}
+
+/// A move decided by the load balancer.
+struct Action {
+    kind: ActionKind,
+    started_at: NaiveDateTime,
+    from_uuid: String,
+    to_uuid: String,
+}
+
+#[derive(PartialEq, Debug)]
+enum Outcome {
+    Succeeded,
+    Failed,
+    Unfinished,
+}
+
+struct BalancerRegexes {
+    replica_move_re: Regex,
+    leader_move_re: Regex,
+    change_config_re: Regex,
+    /// A failed ChangeConfig or load balancer move, in either order of the failure and what
+    /// failed.
+    master_failure_re: Regex,
+    /// The tablet of a failed move, named as "tablet <id>" or "leader of <id>".
+    master_failure_tablet_re: Regex,
+    raft_peer_re: Regex,
+    /// Tserver steps, with the kind of step each one is.
+    tserver_steps: Vec<(Regex, StepKind)>,
+}
+
+impl BalancerRegexes {
+    fn new() -> BalancerRegexes {
+        BalancerRegexes {
+            // Moving replica 0123...cdef from 3f1a... to 9c2b...
+            replica_move_re: parse_regex(concat!(
+                r"Moving replica (?P<tablet>[0-9a-f]{32}) ",
+                r"from (?P<from>[0-9a-f]+) to (?P<to>[0-9a-f]+)"
+            )),
+            // Moving leader of 0123...cdef from TS 3f1a... to 9c2b...
+            leader_move_re: parse_regex(concat!(
+                r"Moving leader of (?P<tablet>[0-9a-f]{32}) ",
+                r"from TS (?P<from>[0-9a-f]+) to (?P<to>[0-9a-f]+)"
+            )),
+            change_config_re: parse_regex(concat!(
+                r"Sending (?P<op>AddServer|RemoveServer) ChangeConfig for tablet ",
+                r"(?P<tablet>[0-9a-f]{32}) on TS (?P<ts>[0-9a-f]+)"
+            )),
+            // AddServer ChangeConfig RPC for tablet 0123...cdef on peer 9c2b...: ChangeConfig()
+            // failed on leader 3f1a..., or Failed to move replica of tablet 0123...cdef
+            master_failure_re: parse_regex(concat!(
+                r"(?i)(?:ChangeConfig|load balancer|mov(?:e|ing) (?:replica|leader)).*",
+                r"\b(?:failed|error|aborted)\b|",
+                r"\b(?:failed|error|aborted)\b.*",
+                r"(?:ChangeConfig|load balancer|mov(?:e|ing) (?:replica|leader))"
+            )),
+            master_failure_tablet_re: parse_regex(r"\b(?:tablet|of) (?P<tablet>[0-9a-f]{32})\b"),
+            raft_peer_re: parse_regex(r"^T [0-9a-f]{32} P (?P<peer>[0-9a-f]+)\b"),
+            tserver_steps: remote_bootstrap_events(
+                StepKind::RemoteBootstrapStarted,
+                StepKind::RemoteBootstrapFailed,
+                StepKind::RemoteBootstrapDone,
+            ).into_iter().chain(vec![
+                (parse_regex(r"(?i)leader election won|becoming leader|became leader"),
+                 StepKind::LeaderElected),
+                (parse_regex(r"(?i)deleting tablet data|tablet deleted|deleted tablet"),
+                 StepKind::TabletDeleted),
+            ]).collect(),
+        }
+    }
+}
+
+/// Looks up the node that runs a tserver, by its UUID.
+fn node_of(tserver_nodes: &HashMap<String, String>, uuid: &str) -> String {
+    match tserver_nodes.get(uuid) {
+        Some(node) => format!("{} ({})", uuid, node),
+        None => String::from(uuid),
+    }
+}
+
+/// The step that ends an action on the tablet, and whether the action succeeded.
+fn outcome(action: &Action, steps: &[&Step]) -> (Outcome, Option<NaiveDateTime>) {
+    for step in steps {
+        let on_from = step.tserver_uuid.as_deref() == Some(&action.from_uuid);
+        let on_to = step.tserver_uuid.as_deref() == Some(&action.to_uuid);
+        match (action.kind, step.kind) {
+            (_, StepKind::MasterFailure) | (_, StepKind::RemoteBootstrapFailed) =>
+                return (Outcome::Failed, Some(step.timestamp)),
+            (ActionKind::LeaderMove, StepKind::LeaderElected) if on_to =>
+                return (Outcome::Succeeded, Some(step.timestamp)),
+            (ActionKind::LeaderMove, StepKind::LeaderElected) =>
+                return (Outcome::Failed, Some(step.timestamp)),
+            (ActionKind::ReplicaMove, StepKind::TabletDeleted) if on_from =>
+                return (Outcome::Succeeded, Some(step.timestamp)),
+            _ => {}
+        }
+    }
+    (Outcome::Unfinished, None)
+}
+
+/// Collects the moves decided by the master and the steps logged for them, by tablet.
+struct BalancerCollector {
+    regexes: BalancerRegexes,
+    actions: BTreeMap<String, Vec<Action>>,
+    steps: HashMap<String, Vec<Step>>,
+    /// Tserver UUID to the node it runs on, from the "P <uuid>" prefix of its tablet log lines.
+    tserver_nodes: HashMap<String, String>,
+}
+
+impl BalancerCollector {
+    fn new() -> BalancerCollector {
+        BalancerCollector {
+            regexes: BalancerRegexes::new(),
+            actions: BTreeMap::new(),
+            steps: HashMap::new(),
+            tserver_nodes: HashMap::new(),
+        }
+    }
+
+    fn add_line(&mut self, line: YBLogLine) {
+        let regexes = &self.regexes;
+        let node = line.source.node.clone().unwrap_or_default();
+        let message = &line.message;
+        let is_master = line.source.process.program.contains("master");
+        if is_master {
+            let moves = [(&regexes.replica_move_re, ActionKind::ReplicaMove),
+                         (&regexes.leader_move_re, ActionKind::LeaderMove)];
+            for (re, kind) in moves.iter() {
+                if let Some(captures) = re.captures(message) {
+                    self.actions.entry(String::from(&captures["tablet"])).or_default()
+                        .push(Action {
+                            kind: *kind,
+                            started_at: line.timestamp,
+                            from_uuid: String::from(&captures["from"]),
+                            to_uuid: String::from(&captures["to"]),
+                        });
+                }
+            }
+            let step = if let Some(captures) = regexes.change_config_re.captures(message) {
+                let kind = if &captures["op"] == "AddServer" {
+                    StepKind::AddServer
+                } else {
+                    StepKind::RemoveServer
+                };
+                Some((String::from(&captures["tablet"]), kind, Some(String::from(&captures["ts"]))))
+            } else if regexes.master_failure_re.is_match(message) {
+                regexes.master_failure_tablet_re.captures(message).map(|captures| (
+                    String::from(&captures["tablet"]), StepKind::MasterFailure, None))
+            } else {
+                None
+            };
+            if let Some((tablet, kind, tserver_uuid)) = step {
+                self.steps.entry(tablet).or_default().push(Step {
+                    timestamp: line.timestamp,
+                    kind,
+                    node,
+                    tserver_uuid,
+                    message: message.clone(),
+                });
+            }
+            return;
+        }
+
+        let tablet_id = match line.tablet_id {
+            Some(tablet_id) => tablet_id.to_simple().to_string(),
+            None => return,
+        };
+        let peer = regexes.raft_peer_re.captures(message)
+            .map(|captures| String::from(&captures["peer"]));
+        if let Some(peer) = &peer {
+            if !self.tserver_nodes.contains_key(peer) {
+                self.tserver_nodes.insert(peer.clone(), node.clone());
+            }
+        }
+        if let Some((_, kind)) = regexes.tserver_steps.iter().find(|(re, _)| re.is_match(message)) {
+            self.steps.entry(tablet_id).or_default().push(Step {
+                timestamp: line.timestamp,
+                kind: *kind,
+                node,
+                tserver_uuid: peer,
+                message: line.message,
+            });
+        }
+    }
+}
+
+/// The steps of the `index`th action on a tablet. Steps belong to the latest action started
+/// before them, and are left out if they happened on a tserver the action does not move between.
+fn action_steps<'a>(tablet_actions: &[Action], index: usize, tablet_steps: &'a [Step])
+        -> Vec<&'a Step> {
+    let action = &tablet_actions[index];
+    let next_started_at = tablet_actions.get(index + 1).map(|next| next.started_at);
+    tablet_steps.iter()
+        .filter(|step| step.timestamp >= action.started_at)
+        .filter(|step| match next_started_at {
+            Some(next_started_at) => step.timestamp < next_started_at,
+            None => true,
+        })
+        .filter(|step| match &step.tserver_uuid {
+            Some(uuid) => uuid == &action.from_uuid || uuid == &action.to_uuid,
+            None => true,
+        })
+        .collect()
+}
+
+/// Prints, per tablet, each replica and leader move decided by the master load balancer, the
+/// steps logged for it by the master and the tservers it moved from and to, and its outcome.
+pub(crate) fn print_balancer_report<I: Iterator<Item = YBLogLine>>(lines: I) {
+    let mut collector = BalancerCollector::new();
+    for line in lines {
+        collector.add_line(line);
+    }
+    let BalancerCollector { actions, steps, tserver_nodes, .. } = collector;
+
+    let num_actions: usize = actions.values().map(Vec::len).sum();
+    println!("Found {} load balancer moves of {} tablets", num_actions, actions.len());
+    let no_steps: Vec<Step> = Vec::new();
+    for (tablet, tablet_actions) in &actions {
+        println!();
+        println!("Tablet {}", tablet);
+        let tablet_steps = steps.get(tablet).unwrap_or(&no_steps);
+        for (i, action) in tablet_actions.iter().enumerate() {
+            let action_steps = action_steps(tablet_actions, i, tablet_steps);
+            println!("  {:<26}  {:?} from {} to {}",
+                     action.started_at.to_string(), action.kind,
+                     node_of(&tserver_nodes, &action.from_uuid),
+                     node_of(&tserver_nodes, &action.to_uuid));
+            let (outcome, finished_at) = outcome(action, &action_steps);
+            for step in &action_steps {
+                println!("  {:<26}    {:<22}  {:<24}  {}",
+                         step.timestamp.to_string(), format!("{:?}", step.kind), step.node,
+                         step.message.lines().next().unwrap_or(""));
+                if Some(step.timestamp) == finished_at {
+                    break;
+                }
+            }
+            match finished_at {
+                Some(finished_at) => println!(
+                    "  {:<26}  {:?} after {:.3} s", finished_at.to_string(), outcome,
+                    (finished_at - action.started_at).num_milliseconds() as f64 / 1000.0),
+                None => println!("  {:<26}  {:?}", "", outcome),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    const TABLET_A: &str = "0123456789abcdef0123456789abcdef";
+    const TABLET_B: &str = "fedcba9876543210fedcba9876543210";
+    const TS_1: &str = "3f1a2b3c4d5e4f60a1b2c3d4e5f60718";
+    const TS_2: &str = "9c2b3a4d5e6f47a8b9c0d1e2f3a4b5c6";
+    const TS_3: &str = "5d6e7f8091a24b3c8d9e0f1a2b3c4d5e";
+
+    fn collect(lines: &[(&str, String)]) -> BalancerCollector {
+        let master = source(0, "m1", "yb-master.m1.yugabyte.log.INFO.20210408-100000.2000");
+        let n1 = source(1, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let n2 = source(2, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        let mut collector = BalancerCollector::new();
+        for (node, text) in lines {
+            let source = match *node {
+                "m1" => &master,
+                "n1" => &n1,
+                _ => &n2,
+            };
+            collector.add_line(glog_line(source, text));
+        }
+        collector
+    }
+
+    /// The kinds of the steps of each action on a tablet and how the action ended.
+    fn outcomes(collector: &BalancerCollector, tablet: &str)
+            -> Vec<(ActionKind, Vec<StepKind>, Outcome, Option<String>)> {
+        let tablet_actions = &collector.actions[tablet];
+        (0..tablet_actions.len())
+            .map(|i| {
+                let steps = action_steps(tablet_actions, i, &collector.steps[tablet]);
+                let (outcome, finished_at) = outcome(&tablet_actions[i], &steps);
+                (tablet_actions[i].kind,
+                 steps.iter().map(|step| step.kind).collect(),
+                 outcome,
+                 finished_at.map(|finished_at| finished_at.time().to_string()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn moves_are_followed_through_master_and_tserver_logs() {
+        let collector = collect(&[
+            ("m1", format!("I0408 10:00:00.000000  2000 cluster_balance.cc:1003] \
+                            Moving replica {} from {} to {}", TABLET_A, TS_1, TS_2)),
+            ("m1", format!("I0408 10:00:00.100000  2001 async_rpc_tasks.cc:900] \
+                            Sending AddServer ChangeConfig for tablet {} on TS {}",
+                           TABLET_A, TS_1)),
+            ("n2", format!("I0408 10:00:01.000000  5690 remote_bootstrap_client.cc:250] \
+                            T {} P {}: Beginning remote bootstrap session from remote peer \
+                            at address 10.0.0.1:9100", TABLET_A, TS_2)),
+            // On a tserver the move is not between.
+            ("n2", format!("I0408 10:00:03.000000  5691 raft_consensus.cc:900] \
+                            T {} P {}: Leader election won for term 4", TABLET_A, TS_3)),
+            ("n2", format!("I0408 10:00:05.000000  5690 remote_bootstrap_client.cc:600] \
+                            T {} P {}: Remote bootstrap complete. Replacing tablet superblock.",
+                           TABLET_A, TS_2)),
+            ("n1", format!("I0408 10:00:07.000000  1240 ts_tablet_manager.cc:1500] \
+                            T {} P {}: Tablet deleted. Last logged OpId: 1.23",
+                           TABLET_A, TS_1)),
+            ("m1", format!("I0408 10:00:10.000000  2000 cluster_balance.cc:1020] \
+                            Moving leader of {} from TS {} to {}", TABLET_B, TS_1, TS_2)),
+            ("n2", format!("I0408 10:00:11.000000  5691 raft_consensus.cc:900] \
+                            T {} P {}: Leader election won for term 7", TABLET_B, TS_2)),
+            ("m1", format!("I0408 10:01:00.000000  2000 cluster_balance.cc:1003] \
+                            Moving replica {} from {} to {}", TABLET_A, TS_2, TS_3)),
+            ("m1", format!("W0408 10:01:02.000000  2000 cluster_balance.cc:1100] \
+                            Failed to move replica of tablet {}: Illegal state: \
+                            Tablet not running", TABLET_A)),
+        ]);
+
+        assert_eq!(outcomes(&collector, TABLET_A), [
+            (ActionKind::ReplicaMove,
+             vec![StepKind::AddServer, StepKind::RemoteBootstrapStarted,
+                  StepKind::RemoteBootstrapDone, StepKind::TabletDeleted],
+             Outcome::Succeeded, Some(String::from("10:00:07"))),
+            (ActionKind::ReplicaMove, vec![StepKind::MasterFailure],
+             Outcome::Failed, Some(String::from("10:01:02"))),
+        ]);
+        assert_eq!(outcomes(&collector, TABLET_B), [
+            (ActionKind::LeaderMove, vec![StepKind::LeaderElected],
+             Outcome::Succeeded, Some(String::from("10:00:11"))),
+        ]);
+        assert_eq!(node_of(&collector.tserver_nodes, TS_1), format!("{} (n1)", TS_1));
+        assert_eq!(node_of(&collector.tserver_nodes, "abc"), "abc");
+    }
+
+    #[test]
+    fn outcomes_of_moves() {
+        let at = |seconds: u32| -> NaiveDateTime {
+            format!("2021-04-08T10:00:{:02}", seconds).parse().unwrap()
+        };
+        let action = |kind| Action {
+            kind,
+            started_at: at(0),
+            from_uuid: String::from(TS_1),
+            to_uuid: String::from(TS_2),
+        };
+        let step = |seconds, kind, tserver_uuid: &str| Step {
+            timestamp: at(seconds),
+            kind,
+            node: String::from("n1"),
+            tserver_uuid: Some(String::from(tserver_uuid)),
+            message: String::new(),
+        };
+        let leader_move = action(ActionKind::LeaderMove);
+        let replica_move = action(ActionKind::ReplicaMove);
+
+        assert_eq!(outcome(&leader_move, &[]), (Outcome::Unfinished, None));
+        // Another tserver won the election.
+        let elected_elsewhere = step(1, StepKind::LeaderElected, TS_3);
+        assert_eq!(outcome(&leader_move, &[&elected_elsewhere]), (Outcome::Failed, Some(at(1))));
+        // The replica is only deleted from the tserver it is moved to.
+        let deleted_on_to = step(2, StepKind::TabletDeleted, TS_2);
+        assert_eq!(outcome(&replica_move, &[&deleted_on_to]), (Outcome::Unfinished, None));
+        let bootstrap_failed = step(3, StepKind::RemoteBootstrapFailed, TS_2);
+        let deleted_on_from = step(4, StepKind::TabletDeleted, TS_1);
+        assert_eq!(outcome(&replica_move, &[&deleted_on_to, &bootstrap_failed, &deleted_on_from]),
+                   (Outcome::Failed, Some(at(3))));
+        assert_eq!(outcome(&replica_move, &[&deleted_on_from, &bootstrap_failed]),
+                   (Outcome::Succeeded, Some(at(4))));
+    }
+}
@@ -0,0 +1,324 @@
+// ------------------------------------------------------------------------------------------------
+// Clock skew estimation: offsets between node clocks, bounded by messages that one node logs
+// when sending and another when receiving, which cannot be received before they were sent.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use regex::{Captures, Regex};
+
+use yblp::parse_regex;
+
+use crate::external_sort::MergedLines;
+
+/// How far ahead of the reference node's clock each node's clock is, by node.
+pub(crate) type ClockOffsets = BTreeMap<String, Duration>;
+
+/// Named groups that identify a message across its sender and receiver, in key order.
+const KEY_GROUPS: &[&str] =
+    &["kind", "tablet", "term", "candidate", "voter", "peer", "method", "server", "call_id"];
+
+/// An event logged by the sending node and by the receiving node with the same key.
+struct PairedEventPattern {
+    send_re: Regex,
+    receive_re: Regex,
+    /// Whether the key may recur for unrelated messages, e.g. call ids, which each connection
+    /// numbers from zero. Such keys are only used if they occur exactly once on each side.
+    key_may_recur: bool,
+}
+
+impl PairedEventPattern {
+    fn new(send_re: &str, receive_re: &str) -> PairedEventPattern {
+        PairedEventPattern {
+            send_re: parse_regex(send_re),
+            receive_re: parse_regex(receive_re),
+            key_may_recur: false,
+        }
+    }
+
+    fn with_recurring_key(send_re: &str, receive_re: &str) -> PairedEventPattern {
+        PairedEventPattern { key_may_recur: true, ..PairedEventPattern::new(send_re, receive_re) }
+    }
+}
+
+/// Start of a line logged by a Raft peer, capturing the peer's UUID as `peer_group`.
+macro_rules! raft_prefix {
+    ($peer_group:literal) => {
+        concat!(r"^T (?P<tablet>[0-9a-f]{32}) P (?P<", $peer_group, r">[0-9a-f]+)[^:]*: ")
+    };
+}
+
+/// A voter's log line on a vote request, which is both receiving the request and sending the
+/// response.
+macro_rules! vote_request_handled_re {
+    ($peer_group:literal) => {
+        concat!(
+            raft_prefix!($peer_group),
+            r"Leader (?P<kind>pre-)?election vote request: ",
+            r"(?:Granting yes vote for|Denying vote to) candidate (?P<candidate>[0-9a-f]+) ",
+            r"(?:in|for earlier) term (?P<term>\d+)"
+        )
+    };
+}
+
+fn paired_event_patterns() -> Vec<PairedEventPattern> {
+    vec![
+        // Raft vote request, from the candidate to each voter.
+        PairedEventPattern::new(
+            concat!(
+                raft_prefix!("candidate"),
+                r"Term (?P<term>\d+) (?P<kind>pre-)?election: Requested (?:pre-)?vote from peers"
+            ),
+            vote_request_handled_re!("receiver")),
+        // Raft vote response, from each voter back to the candidate.
+        PairedEventPattern::new(
+            vote_request_handled_re!("voter"),
+            concat!(
+                raft_prefix!("candidate"),
+                r"Term (?P<term>\d+) (?P<kind>pre-)?election: ",
+                r"Vote (?:granted|denied) by peer (?P<voter>[0-9a-f]+)"
+            )),
+        // RPC response, from the server, which logs the inbound call when it responds, e.g.
+        //   Call yb.consensus.ConsensusService.UpdateConsensus 10.0.0.1:52814 => 10.0.0.2:9100
+        //   (request call id 12345) took 1520ms
+        // to the client, which logs the outbound call once the response has arrived, e.g.
+        //   RPC call yb.consensus.ConsensusService.UpdateConsensus -> { remote: 10.0.0.2:9100
+        //   idx: 1 protocol: 0x... -> tcp } , call_id=12345 , state=FINISHED_SUCCESS.
+        PairedEventPattern::with_recurring_key(
+            concat!("^", inbound_call_re!("method")),
+            concat!(
+                r"^RPC call (?P<method>[\w.]+) -> \{ ?remote: (?P<server>[^\s,}]+)[^}]*\} ?, ",
+                r"call[ _]id[ =:]*(?P<call_id>\d+)\b.*\bstate=FINISHED_SUCCESS"
+            )),
+        // Remote bootstrap session, from the new replica, which logs the start before sending the
+        // request, to the tserver it copies the tablet from.
+        PairedEventPattern::with_recurring_key(
+            concat!(
+                raft_prefix!("peer"),
+                r"(?:Remote bootstrap client: )?Beginning remote bootstrap session"
+            ),
+            concat!(
+                r"Beginning new remote bootstrap session on tablet (?P<tablet>[0-9a-f]{32}) ",
+                r"from peer (?P<peer>[0-9a-f]+)"
+            )),
+    ]
+}
+
+fn event_key(pattern_index: usize, captures: &Captures) -> (usize, String) {
+    let mut key = String::new();
+    for group in KEY_GROUPS {
+        key.push('/');
+        key.push_str(captures.name(group).map_or("", |m| m.as_str()));
+    }
+    (pattern_index, key)
+}
+
+type EventsByKey = HashMap<(usize, String), Vec<(String, NaiveDateTime)>>;
+
+/// Bound on how far ahead the receiver's clock is of the sender's, from all messages between
+/// one ordered pair of nodes.
+struct PairBound {
+    /// The smallest receive time minus send time seen.
+    upper_bound: Duration,
+    num_samples: usize,
+}
+
+pub(crate) struct SkewEstimator {
+    /// Keyed by (sender, receiver).
+    bounds: BTreeMap<(String, String), PairBound>,
+    nodes: BTreeSet<String>,
+}
+
+impl SkewEstimator {
+    pub(crate) fn estimate(lines: MergedLines) -> SkewEstimator {
+        let patterns = paired_event_patterns();
+        let mut sends: EventsByKey = HashMap::new();
+        let mut receives: EventsByKey = HashMap::new();
+        let mut nodes: BTreeSet<String> = BTreeSet::new();
+        for line in lines {
+            let node = match &line.source.node {
+                Some(node) => node,
+                None => continue,
+            };
+            if !nodes.contains(node) {
+                nodes.insert(node.clone());
+            }
+            // glog writes a WARNING line to both the INFO and WARNING files, so the same event
+            // can show up twice on a node; such copies are only counted once.
+            let add_event = |events: &mut EventsByKey, key: (usize, String)| {
+                let key_events = events.entry(key).or_default();
+                let event = (node.clone(), line.timestamp);
+                if !key_events.contains(&event) {
+                    key_events.push(event);
+                }
+            };
+            for (pattern_index, pattern) in patterns.iter().enumerate() {
+                if let Some(captures) = pattern.send_re.captures(&line.message) {
+                    add_event(&mut sends, event_key(pattern_index, &captures));
+                }
+                if let Some(captures) = pattern.receive_re.captures(&line.message) {
+                    add_event(&mut receives, event_key(pattern_index, &captures));
+                }
+            }
+        }
+
+        let mut bounds: BTreeMap<(String, String), PairBound> = BTreeMap::new();
+        for (key, key_sends) in &sends {
+            let key_receives = match receives.get(key) {
+                Some(key_receives) => key_receives,
+                None => continue,
+            };
+            if patterns[key.0].key_may_recur && (key_sends.len() > 1 || key_receives.len() > 1) {
+                continue;
+            }
+            for (sender, sent_at) in key_sends {
+                for (receiver, received_at) in key_receives {
+                    if sender == receiver {
+                        continue;
+                    }
+                    let delay = *received_at - *sent_at;
+                    let bound = bounds.entry((sender.clone(), receiver.clone()))
+                        .or_insert(PairBound { upper_bound: delay, num_samples: 0 });
+                    bound.upper_bound = bound.upper_bound.min(delay);
+                    bound.num_samples += 1;
+                }
+            }
+        }
+        SkewEstimator { bounds, nodes }
+    }
+
+    /// Estimated offset of `to`'s clock relative to `from`'s and the number of messages it is
+    /// based on. With bounds in both directions this is the middle of the range; with a bound in
+    /// one direction only, the clocks are assumed to agree unless the bound rules that out.
+    fn pair_offset(&self, from: &str, to: &str) -> Option<(Duration, usize)> {
+        let upper = self.bounds.get(&(String::from(from), String::from(to)));
+        let lower = self.bounds.get(&(String::from(to), String::from(from)));
+        match (upper, lower) {
+            (Some(upper), Some(lower)) => Some((
+                (upper.upper_bound - lower.upper_bound) / 2,
+                upper.num_samples + lower.num_samples)),
+            (Some(upper), None) =>
+                Some((upper.upper_bound.min(Duration::zero()), upper.num_samples)),
+            (None, Some(lower)) =>
+                Some(((-lower.upper_bound).max(Duration::zero()), lower.num_samples)),
+            (None, None) => None,
+        }
+    }
+
+    /// The reference node: the one involved in the most paired messages, or the first node.
+    fn reference_node(&self) -> Option<String> {
+        let mut num_samples: BTreeMap<&str, usize> = BTreeMap::new();
+        for ((sender, receiver), bound) in &self.bounds {
+            *num_samples.entry(sender).or_insert(0) += bound.num_samples;
+            *num_samples.entry(receiver).or_insert(0) += bound.num_samples;
+        }
+        num_samples.iter()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(node, _)| String::from(*node))
+            .or_else(|| self.nodes.iter().next().cloned())
+    }
+
+    /// Offsets of all nodes connected to the reference node by paired messages, following the
+    /// best-supported pair offsets outwards from the reference node. Nodes that are not connected
+    /// are left out and thus not corrected.
+    pub(crate) fn offsets(&self) -> ClockOffsets {
+        let mut offsets = ClockOffsets::new();
+        let reference = match self.reference_node() {
+            Some(reference) => reference,
+            None => return offsets,
+        };
+        offsets.insert(reference, Duration::zero());
+        loop {
+            let mut best: Option<(usize, String, Duration)> = None;
+            for (known, known_offset) in &offsets {
+                for node in &self.nodes {
+                    if offsets.contains_key(node) {
+                        continue;
+                    }
+                    if let Some((offset, num_samples)) = self.pair_offset(known, node) {
+                        let is_better = match &best {
+                            Some((best_samples, _, _)) => num_samples > *best_samples,
+                            None => true,
+                        };
+                        if is_better {
+                            best = Some((num_samples, node.clone(), *known_offset + offset));
+                        }
+                    }
+                }
+            }
+            match best {
+                Some((_, node, offset)) => { offsets.insert(node, offset); }
+                None => return offsets,
+            }
+        }
+    }
+
+    pub(crate) fn print_offsets(&self) {
+        let offsets = self.offsets();
+        let reference = self.reference_node();
+        println!("Estimated clock offsets from {} nodes:", self.nodes.len());
+        for node in &self.nodes {
+            match offsets.get(node) {
+                _ if reference.as_ref() == Some(node) => println!("  {:<24}  reference", node),
+                Some(offset) => println!("  {:<24}  {:+.3} s", node,
+                                         offset.num_microseconds().unwrap() as f64 / 1e6),
+                None => println!("  {:<24}  unknown, no messages paired with other nodes", node),
+            }
+        }
+        for node in self.nodes.iter().filter(|node| !offsets.contains_key(*node)) {
+            println!("Warning: no samples to estimate the clock offset of {} from, so its \
+                      timestamps are not corrected", node);
+        }
+    }
+
+    pub(crate) fn print(&self) {
+        println!("Paired messages between nodes: the receiver's clock is at most 'bound' ahead \
+                  of the sender's");
+        println!("{:<24}  {:<24}  {:>8}  {:>12}", "sender", "receiver", "messages", "bound (s)");
+        for ((sender, receiver), bound) in &self.bounds {
+            println!("{:<24}  {:<24}  {:>8}  {:>12.3}",
+                     sender, receiver, bound.num_samples,
+                     bound.upper_bound.num_microseconds().unwrap() as f64 / 1e6);
+        }
+        println!();
+        self.print_offsets();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_lines::{glog_line, merged_lines, source};
+
+    #[test]
+    fn rpc_lines_copied_to_the_warning_file_are_paired_once() {
+        let sources = vec![
+            source(0, "node-1", "yb-tserver.host.yugabyte.log.INFO.20210408-100000.1001"),
+            source(1, "node-1", "yb-tserver.host.yugabyte.log.WARNING.20210408-100000.1001"),
+            source(2, "node-2", "yb-tserver.host.yugabyte.log.INFO.20210408-100000.2002"),
+            source(3, "node-2", "yb-tserver.host.yugabyte.log.WARNING.20210408-100000.2002"),
+        ];
+        let server_line = "W0408 10:00:01.520000  2345 inbound_call.cc:118] \
+            Call yb.consensus.ConsensusService.UpdateConsensus 10.0.0.2:52814 => 10.0.0.1:9100 \
+            (request call id 12345) took 1520ms (client timeout 3000ms).";
+        let client_line = "W0408 10:00:01.530000  3456 outbound_call.cc:512] \
+            RPC call yb.consensus.ConsensusService.UpdateConsensus -> \
+            { remote: 10.0.0.1:9100 idx: 1 protocol: 0x00007f2b5c8e1a40 -> tcp } , \
+            call_id=12345 , state=FINISHED_SUCCESS.";
+        let lines = sources.iter()
+            .map(|source| glog_line(source, if source.node.as_deref() == Some("node-1") {
+                server_line
+            } else {
+                client_line
+            }))
+            .collect();
+
+        let estimate = SkewEstimator::estimate(merged_lines(&sources, lines));
+        let bound = &estimate.bounds[&(String::from("node-1"), String::from("node-2"))];
+        assert_eq!(bound.num_samples, 1);
+        assert_eq!(bound.upper_bound, Duration::milliseconds(10));
+        assert_eq!(estimate.bounds.len(), 1);
+    }
+}
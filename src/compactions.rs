@@ -0,0 +1,422 @@
+// ------------------------------------------------------------------------------------------------
+// RocksDB flush and compaction activity: start and finish lines paired by tablet and job id into
+// events with sizes and durations.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use regex::{Match, Regex};
+use uuid::Uuid;
+
+use yblp::{bucket_start, parse_regex};
+
+use crate::YBLogLine;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+enum ActivityKind {
+    Flush,
+    Compaction,
+}
+
+/// A flush or compaction of one RocksDB instance of a tablet.
+struct Activity {
+    kind: ActivityKind,
+    node: String,
+    tablet_id: Option<Uuid>,
+    /// "R" for the regular DB, "I" for the intents DB, or empty if not logged.
+    db: String,
+    job: u64,
+    started_at: NaiveDateTime,
+    finished_at: NaiveDateTime,
+    bytes_written: u64,
+}
+
+impl Activity {
+    fn duration(&self) -> Duration {
+        self.finished_at - self.started_at
+    }
+
+    fn tablet_str(&self) -> String {
+        match self.tablet_id {
+            Some(tablet_id) => format!("{} {}", tablet_id.to_simple(), self.db),
+            None => format!("- {}", self.db),
+        }
+    }
+}
+
+/// Identifies an activity between its start and finish lines. RocksDB job ids are only unique
+/// within one DB.
+type ActivityKey = (ActivityKind, String, Option<Uuid>, String, u64);
+
+/// A start line seen without its finish line yet.
+struct PendingActivity {
+    started_at: NaiveDateTime,
+}
+
+struct ActivityRegexes {
+    db_re: Regex,
+    flush_start_re: Regex,
+    flush_finish_re: Regex,
+    event_log_re: Regex,
+}
+
+impl ActivityRegexes {
+    fn new() -> ActivityRegexes {
+        ActivityRegexes {
+            db_re: parse_regex(r"^T [0-9a-f]{32} P [0-9a-f]+ \[([RI])\]: "),
+            // [default] [JOB 3] Flushing memtable with next log file: 5
+            flush_start_re: parse_regex(r"\[JOB (\d+)\] Flushing memtable"),
+            // [default] [JOB 3] Level-0 flush table #12: 1234567 bytes OK
+            flush_finish_re: parse_regex(
+                r"\[JOB (\d+)\] Level-0 flush table #\d+: (\d+) bytes OK"),
+            // EVENT_LOG_v1 {"time_micros": 1617876000123456, "job": 5,
+            //               "event": "compaction_started", ...}
+            event_log_re: parse_regex(
+                r#"EVENT_LOG_v1 \{.*"event": "(compaction_started|compaction_finished)""#),
+        }
+    }
+}
+
+/// Returns the value of a numeric field of the JSON object in an EVENT_LOG_v1 line.
+fn json_number(message: &str, field: &str) -> Option<u64> {
+    let field_start = message.find(&format!("\"{}\": ", field))? + field.len() + 4;
+    let digits: String = message[field_start..].chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// Pairs start and finish lines into activities.
+struct ActivityCollector {
+    regexes: ActivityRegexes,
+    pending: HashMap<ActivityKey, PendingActivity>,
+    finished: Vec<Activity>,
+}
+
+impl ActivityCollector {
+    fn new() -> ActivityCollector {
+        ActivityCollector {
+            regexes: ActivityRegexes::new(),
+            pending: HashMap::new(),
+            finished: Vec::new(),
+        }
+    }
+
+    fn add_line(&mut self, line: &YBLogLine) {
+        let message = &line.message;
+        // Numbers too large for a u64, e.g. in a corrupted line, make the line be skipped.
+        let number = |capture: Option<Match>| -> Option<u64> { capture?.as_str().parse().ok() };
+        let (kind, is_start, job, bytes_written, duration) =
+            if let Some(captures) = self.regexes.flush_start_re.captures(message) {
+                let job = match number(captures.get(1)) {
+                    Some(job) => job,
+                    None => return,
+                };
+                (ActivityKind::Flush, true, job, 0, None)
+            } else if let Some(captures) = self.regexes.flush_finish_re.captures(message) {
+                let job = number(captures.get(1));
+                let (job, bytes_written) = match (job, number(captures.get(2))) {
+                    (Some(job), Some(bytes_written)) => (job, bytes_written),
+                    _ => return,
+                };
+                (ActivityKind::Flush, false, job, bytes_written, None)
+            } else if let Some(captures) = self.regexes.event_log_re.captures(message) {
+                let job = match json_number(message, "job") {
+                    Some(job) => job,
+                    None => return,
+                };
+                let is_start = captures.get(1).unwrap().as_str() == "compaction_started";
+                (ActivityKind::Compaction, is_start, job,
+                 json_number(message, "total_output_size").unwrap_or(0),
+                 json_number(message, "compaction_time_micros")
+                     .map(|micros| Duration::microseconds(micros as i64)))
+            } else {
+                return;
+            };
+
+        let db = match self.regexes.db_re.captures(message) {
+            Some(captures) => String::from(captures.get(1).unwrap().as_str()),
+            None => String::new(),
+        };
+        let node = line.source.node.clone().unwrap_or_default();
+        let key: ActivityKey = (kind, node, line.tablet_id, db, job);
+        if is_start {
+            self.pending.entry(key).or_insert(PendingActivity { started_at: line.timestamp });
+            return;
+        }
+        // A compaction logs its own duration, so its start line is not needed.
+        let started_at = match (self.pending.remove(&key), duration) {
+            (_, Some(duration)) => line.timestamp - duration,
+            (Some(pending), None) => pending.started_at,
+            (None, None) => line.timestamp,
+        };
+        let (kind, node, tablet_id, db, job) = key;
+        self.finished.push(Activity {
+            kind,
+            node,
+            tablet_id,
+            db,
+            job,
+            started_at,
+            finished_at: line.timestamp,
+            bytes_written,
+        });
+    }
+}
+
+#[derive(Default)]
+struct TabletSummary {
+    num_flushes: u64,
+    flushed_bytes: u64,
+    num_compactions: u64,
+    compacted_bytes: u64,
+    compaction_time: Option<Duration>,
+}
+
+/// For each time bucket and node, the highest number of compactions running at the same time.
+fn max_concurrent_compactions(
+        activities: &[Activity], bucket: Duration) -> BTreeMap<(NaiveDateTime, String), usize> {
+    // Starts sort before finishes at equal times, so back-to-back compactions count as
+    // overlapping, erring on the side of showing more concurrency.
+    let mut events: Vec<(&str, NaiveDateTime, bool)> = Vec::new();
+    for activity in activities.iter().filter(|a| a.kind == ActivityKind::Compaction) {
+        events.push((&activity.node, activity.started_at, false));
+        events.push((&activity.node, activity.finished_at, true));
+    }
+    events.sort();
+
+    let mut max_running: BTreeMap<(NaiveDateTime, String), usize> = BTreeMap::new();
+    let mut running: usize = 0;
+    let mut previous: Option<(&str, NaiveDateTime)> = None;
+    for (node, timestamp, is_finish) in events {
+        match previous {
+            Some((previous_node, previous_timestamp)) if previous_node == node => {
+                // Compactions that keep running through whole buckets count in those too.
+                let mut bucket_ts = bucket_start(previous_timestamp, bucket);
+                while bucket_ts <= timestamp && running > 0 {
+                    let max = max_running.entry((bucket_ts, String::from(node))).or_insert(0);
+                    *max = (*max).max(running);
+                    bucket_ts += bucket;
+                }
+            }
+            _ => running = 0,
+        }
+        if is_finish {
+            running = running.saturating_sub(1);
+        } else {
+            running += 1;
+            let max = max_running.entry((bucket_start(timestamp, bucket), String::from(node)))
+                .or_insert(0);
+            *max = (*max).max(running);
+        }
+        previous = Some((node, timestamp));
+    }
+    max_running
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Prints flush and compaction totals per tablet, the number of concurrent compactions per node
+/// and time bucket, and the `top` slowest flushes and compactions.
+pub(crate) fn print_compactions_report<I: Iterator<Item = YBLogLine>>(
+        lines: I, top: usize, bucket: Duration) {
+    let mut collector = ActivityCollector::new();
+    for line in lines {
+        collector.add_line(&line);
+    }
+    let activities = collector.finished;
+
+    let mut summaries: BTreeMap<(String, String), TabletSummary> = BTreeMap::new();
+    for activity in &activities {
+        let summary = summaries.entry((activity.node.clone(), activity.tablet_str())).or_default();
+        match activity.kind {
+            ActivityKind::Flush => {
+                summary.num_flushes += 1;
+                summary.flushed_bytes += activity.bytes_written;
+            }
+            ActivityKind::Compaction => {
+                summary.num_compactions += 1;
+                summary.compacted_bytes += activity.bytes_written;
+                summary.compaction_time = Some(
+                    summary.compaction_time.unwrap_or_else(Duration::zero) + activity.duration());
+            }
+        }
+    }
+    let mut summary_rows: Vec<(&(String, String), &TabletSummary)> = summaries.iter().collect();
+    summary_rows.sort_by(|a, b| (b.1.flushed_bytes + b.1.compacted_bytes)
+        .cmp(&(a.1.flushed_bytes + a.1.compacted_bytes))
+        .then_with(|| a.0.cmp(b.0)));
+
+    println!("Found {} flushes and {} compactions of {} tablets, {} not finished in the logs",
+             activities.iter().filter(|a| a.kind == ActivityKind::Flush).count(),
+             activities.iter().filter(|a| a.kind == ActivityKind::Compaction).count(),
+             summaries.len(),
+             collector.pending.len());
+    println!();
+    println!("Tablets by bytes written (showing up to {} of {})", top, summary_rows.len());
+    println!("{:<24}  {:<34}  {:>8}  {:>12}  {:>11}  {:>14}  {:>12}",
+             "node", "tablet", "flushes", "flushed MB", "compactions", "compacted MB",
+             "compaction s");
+    for ((node, tablet), summary) in summary_rows.iter().take(top) {
+        println!("{:<24}  {:<34}  {:>8}  {:>12.1}  {:>11}  {:>14.1}  {:>12.3}",
+                 node, tablet, summary.num_flushes, megabytes(summary.flushed_bytes),
+                 summary.num_compactions, megabytes(summary.compacted_bytes),
+                 summary.compaction_time.map_or(0.0, |d| d.num_milliseconds() as f64 / 1000.0));
+    }
+
+    println!();
+    println!("Most compactions running at once, per node and {} s bucket", bucket.num_seconds());
+    println!("{:<21}  {:<24}  {:>7}", "bucket", "node", "running");
+    for ((bucket_ts, node), running) in max_concurrent_compactions(&activities, bucket) {
+        println!("{:<21}  {:<24}  {:>7}", bucket_ts.to_string(), node, running);
+    }
+
+    let mut slowest: Vec<&Activity> = activities.iter().collect();
+    slowest.sort_by(|a, b| b.duration().cmp(&a.duration())
+        .then_with(|| a.started_at.cmp(&b.started_at)));
+    println!();
+    println!("Slowest flushes and compactions (showing up to {} of {})", top, slowest.len());
+    println!("{:>12}  {:<10}  {:<26}  {:<24}  {:<34}  {:>6}  {:>10}",
+             "duration s", "kind", "started at", "node", "tablet", "job", "MB written");
+    for activity in slowest.iter().take(top) {
+        println!("{:>12.3}  {:<10}  {:<26}  {:<24}  {:<34}  {:>6}  {:>10.1}",
+                 activity.duration().num_milliseconds() as f64 / 1000.0,
+                 format!("{:?}", activity.kind),
+                 activity.started_at.to_string(),
+                 activity.node,
+                 activity.tablet_str(),
+                 activity.job,
+                 megabytes(activity.bytes_written));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_lines::{glog_line, source};
+
+    #[test]
+    fn lines_with_numbers_out_of_range_are_skipped() {
+        let source = source(0, "node-1", "yb-tserver.INFO");
+        let mut collector = ActivityCollector::new();
+        for text in &[
+            "I0408 10:00:01.000000  1020 db_impl.cc:2131] \
+             T 35bf992dc9e9c616612e7696a6cecc1b P 8d4b2f0a1c9e4e57b6f3a2d1c0e9f8a7 [R]: \
+             [default] [JOB 99999999999999999999] Flushing memtable with next log file: 5",
+            "I0408 10:00:02.000000  1020 flush_job.cc:318] \
+             T 35bf992dc9e9c616612e7696a6cecc1b P 8d4b2f0a1c9e4e57b6f3a2d1c0e9f8a7 [R]: \
+             [default] [JOB 3] Level-0 flush table #12: 123456789012345678901234 bytes OK",
+        ] {
+            collector.add_line(&glog_line(&source, text));
+        }
+        assert!(collector.pending.is_empty());
+        assert!(collector.finished.is_empty());
+    }
+
+    const TABLET: &str = "T 35bf992dc9e9c616612e7696a6cecc1b P 8d4b2f0a1c9e4e57b6f3a2d1c0e9f8a7";
+
+    fn activities(lines: &[String]) -> ActivityCollector {
+        let source = source(0, "node-1", "yb-tserver.INFO");
+        let mut collector = ActivityCollector::new();
+        for text in lines {
+            collector.add_line(&glog_line(&source, text));
+        }
+        collector
+    }
+
+    fn summary(activity: &Activity) -> (ActivityKind, String, u64, String, i64, u64) {
+        (activity.kind, activity.tablet_str(), activity.job,
+         activity.started_at.time().to_string(), activity.duration().num_milliseconds(),
+         activity.bytes_written)
+    }
+
+    #[test]
+    fn flushes_are_paired_by_tablet_db_and_job() {
+        let collector = activities(&[
+            format!("I0408 10:00:01.000000  1020 db_impl.cc:2131] {} [R]: \
+                     [default] [JOB 3] Flushing memtable with next log file: 5", TABLET),
+            format!("I0408 10:00:01.500000  1021 db_impl.cc:2131] {} [I]: \
+                     [default] [JOB 3] Flushing memtable with next log file: 7", TABLET),
+            format!("I0408 10:00:03.000000  1020 flush_job.cc:318] {} [R]: \
+                     [default] [JOB 3] Level-0 flush table #12: 1234567 bytes OK", TABLET),
+            format!("I0408 10:00:04.000000  1021 flush_job.cc:318] {} [I]: \
+                     [default] [JOB 3] Level-0 flush table #13: 4567 bytes OK", TABLET),
+            format!("I0408 10:00:05.000000  1022 db_impl.cc:2131] {} [R]: \
+                     [default] [JOB 4] Flushing memtable with next log file: 9", TABLET),
+        ]);
+        let tablet_r = String::from("35bf992dc9e9c616612e7696a6cecc1b R");
+        let tablet_i = String::from("35bf992dc9e9c616612e7696a6cecc1b I");
+        let finished: Vec<_> = collector.finished.iter().map(summary).collect();
+        assert_eq!(finished, [
+            (ActivityKind::Flush, tablet_r, 3, String::from("10:00:01"), 2000, 1234567),
+            (ActivityKind::Flush, tablet_i, 3, String::from("10:00:01.500"), 2500, 4567),
+        ]);
+        assert_eq!(collector.pending.len(), 1);
+    }
+
+    #[test]
+    fn compactions_take_their_duration_from_the_event_log() {
+        let collector = activities(&[
+            format!("I0408 10:00:01.000000  1030 event_logger.cc:77] {} [R]: EVENT_LOG_v1 \
+                     {{\"time_micros\": 1617876001000000, \"job\": 5, \
+                     \"event\": \"compaction_started\", \"files_L0\": [12, 13], \
+                     \"score\": 1, \"input_data_size\": 2469134}}", TABLET),
+            format!("I0408 10:00:03.000000  1030 event_logger.cc:77] {} [R]: EVENT_LOG_v1 \
+                     {{\"time_micros\": 1617876003000000, \"job\": 5, \
+                     \"event\": \"compaction_finished\", \
+                     \"compaction_time_micros\": 1500000, \"output_level\": 1, \
+                     \"num_output_files\": 1, \"total_output_size\": 2097152, \
+                     \"num_input_records\": 1000, \"num_output_records\": 900}}", TABLET),
+            // Without a duration or a start line, a compaction takes no time.
+            String::from("I0408 10:00:04.000000  1031 event_logger.cc:77] EVENT_LOG_v1 \
+                          {\"time_micros\": 1617876004000000, \"job\": 6, \
+                          \"event\": \"compaction_finished\", \"total_output_size\": 10}"),
+        ]);
+        let finished: Vec<_> = collector.finished.iter().map(summary).collect();
+        assert_eq!(finished, [
+            (ActivityKind::Compaction, String::from("35bf992dc9e9c616612e7696a6cecc1b R"), 5,
+             String::from("10:00:01.500"), 1500, 2097152),
+            (ActivityKind::Compaction, String::from("- "), 6, String::from("10:00:04"), 0, 10),
+        ]);
+        // The start line is paired with the finish line all the same.
+        assert!(collector.pending.is_empty());
+    }
+
+    #[test]
+    fn concurrent_compactions_per_bucket() {
+        let activity = |kind, node: &str, started_at: &str, finished_at: &str| Activity {
+            kind,
+            node: String::from(node),
+            tablet_id: None,
+            db: String::new(),
+            job: 1,
+            started_at: format!("2021-04-08T10:00:{}", started_at).parse().unwrap(),
+            finished_at: format!("2021-04-08T10:00:{}", finished_at).parse().unwrap(),
+            bytes_written: 0,
+        };
+        let activities = [
+            activity(ActivityKind::Compaction, "n1", "05", "25"),
+            activity(ActivityKind::Compaction, "n1", "08", "12"),
+            // Starts when the first one finishes, which counts as overlapping.
+            activity(ActivityKind::Compaction, "n1", "25", "30"),
+            activity(ActivityKind::Compaction, "n2", "00", "01"),
+            activity(ActivityKind::Flush, "n2", "00", "40"),
+        ];
+        let max_running: Vec<(String, String, usize)> =
+            max_concurrent_compactions(&activities, Duration::seconds(10)).into_iter()
+                .map(|((bucket_ts, node), running)| (bucket_ts.time().to_string(), node, running))
+                .collect();
+        let row = |bucket_ts: &str, node: &str, running| {
+            (String::from(bucket_ts), String::from(node), running)
+        };
+        assert_eq!(max_running, [
+            row("10:00:00", "n1", 2),
+            row("10:00:00", "n2", 1),
+            row("10:00:10", "n1", 2),
+            row("10:00:20", "n1", 2),
+            row("10:00:30", "n1", 1),
+        ]);
+    }
+}
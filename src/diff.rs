@@ -0,0 +1,277 @@
+// ------------------------------------------------------------------------------------------------
+// Log diff: compares message templates and source locations between two sets of log lines, e.g.
+// two bundles or two time ranges of the same bundle.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+
+use crate::patterns::TemplateMiner;
+use crate::top_sources::sample_message;
+use crate::{capitalize_string, YBLogLine};
+
+/// Entries with fewer lines than this on both sides are never reported as changed, however large
+/// the ratio of their rates is.
+const MIN_CHANGE_COUNT: u64 = 5;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Side {
+    A = 0,
+    B = 1,
+}
+
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+struct SourceLocationKey {
+    file_name: String,
+    line_number: i32,
+    log_level: char,
+}
+
+/// Line counts of one template or source location on both sides.
+#[derive(Default)]
+struct DiffCounts {
+    counts: [u64; 2],
+}
+
+/// One row of the report: an entry's description and its counts on both sides.
+struct DiffRow {
+    description: String,
+    counts: [u64; 2],
+}
+
+pub(crate) struct LogDiff {
+    /// Shared by both sides, so that the same message maps to the same template on either side.
+    miner: TemplateMiner,
+    template_counts: HashMap<usize, DiffCounts>,
+    location_counts: HashMap<SourceLocationKey, DiffCounts>,
+    location_samples: HashMap<SourceLocationKey, String>,
+    num_lines: [u64; 2],
+    time_ranges: [Option<(NaiveDateTime, NaiveDateTime)>; 2],
+}
+
+impl LogDiff {
+    pub(crate) fn new() -> LogDiff {
+        LogDiff {
+            miner: TemplateMiner::new(),
+            template_counts: HashMap::new(),
+            location_counts: HashMap::new(),
+            location_samples: HashMap::new(),
+            num_lines: [0, 0],
+            time_ranges: [None, None],
+        }
+    }
+
+    pub(crate) fn add_lines<I: Iterator<Item = YBLogLine>>(&mut self, side: Side, lines: I) {
+        let side = side as usize;
+        for line in lines {
+            self.num_lines[side] += 1;
+            // Lines arrive in timestamp order.
+            self.time_ranges[side] = match self.time_ranges[side] {
+                Some((first, _)) => Some((first, line.timestamp)),
+                None => Some((line.timestamp, line.timestamp)),
+            };
+            let template_id = self.miner.add(&line);
+            self.template_counts.entry(template_id).or_default().counts[side] += 1;
+            let key = SourceLocationKey {
+                file_name: line.file_name,
+                line_number: line.line_number,
+                log_level: line.log_level,
+            };
+            if !self.location_samples.contains_key(&key) {
+                self.location_samples.insert(key.clone(), sample_message(&line.message));
+            }
+            self.location_counts.entry(key).or_default().counts[side] += 1;
+        }
+    }
+
+    /// Minutes covered by one side, at least one, so that short windows do not inflate rates.
+    fn minutes(&self, side: usize) -> f64 {
+        match self.time_ranges[side] {
+            Some((first, last)) => ((last - first).num_milliseconds() as f64 / 60000.0).max(1.0),
+            None => 1.0,
+        }
+    }
+
+    pub(crate) fn print(&self, top: usize, change_factor: f64) {
+        for side in 0..2 {
+            let name = if side == 0 { "A" } else { "B" };
+            match self.time_ranges[side] {
+                Some((first, last)) => println!("Side {}: {} lines from {} to {}",
+                                                name, self.num_lines[side], first, last),
+                None => println!("Side {}: no lines", name),
+            }
+        }
+
+        self.print_section("templates", self.template_rows(), top, change_factor);
+        self.print_section("source locations", self.location_rows(), top, change_factor);
+    }
+
+    fn template_rows(&self) -> Vec<DiffRow> {
+        self.template_counts.iter()
+            .map(|(&template_id, counts)| DiffRow {
+                description: self.miner.template(template_id),
+                counts: counts.counts,
+            })
+            .collect()
+    }
+
+    fn location_rows(&self) -> Vec<DiffRow> {
+        self.location_counts.iter()
+            .map(|(key, counts)| DiffRow {
+                description: format!("{} {}:{}  {}",
+                                     key.log_level, key.file_name, key.line_number,
+                                     self.location_samples[key]),
+                counts: counts.counts,
+            })
+            .collect()
+    }
+
+    fn rate(&self, row: &DiffRow, side: usize) -> f64 {
+        row.counts[side] as f64 / self.minutes(side)
+    }
+
+    /// How many times more or less frequent an entry is on side B, always at least 1.
+    fn change(&self, row: &DiffRow) -> f64 {
+        let (rate_a, rate_b) = (self.rate(row, 0), self.rate(row, 1));
+        rate_a.max(rate_b) / rate_a.min(rate_b)
+    }
+
+    /// Splits rows into those only on side A and only on side B, most frequent first, and those
+    /// on both sides whose rate changed by `change_factor` or more, largest change first.
+    fn split_rows<'a>(&self, rows: &'a [DiffRow], change_factor: f64)
+            -> (Vec<&'a DiffRow>, Vec<&'a DiffRow>, Vec<&'a DiffRow>) {
+        let mut only_in_a: Vec<&DiffRow> = Vec::new();
+        let mut only_in_b: Vec<&DiffRow> = Vec::new();
+        let mut changed: Vec<&DiffRow> = Vec::new();
+        for row in rows {
+            if row.counts[1] == 0 {
+                only_in_a.push(row);
+            } else if row.counts[0] == 0 {
+                only_in_b.push(row);
+            } else if row.counts[0].max(row.counts[1]) >= MIN_CHANGE_COUNT &&
+                    self.change(row) >= change_factor {
+                changed.push(row);
+            }
+        }
+        let by_count_then_description = |side: usize| {
+            move |a: &&DiffRow, b: &&DiffRow| b.counts[side].cmp(&a.counts[side])
+                .then_with(|| a.description.cmp(&b.description))
+        };
+        only_in_a.sort_by(by_count_then_description(0));
+        only_in_b.sort_by(by_count_then_description(1));
+        changed.sort_by(|a, b| self.change(b).partial_cmp(&self.change(a)).unwrap()
+            .then_with(|| a.description.cmp(&b.description)));
+        (only_in_a, only_in_b, changed)
+    }
+
+    fn print_section(&self, kind: &str, rows: Vec<DiffRow>, top: usize, change_factor: f64) {
+        let rate = |row: &DiffRow, side: usize| self.rate(row, side);
+        let change = |row: &DiffRow| self.change(row);
+        let (only_in_a, only_in_b, changed) = self.split_rows(&rows, change_factor);
+
+        for (side_name, side, only_rows) in [("A", 0, &only_in_a), ("B", 1, &only_in_b)] {
+            println!();
+            println!("{} only in {} (showing up to {} of {})",
+                     capitalize_string(kind), side_name, top, only_rows.len());
+            println!("{:>10}  {:>10}  {}", "count", "per min", kind);
+            for row in only_rows.iter().take(top) {
+                println!("{:>10}  {:>10.2}  {}",
+                         row.counts[side], rate(row, side), row.description);
+            }
+        }
+
+        println!();
+        println!("{} whose rate per minute changed by {}x or more (showing up to {} of {})",
+                 capitalize_string(kind), change_factor, top, changed.len());
+        println!("{:>10}  {:>10}  {:>10}  {:>10}  {:>8}  {}",
+                 "count A", "count B", "per min A", "per min B", "change", kind);
+        for row in changed.iter().take(top) {
+            let direction = if rate(row, 1) >= rate(row, 0) { "+" } else { "-" };
+            println!("{:>10}  {:>10}  {:>10.2}  {:>10.2}  {:>7}  {}",
+                     row.counts[0], row.counts[1], rate(row, 0), rate(row, 1),
+                     format!("{}{:.1}x", direction, change(row)),
+                     row.description);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    /// `count` lines spread evenly over `minutes`, starting at 10:00 on the given day.
+    fn lines(day: u32, file_line: &str, message: &str, count: u32, minutes: u32)
+            -> Vec<YBLogLine> {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        (0..count)
+            .map(|i| {
+                let seconds = i * minutes * 60 / (count - 1).max(1);
+                glog_line(&n1, &format!("I04{:02} {:02}:{:02}:{:02}.000000  1234 {}] {}",
+                                        day, 10 + seconds / 3600, seconds / 60 % 60,
+                                        seconds % 60, file_line,
+                                        message.replace("{}", &i.to_string())))
+            })
+            .collect()
+    }
+
+    fn sorted(mut lines: Vec<YBLogLine>) -> Vec<YBLogLine> {
+        lines.sort_by_key(|line| line.timestamp);
+        lines
+    }
+
+    fn descriptions(rows: &[&DiffRow]) -> Vec<String> {
+        rows.iter().map(|row| row.description.clone()).collect()
+    }
+
+    #[test]
+    fn templates_only_on_one_side_and_changed_rates() {
+        let mut log_diff = LogDiff::new();
+        // Side A covers 10 minutes.
+        log_diff.add_lines(Side::A, sorted([
+            lines(8, "log.cc:100", "Rolled over log segment to {}", 11, 10),
+            lines(8, "tablet_peer.cc:200", "Tablet bootstrap finished in {} ms", 3, 10),
+            lines(8, "consensus.cc:300", "Leader election lost for term {}", 2, 10),
+        ].concat()).into_iter());
+        // All lines of side B have the same timestamp, which counts as a minute.
+        log_diff.add_lines(Side::B, sorted([
+            lines(9, "log.cc:100", "Rolled over log segment to {}", 5, 0),
+            lines(9, "yb_rpc.cc:400", "Call yb.tserver.TabletServerService.Write from \
+                                       10.1.2.3:4567 took {}ms", 1, 0),
+            lines(9, "consensus.cc:300", "Leader election lost for term {}", 2, 0),
+            lines(9, "log.cc:500", "Reading log segment {}", 2, 0),
+        ].concat()).into_iter());
+        assert_eq!(log_diff.num_lines, [16, 10]);
+        assert_eq!(log_diff.minutes(0), 10.0);
+        assert_eq!(log_diff.minutes(1), 1.0);
+
+        let rows = log_diff.template_rows();
+        let (only_in_a, only_in_b, changed) = log_diff.split_rows(&rows, 2.0);
+        assert_eq!(descriptions(&only_in_a), ["Tablet bootstrap finished in <duration>"]);
+        assert_eq!(descriptions(&only_in_b), [
+            "Reading log segment <num>",
+            "Call yb.tserver.TabletServerService.Write from <host> took <duration>",
+        ]);
+        // Leader elections are 10x as frequent on side B, but too few to be reported.
+        assert_eq!(descriptions(&changed), ["Rolled over log segment to <num>"]);
+        assert_eq!(log_diff.change(changed[0]), 5.0 / 1.1);
+        assert_eq!(changed[0].counts, [11, 5]);
+
+        let (_, _, changed) = log_diff.split_rows(&rows, 5.0);
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn source_locations_show_a_sample_message() {
+        let mut log_diff = LogDiff::new();
+        log_diff.add_lines(Side::A, lines(8, "log.cc:100", "Rolled over log segment to {}", 2, 1)
+            .into_iter());
+        log_diff.add_lines(Side::B, Vec::new().into_iter());
+        let rows = log_diff.location_rows();
+        let (only_in_a, only_in_b, changed) = log_diff.split_rows(&rows, 2.0);
+        assert_eq!(descriptions(&only_in_a), ["I log.cc:100  Rolled over log segment to 0"]);
+        assert!(only_in_b.is_empty() && changed.is_empty());
+        assert_eq!(log_diff.time_ranges[1], None);
+    }
+}
@@ -0,0 +1,473 @@
+// ------------------------------------------------------------------------------------------------
+// External merge sort support: a compact binary encoding of YBLogLine, sorted runs spilled to
+// temporary files, and a k-way merge over in-memory and spilled runs.
+// ------------------------------------------------------------------------------------------------
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{Duration, NaiveDateTime};
+use uuid::Uuid;
+
+use yblp::unix_epoch;
+
+use crate::rules::{FieldValue, Fields};
+use crate::{LogSource, YBLogLine};
+
+static NEXT_SPILL_FILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn write_varint<W: Write>(w: &mut W, mut value: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    w.write_all(&buf[..len])
+}
+
+/// Returns None on a clean end of input before the first byte.
+fn read_varint<R: Read>(r: &mut R) -> io::Result<Option<u64>> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    let mut byte = [0u8; 1];
+    loop {
+        if r.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated varint"));
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+        if shift >= 64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "varint too long"));
+        }
+    }
+}
+
+fn read_required_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    read_varint(r)?.ok_or_else(
+        || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated log line record"))
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_varint(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_required_varint(r)? as usize;
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_fields<W: Write>(w: &mut W, fields: &[(String, FieldValue)]) -> io::Result<()> {
+    write_varint(w, fields.len() as u64)?;
+    for (name, value) in fields {
+        write_str(w, name)?;
+        match value {
+            FieldValue::String(value) => {
+                w.write_all(&[0])?;
+                write_str(w, value)?;
+            }
+            FieldValue::Int(value) => {
+                w.write_all(&[1])?;
+                write_varint(w, zigzag_encode(*value))?;
+            }
+            FieldValue::Float(value) => {
+                w.write_all(&[2])?;
+                w.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_fields<R: Read>(r: &mut R) -> io::Result<Fields> {
+    let num_fields = read_required_varint(r)? as usize;
+    let mut fields = Fields::with_capacity(num_fields);
+    for _ in 0..num_fields {
+        let name = read_string(r)?;
+        let mut tag = [0u8; 1];
+        r.read_exact(&mut tag)?;
+        let value = match tag[0] {
+            0 => FieldValue::String(read_string(r)?),
+            1 => FieldValue::Int(zigzag_decode(read_required_varint(r)?)),
+            2 => {
+                let mut bytes = [0u8; 8];
+                r.read_exact(&mut bytes)?;
+                FieldValue::Float(f64::from_le_bytes(bytes))
+            }
+            tag => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, format!("unknown field value tag {}", tag))),
+        };
+        fields.push((name, value));
+    }
+    Ok(fields)
+}
+
+/// Encodes a sorted run of lines. Timestamps are stored as deltas from the previous line, which
+/// keeps them to one or two bytes for a typical log.
+struct RunEncoder {
+    prev_timestamp_micros: i64,
+}
+
+impl RunEncoder {
+    fn new() -> RunEncoder {
+        RunEncoder { prev_timestamp_micros: 0 }
+    }
+
+    fn write_line<W: Write>(&mut self, w: &mut W, line: &YBLogLine) -> io::Result<()> {
+        let timestamp_micros = (line.timestamp - unix_epoch()).num_microseconds().unwrap();
+        write_varint(w, zigzag_encode(timestamp_micros - self.prev_timestamp_micros))?;
+        self.prev_timestamp_micros = timestamp_micros;
+        w.write_all(&[line.log_level as u8])?;
+        write_varint(w, zigzag_encode(line.thread_id))?;
+        write_str(w, &line.file_name)?;
+        write_varint(w, zigzag_encode(i64::from(line.line_number)))?;
+        match &line.tablet_id {
+            Some(tablet_id) => {
+                w.write_all(&[1])?;
+                w.write_all(tablet_id.as_bytes())?;
+            }
+            None => w.write_all(&[0])?,
+        }
+        write_str(w, &line.message)?;
+        write_varint(w, line.source.index as u64)?;
+        write_varint(w, line.file_offset)?;
+        write_fields(w, &line.fields)
+    }
+
+    fn read_line<R: Read>(
+            &mut self,
+            r: &mut R,
+            sources: &[Arc<LogSource>]) -> io::Result<Option<YBLogLine>> {
+        let timestamp_delta = match read_varint(r)? {
+            Some(delta) => zigzag_decode(delta),
+            None => return Ok(None),
+        };
+        let timestamp_micros = self.prev_timestamp_micros + timestamp_delta;
+        self.prev_timestamp_micros = timestamp_micros;
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte)?;
+        let log_level = char::from(byte[0]);
+        let thread_id = zigzag_decode(read_required_varint(r)?);
+        let file_name = read_string(r)?;
+        let line_number = zigzag_decode(read_required_varint(r)?) as i32;
+        r.read_exact(&mut byte)?;
+        let tablet_id = if byte[0] == 1 {
+            let mut uuid_bytes = [0u8; 16];
+            r.read_exact(&mut uuid_bytes)?;
+            Some(Uuid::from_bytes(uuid_bytes))
+        } else {
+            None
+        };
+        let message = read_string(r)?;
+        let source_index = read_required_varint(r)? as usize;
+        let source = sources.get(source_index).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData, format!("unknown source index {}", source_index)))?;
+        let file_offset = read_required_varint(r)?;
+        let fields = read_fields(r)?;
+        Ok(Some(YBLogLine {
+            log_level,
+            timestamp: unix_epoch() + Duration::microseconds(timestamp_micros),
+            thread_id,
+            file_name,
+            line_number,
+            tablet_id,
+            message,
+            source: source.clone(),
+            file_offset,
+            fields,
+        }))
+    }
+}
+
+/// Approximate heap and inline memory used by a line, for enforcing --max-memory.
+pub(crate) fn estimated_line_size(line: &YBLogLine) -> usize {
+    let fields_size: usize = line.fields.iter().map(|(name, value)| {
+        std::mem::size_of::<(String, FieldValue)>() + name.capacity() + match value {
+            FieldValue::String(value) => value.capacity(),
+            _ => 0,
+        }
+    }).sum();
+    std::mem::size_of::<YBLogLine>() + line.file_name.capacity() + line.message.capacity() +
+        fields_size
+}
+
+// ------------------------------------------------------------------------------------------------
+// SpilledRun -- a sorted run written to a temporary file, deleted when dropped
+// ------------------------------------------------------------------------------------------------
+
+pub(crate) struct SpilledRun {
+    path: PathBuf,
+    num_lines: usize,
+}
+
+impl SpilledRun {
+    pub(crate) fn write<I: Iterator<Item = YBLogLine>>(
+            lines: I, spill_dir: &Path) -> io::Result<SpilledRun> {
+        let path = spill_dir.join(format!(
+            "yblp-{}-{}.run", std::process::id(),
+            NEXT_SPILL_FILE_ID.fetch_add(1, Ordering::SeqCst)));
+        let mut spilled_run = SpilledRun { path, num_lines: 0 };
+        let mut writer = BufWriter::new(File::create(&spilled_run.path)?);
+        let mut encoder = RunEncoder::new();
+        for line in lines {
+            encoder.write_line(&mut writer, &line)?;
+            spilled_run.num_lines += 1;
+        }
+        writer.flush()?;
+        Ok(spilled_run)
+    }
+
+    pub(crate) fn num_lines(&self) -> usize {
+        self.num_lines
+    }
+
+    fn open(self, sources: Arc<Vec<Arc<LogSource>>>) -> io::Result<SpilledRunReader> {
+        Ok(SpilledRunReader {
+            reader: BufReader::new(File::open(&self.path)?),
+            decoder: RunEncoder::new(),
+            sources,
+            _run: self,
+        })
+    }
+}
+
+impl Drop for SpilledRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct SpilledRunReader {
+    reader: BufReader<File>,
+    decoder: RunEncoder,
+    sources: Arc<Vec<Arc<LogSource>>>,
+    // Keeps the file around until we are done reading it.
+    _run: SpilledRun,
+}
+
+impl Iterator for SpilledRunReader {
+    type Item = YBLogLine;
+
+    fn next(&mut self) -> Option<YBLogLine> {
+        self.decoder.read_line(&mut self.reader, &self.sources).unwrap_or_else(
+            |e| panic!("Error reading spilled run {:?}: {}", self._run.path, e))
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// MergedLines -- k-way merge of sorted runs
+// ------------------------------------------------------------------------------------------------
+
+enum SortedRunReader {
+    InMemory(std::vec::IntoIter<YBLogLine>),
+    Spilled(SpilledRunReader),
+}
+
+impl Iterator for SortedRunReader {
+    type Item = YBLogLine;
+
+    fn next(&mut self) -> Option<YBLogLine> {
+        match self {
+            SortedRunReader::InMemory(iter) => iter.next(),
+            SortedRunReader::Spilled(reader) => reader.next(),
+        }
+    }
+}
+
+type MergeKey = (NaiveDateTime, Arc<LogSource>, u64, usize);
+
+fn merge_key(line: &YBLogLine, run_index: usize) -> Reverse<MergeKey> {
+    Reverse((line.timestamp, line.source.clone(), line.file_offset, run_index))
+}
+
+/// Yields lines from a set of sorted runs in the order given by `YBLogLine::ordering_key`.
+pub(crate) struct MergedLines {
+    run_readers: Vec<SortedRunReader>,
+    heads: Vec<Option<YBLogLine>>,
+    heap: BinaryHeap<Reverse<MergeKey>>,
+    sources: Arc<Vec<Arc<LogSource>>>,
+}
+
+impl MergedLines {
+    /// `sources` is the table of all input files, needed to decode spilled runs.
+    pub(crate) fn new(
+            spilled_runs: Vec<SpilledRun>,
+            in_memory_runs: Vec<Vec<YBLogLine>>,
+            sources: Vec<Arc<LogSource>>) -> io::Result<MergedLines> {
+        let sources = Arc::new(sources);
+        let mut run_readers = Vec::with_capacity(spilled_runs.len() + in_memory_runs.len());
+        for spilled_run in spilled_runs {
+            run_readers.push(SortedRunReader::Spilled(spilled_run.open(sources.clone())?));
+        }
+        run_readers.extend(
+            in_memory_runs.into_iter().map(|run| SortedRunReader::InMemory(run.into_iter())));
+        let heads: Vec<Option<YBLogLine>> = run_readers.iter_mut().map(Iterator::next).collect();
+        let heap = heads.iter().enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|line| merge_key(line, i)))
+            .collect();
+        Ok(MergedLines { run_readers, heads, heap, sources })
+    }
+
+    /// All input files that were read, including those that had no lines in the output.
+    pub(crate) fn sources(&self) -> &[Arc<LogSource>] {
+        &self.sources
+    }
+}
+
+impl Iterator for MergedLines {
+    type Item = YBLogLine;
+
+    fn next(&mut self) -> Option<YBLogLine> {
+        let Reverse((_, _, _, run_index)) = self.heap.pop()?;
+        let line = self.heads[run_index].take().unwrap();
+        self.heads[run_index] = self.run_readers[run_index].next();
+        if let Some(next_line) = &self.heads[run_index] {
+            self.heap.push(merge_key(next_line, run_index));
+        }
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::ProcessInfo;
+
+    fn source(index: usize, node: &str) -> Arc<LogSource> {
+        Arc::new(LogSource {
+            node: Some(String::from(node)),
+            path: format!("/logs/{}/yb-tserver.INFO", node),
+            index,
+            process: ProcessInfo {
+                program: String::from("yb-tserver"),
+                pid: None,
+                started_at: None,
+                build: None,
+            },
+            clock_offset: Duration::zero(),
+        })
+    }
+
+    fn line(source: &Arc<LogSource>, micros: i64, file_offset: u64, message: &str) -> YBLogLine {
+        YBLogLine {
+            log_level: 'I',
+            timestamp: NaiveDate::from_ymd_opt(2021, 4, 8).unwrap().and_hms_opt(10, 0, 0)
+                .unwrap() + Duration::microseconds(micros),
+            thread_id: 1234,
+            file_name: String::from("raft_consensus.cc"),
+            line_number: 875,
+            tablet_id: None,
+            message: String::from(message),
+            source: source.clone(),
+            file_offset,
+            fields: Fields::new(),
+        }
+    }
+
+    fn summary(line: &YBLogLine) -> (NaiveDateTime, usize, u64, String) {
+        (line.timestamp, line.source.index, line.file_offset, line.message.clone())
+    }
+
+    #[test]
+    fn run_encoder_round_trips_all_fields() {
+        let sources = vec![source(0, "node-1"), source(1, "node-2")];
+        let mut first = line(&sources[1], 123_456, 7, "multi-line\n    @ 0x1  yb::Foo()");
+        first.log_level = 'E';
+        first.thread_id = -1;
+        first.line_number = i32::MAX;
+        first.tablet_id = Uuid::parse_str("0123456789abcdef0123456789abcdef").ok();
+        first.fields = vec![
+            (String::from("method"), FieldValue::String(String::from("Write"))),
+            (String::from("duration_ms"), FieldValue::Int(-42)),
+            (String::from("ratio"), FieldValue::Float(0.25)),
+        ];
+        // Earlier than the previous line, so the timestamp delta is negative.
+        let second = line(&sources[0], -5, u64::MAX, "");
+        let third = line(&sources[0], 3_600_000_000, 0, "\u{e9}t\u{e9}");
+
+        let mut buf: Vec<u8> = Vec::new();
+        let mut encoder = RunEncoder::new();
+        for original in &[&first, &second, &third] {
+            encoder.write_line(&mut buf, original).unwrap();
+        }
+        let mut reader: &[u8] = &buf;
+        let mut decoder = RunEncoder::new();
+        for original in &[&first, &second, &third] {
+            let decoded = decoder.read_line(&mut reader, &sources).unwrap().unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", original));
+            assert_eq!(decoded.source.index, original.source.index);
+            assert_eq!(decoded.fields, original.fields);
+        }
+        assert!(decoder.read_line(&mut reader, &sources).unwrap().is_none());
+    }
+
+    #[test]
+    fn run_encoder_rejects_truncated_records() {
+        let sources = vec![source(0, "node-1")];
+        let mut buf: Vec<u8> = Vec::new();
+        RunEncoder::new().write_line(&mut buf, &line(&sources[0], 0, 0, "message")).unwrap();
+        for len in 1..buf.len() {
+            let mut reader: &[u8] = &buf[..len];
+            assert!(RunEncoder::new().read_line(&mut reader, &sources).is_err(), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn merge_orders_spilled_and_in_memory_runs() {
+        let sources = vec![source(0, "node-1"), source(1, "node-2")];
+        let spilled = SpilledRun::write(vec![
+            line(&sources[1], 0, 0, "b0"),
+            line(&sources[1], 20, 10, "b20"),
+            line(&sources[1], 40, 20, "b40"),
+        ].into_iter(), &std::env::temp_dir()).unwrap();
+        assert_eq!(spilled.num_lines(), 3);
+        let spill_path = spilled.path.clone();
+        let in_memory = vec![
+            vec![line(&sources[0], 0, 5, "a0"), line(&sources[0], 30, 6, "a30")],
+            vec![line(&sources[0], 0, 1, "a0 earlier in file"), line(&sources[1], 20, 15, "b20'")],
+            Vec::new(),
+        ];
+        let merged: Vec<YBLogLine> =
+            MergedLines::new(vec![spilled], in_memory, sources.clone()).unwrap().collect();
+        let messages: Vec<&str> = merged.iter().map(|line| line.message.as_str()).collect();
+        // Equal timestamps are ordered by source (node-1 first), then file offset.
+        assert_eq!(messages, vec!["a0 earlier in file", "a0", "b0", "b20", "b20'", "a30", "b40"]);
+        let mut keys: Vec<_> = merged.iter().map(summary).collect();
+        keys.sort();
+        assert_eq!(keys, merged.iter().map(summary).collect::<Vec<_>>());
+        // The spill file is removed once the merge is done with it.
+        assert!(!spill_path.exists());
+    }
+}
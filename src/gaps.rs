@@ -0,0 +1,184 @@
+// ------------------------------------------------------------------------------------------------
+// Log gaps: silences within a file, in the order its lines were written, and silences of the
+// whole bundle.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::external_sort::MergedLines;
+use crate::LogSource;
+
+/// A silence between two consecutive lines.
+struct Gap {
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    /// The interned file:line of the log statement before the silence.
+    last_location: u32,
+    source: Option<Arc<LogSource>>,
+}
+
+impl Gap {
+    fn duration(&self) -> Duration {
+        self.to - self.from
+    }
+}
+
+/// A line's position in its file, timestamp, and interned file:line of its log statement.
+struct LineInfo {
+    file_offset: u64,
+    timestamp: NaiveDateTime,
+    location: u32,
+}
+
+/// Gaps within files and of the whole bundle, each longest first.
+struct Gaps {
+    file_gaps: Vec<Gap>,
+    bundle_gaps: Vec<Gap>,
+    /// Interned file:line locations of log statements.
+    locations: Vec<String>,
+}
+
+fn sort_gaps(gaps: &mut [Gap]) {
+    gaps.sort_by(|a, b| b.duration().cmp(&a.duration()).then_with(|| a.from.cmp(&b.from)));
+}
+
+fn print_gaps(gaps: &[Gap], top: usize, locations: &[String]) {
+    println!("{:>12}  {:<26}  {:<26}  {:<32}  file",
+             "duration (s)", "from", "to", "last line before the gap");
+    for gap in gaps.iter().take(top) {
+        let file = match &gap.source {
+            Some(source) => format!("{} {}", source.node.as_deref().unwrap_or("-"), source.path),
+            None => String::new(),
+        };
+        println!("{:>12.3}  {:<26}  {:<26}  {:<32}  {}",
+                 gap.duration().num_milliseconds() as f64 / 1000.0,
+                 gap.from.to_string(), gap.to.to_string(),
+                 locations[gap.last_location as usize],
+                 file);
+    }
+}
+
+/// Finds the gaps of at least `min_gap` between consecutive lines of each file, taken in file
+/// order, and between consecutive lines of all files, taken in time order.
+fn find_gaps(lines: MergedLines, min_gap: Duration) -> Gaps {
+    let sources: Vec<Arc<LogSource>> = lines.sources().to_vec();
+    let mut locations: Vec<String> = Vec::new();
+    let mut location_ids: HashMap<String, u32> = HashMap::new();
+    let mut lines_by_source: HashMap<usize, Vec<LineInfo>> = HashMap::new();
+
+    let mut bundle_gaps: Vec<Gap> = Vec::new();
+    let mut previous: Option<(NaiveDateTime, u32)> = None;
+    for line in lines {
+        let location_str = format!("{}:{}", line.file_name, line.line_number);
+        let location = match location_ids.get(&location_str) {
+            Some(location) => *location,
+            None => {
+                let location = locations.len() as u32;
+                locations.push(location_str.clone());
+                location_ids.insert(location_str, location);
+                location
+            }
+        };
+        if let Some((previous_timestamp, previous_location)) = previous {
+            if line.timestamp - previous_timestamp >= min_gap {
+                bundle_gaps.push(Gap {
+                    from: previous_timestamp,
+                    to: line.timestamp,
+                    last_location: previous_location,
+                    source: None,
+                });
+            }
+        }
+        previous = Some((line.timestamp, location));
+        lines_by_source.entry(line.source.index).or_default().push(LineInfo {
+            file_offset: line.file_offset,
+            timestamp: line.timestamp,
+            location,
+        });
+    }
+
+    let mut file_gaps: Vec<Gap> = Vec::new();
+    for (source_index, mut source_lines) in lines_by_source {
+        // Lines come in timestamp order; gaps are measured in the order they were written.
+        source_lines.sort_by_key(|line| line.file_offset);
+        for pair in source_lines.windows(2) {
+            if pair[1].timestamp - pair[0].timestamp >= min_gap {
+                file_gaps.push(Gap {
+                    from: pair[0].timestamp,
+                    to: pair[1].timestamp,
+                    last_location: pair[0].location,
+                    source: Some(sources[source_index].clone()),
+                });
+            }
+        }
+    }
+
+    sort_gaps(&mut file_gaps);
+    sort_gaps(&mut bundle_gaps);
+    Gaps { file_gaps, bundle_gaps, locations }
+}
+
+/// Prints the `top` longest gaps of at least `min_gap` between consecutive lines of each file,
+/// taken in file order, and between consecutive lines of all files, taken in time order.
+pub(crate) fn print_gaps_report(lines: MergedLines, min_gap: Duration, top: usize) {
+    let Gaps { file_gaps, bundle_gaps, locations } = find_gaps(lines, min_gap);
+    println!("Gaps of {} s or more between consecutive lines of a file (showing up to {} of {})",
+             min_gap.num_milliseconds() as f64 / 1000.0, top, file_gaps.len());
+    print_gaps(&file_gaps, top, &locations);
+    println!();
+    println!("Periods of {} s or more in which no file has lines (showing up to {} of {})",
+             min_gap.num_milliseconds() as f64 / 1000.0, top, bundle_gaps.len());
+    print_gaps(&bundle_gaps, top, &locations);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, merged_lines, source};
+
+    fn summary(gaps: &Gaps, file_gaps: bool) -> Vec<(String, String, &str, Option<&str>)> {
+        let gap_list = if file_gaps { &gaps.file_gaps } else { &gaps.bundle_gaps };
+        gap_list.iter()
+            .map(|gap| (gap.from.time().to_string(), gap.to.time().to_string(),
+                        gaps.locations[gap.last_location as usize].as_str(),
+                        gap.source.as_ref().and_then(|source| source.node.as_deref())))
+            .collect()
+    }
+
+    #[test]
+    fn gaps_within_files_and_of_the_bundle() {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let n2 = source(1, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        // In the order the lines were written. The clock of n1 jumped back after 10:00:30.
+        let lines = merged_lines(&[n1.clone(), n2.clone()], vec![
+            glog_line(&n1, "I0408 10:00:00.000000  1234 heartbeater.cc:300] \
+                            Sending a full tablet report to master..."),
+            glog_line(&n1, "I0408 10:00:30.000000  1234 log.cc:100] \
+                            Rolled over log segment to 12"),
+            glog_line(&n1, "I0408 10:00:05.000000  1234 maintenance_manager.cc:400] \
+                            Scheduling FlushMRSOp(tablet-1)"),
+            glog_line(&n2, "I0408 10:00:10.000000  5678 heartbeater.cc:300] \
+                            Sending a full tablet report to master..."),
+            glog_line(&n2, "W0408 10:01:00.000000  5678 yb_rpc.cc:400] \
+                            Call yb.tserver.TabletServerService.Write from 10.1.2.3:45678 \
+                            took 1234ms"),
+        ]);
+
+        let gaps = find_gaps(lines, Duration::seconds(20));
+        let gap = |from: &str, to: &str, location, node| {
+            (String::from(from), String::from(to), location, node)
+        };
+        assert_eq!(summary(&gaps, true), [
+            gap("10:00:10", "10:01:00", "heartbeater.cc:300", Some("n2")),
+            gap("10:00:00", "10:00:30", "heartbeater.cc:300", Some("n1")),
+        ]);
+        // A gap exactly as long as the minimum counts.
+        assert_eq!(summary(&gaps, false), [
+            gap("10:00:30", "10:01:00", "log.cc:100", None),
+            gap("10:00:10", "10:00:30", "heartbeater.cc:300", None),
+        ]);
+    }
+}
@@ -0,0 +1,233 @@
+// ------------------------------------------------------------------------------------------------
+// Histogram of log volume over time, by level and by node. Lines that glog copies into the log
+// files of lower severity are counted once.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use chrono::{Duration, NaiveDateTime};
+
+use yblp::bucket_start;
+
+use crate::processes::without_severity_copies;
+use crate::YBLogLine;
+
+const SPARKLINE_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Sparklines wider than this are downsampled so that one character covers several buckets.
+const MAX_SPARKLINE_WIDTH: usize = 120;
+
+/// Renders counts as a string of block characters scaled to the largest count. Zero counts are
+/// shown as spaces so that silent periods stand out.
+pub(crate) fn sparkline(counts: &[u64]) -> String {
+    let max_count = counts.iter().cloned().max().unwrap_or(0);
+    counts.iter().map(|&count| {
+        if count == 0 {
+            ' '
+        } else {
+            let index = ((count * SPARKLINE_CHARS.len() as u64 - 1) / max_count) as usize;
+            SPARKLINE_CHARS[index]
+        }
+    }).collect()
+}
+
+/// Sums groups of `factor` consecutive counts.
+pub(crate) fn downsample(counts: &[u64], factor: usize) -> Vec<u64> {
+    counts.chunks(factor).map(|chunk| chunk.iter().sum()).collect()
+}
+
+/// Line counts per (bucket, node, level).
+pub(crate) struct Histogram {
+    bucket: Duration,
+    nodes: Vec<String>,
+    node_indexes: HashMap<String, usize>,
+    counts: BTreeMap<(NaiveDateTime, usize, char), u64>,
+}
+
+impl Histogram {
+    pub(crate) fn new(bucket: Duration) -> Histogram {
+        Histogram {
+            bucket,
+            nodes: Vec::new(),
+            node_indexes: HashMap::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, line: &YBLogLine) {
+        let node = line.source.node.as_deref().unwrap_or("?");
+        let node_index = match self.node_indexes.get(node) {
+            Some(node_index) => *node_index,
+            None => {
+                self.nodes.push(String::from(node));
+                self.node_indexes.insert(String::from(node), self.nodes.len() - 1);
+                self.nodes.len() - 1
+            }
+        };
+        let bucket_ts = bucket_start(line.timestamp, self.bucket);
+        *self.counts.entry((bucket_ts, node_index, line.log_level)).or_insert(0) += 1;
+    }
+
+    fn bucket_range(&self) -> Option<(NaiveDateTime, usize)> {
+        let first_bucket = self.counts.keys().next()?.0;
+        let last_bucket = self.counts.keys().next_back()?.0;
+        let num_buckets = ((last_bucket - first_bucket).num_milliseconds() /
+                           self.bucket.num_milliseconds()) as usize + 1;
+        Some((first_bucket, num_buckets))
+    }
+
+    /// Dense per-bucket counts of the lines matching `filter(node_index, level)`.
+    fn series<F: Fn(usize, char) -> bool>(
+            &self, first_bucket: NaiveDateTime, num_buckets: usize, filter: F) -> Vec<u64> {
+        let mut series = vec![0u64; num_buckets];
+        for ((bucket_ts, node_index, level), count) in &self.counts {
+            if filter(*node_index, *level) {
+                let bucket_index = ((*bucket_ts - first_bucket).num_milliseconds() /
+                                    self.bucket.num_milliseconds()) as usize;
+                series[bucket_index] += count;
+            }
+        }
+        series
+    }
+
+    pub(crate) fn print(&self) {
+        let (first_bucket, num_buckets) = match self.bucket_range() {
+            Some(range) => range,
+            None => {
+                println!("No lines to build a histogram from");
+                return;
+            }
+        };
+        let factor = num_buckets.div_ceil(MAX_SPARKLINE_WIDTH);
+        println!("Histogram from {} to {} in {} s buckets ({} buckets{})",
+                 first_bucket,
+                 first_bucket + self.bucket * (num_buckets as i32),
+                 self.bucket.num_milliseconds() as f64 / 1000.0,
+                 num_buckets,
+                 if factor > 1 {
+                     format!(", {} buckets per character", factor)
+                 } else {
+                     String::new()
+                 });
+
+        let mut rows: Vec<(String, Vec<u64>)> = Vec::new();
+        rows.push((String::from("all"), self.series(first_bucket, num_buckets, |_, _| true)));
+        for level in &['F', 'E', 'W', 'I'] {
+            let series = self.series(first_bucket, num_buckets, |_, l| l == *level);
+            if series.iter().any(|count| *count > 0) {
+                rows.push((format!("level {}", level), series));
+            }
+        }
+        let mut node_order: Vec<usize> = (0..self.nodes.len()).collect();
+        node_order.sort_by(|a, b| self.nodes[*a].cmp(&self.nodes[*b]));
+        for node_index in node_order {
+            rows.push((format!("node {}", self.nodes[node_index]),
+                       self.series(first_bucket, num_buckets, |n, _| n == node_index)));
+        }
+
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        println!("{:<width$}  {:>10}  {:>10}  over time", "series", "total", "max/bucket",
+                 width = label_width);
+        for (label, series) in &rows {
+            println!("{:<width$}  {:>10}  {:>10}  |{}|",
+                     label,
+                     series.iter().sum::<u64>(),
+                     series.iter().max().unwrap(),
+                     sparkline(&downsample(series, factor)),
+                     width = label_width);
+        }
+    }
+
+    /// Writes non-zero counts in long format: bucket_start,node,level,count.
+    pub(crate) fn write_csv(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "bucket_start,node,level,count")?;
+        for ((bucket_ts, node_index, level), count) in &self.counts {
+            writeln!(writer, "{},{},{},{}",
+                     bucket_ts.format("%Y-%m-%d %H:%M:%S%.3f"), self.nodes[*node_index], level,
+                     count)?;
+        }
+        writer.flush()
+    }
+}
+
+pub(crate) fn print_histogram_report<I: Iterator<Item = YBLogLine>>(
+        lines: I, bucket: Duration, csv_file: Option<&str>) {
+    let mut histogram = Histogram::new(bucket);
+    let mut lines = without_severity_copies(lines);
+    for line in &mut lines {
+        histogram.add(&line);
+    }
+    lines.print_note();
+    histogram.print();
+    if let Some(csv_file) = csv_file {
+        histogram.write_csv(csv_file).unwrap_or_else(
+            |e| panic!("Could not write CSV file {}: {}", csv_file, e));
+        println!("Wrote histogram CSV to {}", csv_file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[0, 1, 4, 8, 0]), " ▁▄█ ");
+        // The largest count is always a full block.
+        assert_eq!(sparkline(&[3, 1, 2]), "█▃▆");
+    }
+
+    #[test]
+    fn downsampling() {
+        assert_eq!(downsample(&[1, 2, 3, 4, 5], 2), [3, 7, 5]);
+        assert_eq!(downsample(&[1, 2, 3], 1), [1, 2, 3]);
+    }
+
+    #[test]
+    fn counts_per_bucket_node_and_level() {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let n2 = source(1, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        let mut histogram = Histogram::new(Duration::seconds(10));
+        for (source, text) in &[
+            (&n2, "I0408 10:00:01.000000  5678 log.cc:100] Rolled over log segment to 12"),
+            (&n1, "W0408 10:00:09.999999  1234 yb_rpc.cc:400] \
+                   Call yb.tserver.TabletServerService.Write from 10.1.2.3:45678 took 1234ms"),
+            (&n1, "I0408 10:00:10.000000  1234 log.cc:100] Rolled over log segment to 13"),
+            (&n1, "E0408 10:00:35.000000  1234 tablet_peer.cc:200] Tablet bootstrap failed"),
+        ] {
+            histogram.add(&glog_line(source, text));
+        }
+
+        let (first_bucket, num_buckets) = histogram.bucket_range().unwrap();
+        assert_eq!(first_bucket.to_string(), "2021-04-08 10:00:00");
+        assert_eq!(num_buckets, 4);
+        assert_eq!(histogram.series(first_bucket, num_buckets, |_, _| true), [2, 1, 0, 1]);
+        assert_eq!(histogram.series(first_bucket, num_buckets, |_, level| level == 'I'),
+                   [1, 1, 0, 0]);
+        let n1_index = histogram.node_indexes["n1"];
+        assert_eq!(histogram.series(first_bucket, num_buckets, |node, _| node == n1_index),
+                   [1, 1, 0, 1]);
+
+        let csv_path = std::env::temp_dir()
+            .join(format!("yblp-histogram-{}.csv", std::process::id()));
+        let csv_path = csv_path.to_str().unwrap();
+        histogram.write_csv(csv_path).unwrap();
+        let csv = std::fs::read_to_string(csv_path).unwrap();
+        std::fs::remove_file(csv_path).unwrap();
+        assert_eq!(csv, "bucket_start,node,level,count\n\
+                         2021-04-08 10:00:00.000,n2,I,1\n\
+                         2021-04-08 10:00:00.000,n1,W,1\n\
+                         2021-04-08 10:00:10.000,n1,I,1\n\
+                         2021-04-08 10:00:30.000,n1,E,1\n");
+    }
+
+    #[test]
+    fn no_buckets_without_lines() {
+        assert!(Histogram::new(Duration::seconds(10)).bucket_range().is_none());
+    }
+}
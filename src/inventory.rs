@@ -0,0 +1,268 @@
+// ------------------------------------------------------------------------------------------------
+// Version and build inventory: which build each program on each node ran over time, periods when
+// the cluster ran more than one version, and non-release builds.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDateTime;
+
+use crate::external_sort::MergedLines;
+use crate::processes::{NodeProgram, ProcessInstances};
+use crate::BuildInfo;
+
+/// Only the release build type is expected in production.
+const RELEASE_BUILD_TYPE: &str = "RELEASE";
+
+/// Revisions are shortened to this many characters, as in `git log --oneline`.
+const SHORT_REVISION_LEN: usize = 10;
+
+/// Consecutive process instances of a program on a node that ran the same build.
+struct InventoryRow<'a> {
+    key: &'a NodeProgram,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    build: Option<&'a BuildInfo>,
+    num_processes: usize,
+}
+
+/// A time range in which processes of more than one version were running.
+struct MixedVersionPeriod {
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    versions: BTreeSet<(String, u64)>,
+}
+
+fn version_key(build: &BuildInfo) -> (String, u64) {
+    (build.version.clone(), build.build_number)
+}
+
+fn build_columns(build: Option<&BuildInfo>) -> String {
+    match build {
+        Some(build) => format!("{:<12}  {:>6}  {:<10}  {}",
+                               build.version, build.build_number, build.build_type,
+                               &build.revision[..build.revision.len().min(SHORT_REVISION_LEN)]),
+        None => format!("{:<12}  {:>6}  {:<10}  {}", "unknown", "", "", ""),
+    }
+}
+
+/// Sweeps over the start and end of all rows and returns the periods in which more than one
+/// version (ignoring build type) was running somewhere in the cluster.
+fn mixed_version_periods(rows: &[InventoryRow]) -> Vec<MixedVersionPeriod> {
+    // Rows include both ends, so at equal times starts sort before ends.
+    let mut events: Vec<(NaiveDateTime, bool, (String, u64))> = Vec::new();
+    for row in rows {
+        if let Some(build) = row.build {
+            events.push((row.from, false, version_key(build)));
+            events.push((row.to, true, version_key(build)));
+        }
+    }
+    events.sort();
+
+    let mut periods: Vec<MixedVersionPeriod> = Vec::new();
+    let mut running: BTreeMap<(String, u64), usize> = BTreeMap::new();
+    let mut current: Option<MixedVersionPeriod> = None;
+    for (timestamp, is_end, version) in events {
+        if !is_end {
+            *running.entry(version).or_insert(0) += 1;
+        } else {
+            let count = running.get_mut(&version).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&version);
+            }
+        }
+        if running.len() > 1 {
+            let period = current.get_or_insert_with(|| MixedVersionPeriod {
+                from: timestamp,
+                to: timestamp,
+                versions: BTreeSet::new(),
+            });
+            period.versions.extend(running.keys().cloned());
+        } else if let Some(mut period) = current.take() {
+            period.to = timestamp;
+            periods.push(period);
+        }
+    }
+    periods
+}
+
+/// Merges consecutive process instances of each program on each node that ran the same build.
+/// Instances without any known time range are left out.
+fn inventory_rows(instances: &ProcessInstances) -> Vec<InventoryRow<'_>> {
+    let mut rows: Vec<InventoryRow> = Vec::new();
+    for (key, node_instances) in &instances.by_program {
+        let mut previous_row: Option<InventoryRow> = None;
+        for instance in node_instances {
+            let (from, to) = match instance.time_range() {
+                Some(time_range) => time_range,
+                None => continue,
+            };
+            let build = instance.build.as_ref();
+            match &mut previous_row {
+                Some(row) if row.build == build => {
+                    row.to = row.to.max(to);
+                    row.num_processes += 1;
+                }
+                _ => {
+                    rows.extend(previous_row.take());
+                    previous_row = Some(InventoryRow { key, from, to, build, num_processes: 1 });
+                }
+            }
+        }
+        rows.extend(previous_row);
+    }
+    rows
+}
+
+/// Prints a table of node, program, version and build type over time, followed by the periods
+/// in which the cluster ran mixed versions (e.g. rolling upgrades) and any non-release builds.
+pub(crate) fn print_inventory_report(lines: MergedLines) {
+    let mut instances = ProcessInstances::new(lines.sources());
+    for line in lines {
+        instances.observe(&line);
+    }
+    let rows = inventory_rows(&instances);
+
+    println!("Builds run by {} programs across nodes", instances.by_program.len());
+    println!("{:<24}  {:<12}  {:<26}  {:<26}  {:<12}  {:>6}  {:<10}  {:<10}  processes",
+             "node", "program", "from", "to", "version", "build", "type", "revision");
+    for row in &rows {
+        println!("{:<24}  {:<12}  {:<26}  {:<26}  {}  {}",
+                 row.key.0, row.key.1, row.from.to_string(), row.to.to_string(),
+                 build_columns(row.build), row.num_processes);
+    }
+
+    let periods = mixed_version_periods(&rows);
+    println!();
+    if periods.is_empty() {
+        println!("No mixed-version periods");
+    }
+    for period in &periods {
+        println!("Mixed versions from {} to {} ({} s):",
+                 period.from, period.to, (period.to - period.from).num_seconds());
+        for (version, build_number) in &period.versions {
+            let programs: Vec<String> = rows.iter()
+                .filter(|row| row.from <= period.to && row.to >= period.from)
+                .filter(|row| match row.build {
+                    Some(build) => &build.version == version && build.build_number == *build_number,
+                    None => false,
+                })
+                .map(|row| format!("{}/{}", row.key.0, row.key.1))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            println!("  {} build {}: {}", version, build_number, programs.join(", "));
+        }
+    }
+
+    let non_release_rows: Vec<&InventoryRow> = rows.iter()
+        .filter(|row| match row.build {
+            Some(build) => build.build_type != RELEASE_BUILD_TYPE,
+            None => false,
+        })
+        .collect();
+    println!();
+    if non_release_rows.is_empty() {
+        println!("No non-release builds");
+    } else {
+        println!("Non-release builds, not meant for production:");
+    }
+    for row in non_release_rows {
+        println!("{:<24}  {:<12}  {:<26}  {:<26}  {}",
+                 row.key.0, row.key.1, row.from.to_string(), row.to.to_string(),
+                 build_columns(row.build));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use yblp::{GlogFormat, LogFormat};
+
+    use super::*;
+    use crate::test_lines::{glog_line, log_source, merged_lines};
+    use crate::{LogSource, YBLogFilePreamble};
+
+    /// The build from the application fingerprint line of a preamble.
+    fn build(version: &str, build_number: u64, build_type: &str) -> BuildInfo {
+        let mut preamble = YBLogFilePreamble::default();
+        GlogFormat::new().parse_preamble_line(
+            &format!("Application fingerprint: version {} build {} revision \
+                      1b7bb2fc3b910912ef758ffca83b076124051c10 build_type {} built at \
+                      30 Mar 2021 16:14:23 UTC", version, build_number, build_type),
+            &mut preamble.metadata);
+        preamble.build_info().unwrap()
+    }
+
+    /// A tserver or master log file started at `from` on a node, with one line at `to`.
+    fn process(index: usize, node: &str, program: &str, pid: u32, from: &str, to: &str,
+               build: &BuildInfo) -> (Arc<LogSource>, String) {
+        let mut source = log_source(index, node, &format!(
+            "{}.{}.yugabyte.log.INFO.20210408-100000.{}", program, node, pid));
+        source.process.started_at = Some(format!("2021-04-08T{}", from).parse().unwrap());
+        source.process.build = Some(build.clone());
+        let line = format!("I0408 {}.000000 {:>5} heartbeater.cc:300] \
+                            Sending a full tablet report to master...", to, pid);
+        (Arc::new(source), line)
+    }
+
+    #[test]
+    fn rows_and_mixed_version_periods() {
+        let old_build = build("2.4.1.1", 4, "RELEASE");
+        let new_build = build("2.6.0.0", 1, "RELEASE");
+        let debug_build = build("2.6.0.0", 1, "DEBUG");
+        assert_eq!(old_build.to_string(), "2.4.1.1 build 4 RELEASE");
+        assert_eq!(old_build.revision, "1b7bb2fc3b910912ef758ffca83b076124051c10");
+
+        let processes = [
+            process(0, "n1", "yb-tserver", 1001, "10:00:00", "10:10:00", &old_build),
+            process(1, "n1", "yb-tserver", 1002, "10:11:00", "10:20:00", &old_build),
+            process(2, "n1", "yb-tserver", 1003, "10:30:00", "10:40:00", &new_build),
+            process(3, "n2", "yb-tserver", 2001, "10:00:00", "10:35:00", &old_build),
+            process(4, "n2", "yb-tserver", 2002, "10:36:00", "10:40:00", &new_build),
+            process(5, "n3", "yb-master", 3001, "10:30:00", "10:40:00", &debug_build),
+        ];
+        let sources: Vec<Arc<LogSource>> =
+            processes.iter().map(|(source, _)| source.clone()).collect();
+        let lines = processes.iter().map(|(source, line)| glog_line(source, line)).collect();
+        let mut instances = ProcessInstances::new(&sources);
+        for line in merged_lines(&sources, lines) {
+            instances.observe(&line);
+        }
+
+        let rows = inventory_rows(&instances);
+        let summary: Vec<(&str, &str, String, String, String, usize)> = rows.iter()
+            .map(|row| (row.key.0.as_str(), row.key.1.as_str(), row.from.time().to_string(),
+                        row.to.time().to_string(), row.build.unwrap().to_string(),
+                        row.num_processes))
+            .collect();
+        let row = |node, program, from: &str, to: &str, build: &BuildInfo, num_processes| {
+            (node, program, String::from(from), String::from(to), build.to_string(),
+             num_processes)
+        };
+        assert_eq!(summary, [
+            row("n1", "yb-tserver", "10:00:00", "10:20:00", &old_build, 2),
+            row("n1", "yb-tserver", "10:30:00", "10:40:00", &new_build, 1),
+            row("n2", "yb-tserver", "10:00:00", "10:35:00", &old_build, 1),
+            row("n2", "yb-tserver", "10:36:00", "10:40:00", &new_build, 1),
+            row("n3", "yb-master", "10:30:00", "10:40:00", &debug_build, 1),
+        ]);
+
+        // Build types do not make versions differ.
+        let periods = mixed_version_periods(&rows);
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].from.time().to_string(), "10:30:00");
+        assert_eq!(periods[0].to.time().to_string(), "10:35:00");
+        assert_eq!(periods[0].versions.iter().cloned().collect::<Vec<_>>(), [
+            (String::from("2.4.1.1"), 4), (String::from("2.6.0.0"), 1),
+        ]);
+    }
+
+    #[test]
+    fn revisions_are_shortened() {
+        assert_eq!(build_columns(Some(&build("2.4.1.1", 4, "RELEASE"))),
+                   "2.4.1.1            4  RELEASE     1b7bb2fc3b");
+    }
+}
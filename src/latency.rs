@@ -0,0 +1,275 @@
+// ------------------------------------------------------------------------------------------------
+// Latency extraction: durations of operations parsed out of messages such as "took N ms" and
+// "Time spent ...", with percentiles per operation and time bucket.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Duration, NaiveDateTime};
+use regex::Regex;
+use uuid::Uuid;
+
+use yblp::{bucket_start, parse_regex};
+
+use crate::YBLogLine;
+
+/// A duration found in a log line, tagged with what took that long.
+pub(crate) struct LatencySample {
+    pub(crate) operation: String,
+    pub(crate) millis: f64,
+}
+
+/// Finds the duration of an operation in a log line. Extractors are tried in order and the first
+/// one that returns a sample wins, so more specific extractors go first.
+pub(crate) trait LatencyExtractor {
+    fn extract(&self, line: &YBLogLine) -> Option<LatencySample>;
+}
+
+/// Extracts a duration with a regex that has `value` and `unit` groups, and optionally an `op`
+/// group that is appended to `operation_prefix`.
+struct RegexLatencyExtractor {
+    operation_prefix: &'static str,
+    re: Regex,
+}
+
+impl RegexLatencyExtractor {
+    fn new(operation_prefix: &'static str, re: &str) -> RegexLatencyExtractor {
+        RegexLatencyExtractor { operation_prefix, re: parse_regex(re) }
+    }
+}
+
+impl LatencyExtractor for RegexLatencyExtractor {
+    fn extract(&self, line: &YBLogLine) -> Option<LatencySample> {
+        let captures = self.re.captures(&line.message)?;
+        let value: f64 = captures.name("value")?.as_str().parse().ok()?;
+        let millis = match captures.name("unit").map(|unit| unit.as_str()) {
+            Some("us") => value / 1000.0,
+            Some("ms") => value,
+            Some("s") => value * 1000.0,
+            _ => return None,
+        };
+        let operation = match captures.name("op") {
+            Some(op) => format!("{}{}", self.operation_prefix, op.as_str().trim()),
+            None => String::from(self.operation_prefix),
+        };
+        Some(LatencySample { operation, millis })
+    }
+}
+
+/// Optional "T <tablet> P <peer>: " prefix that tablet-level messages start with, so that anchored
+/// extractors also match them.
+macro_rules! tablet_prefix {
+    () => { r"^(?:T [0-9a-f]{32} P [0-9a-f]{32}(?: \[[^\]]*\])?: )?" }
+}
+
+pub(crate) fn default_extractors() -> Vec<Box<dyn LatencyExtractor>> {
+    vec![
+        // Call yb.tserver.TabletServerService.Write 10.0.0.1:41688 => 10.0.0.2:9100
+        // (request call id 2451) took 1520ms (client timeout 60000ms).
+        Box::new(RegexLatencyExtractor::new(
+            "rpc ",
+            concat!(tablet_prefix!(), inbound_call_re!("op"), r"took (?P<value>\d+)(?P<unit>ms)"))),
+        // Time spent Apply: real 0.791s	user 0.001s	sys 0.000s
+        Box::new(RegexLatencyExtractor::new(
+            "time spent ",
+            concat!(
+                tablet_prefix!(),
+                r"Time spent (?P<op>[^:]+): real (?P<value>[\d.]+)(?P<unit>s)"
+            ))),
+        // UpdateReplica running for 1.000s in thread 1234:
+        Box::new(RegexLatencyExtractor::new(
+            "long operation ",
+            concat!(
+                tablet_prefix!(),
+                r"(?P<op>\w[\w ]*) running for (?P<value>[\d.]+)(?P<unit>s) in thread"
+            ))),
+        // Slow RPC ... took 1234ms, or any other "<operation> took <duration>".
+        Box::new(RegexLatencyExtractor::new(
+            "",
+            concat!(
+                r"(?P<op>[A-Za-z][\w.]*(?: [\w.]+){0,3}) ",
+                r"took (?P<value>[\d.]+) ?(?P<unit>us|ms|s)\b"
+            ))),
+    ]
+}
+
+/// Extracts the first sample any of the extractors finds in a line.
+pub(crate) fn extract_latency(
+        extractors: &[Box<dyn LatencyExtractor>], line: &YBLogLine) -> Option<LatencySample> {
+    extractors.iter().find_map(|extractor| extractor.extract(line))
+}
+
+/// Returns the value at `percentile` (0 to 100) of sorted values, by the nearest-rank method.
+fn percentile(sorted_values: &[f64], percentile: f64) -> f64 {
+    let rank = (percentile / 100.0 * sorted_values.len() as f64).ceil() as usize;
+    sorted_values[rank.clamp(1, sorted_values.len()) - 1]
+}
+
+fn tablet_column(tablet_id: Option<Uuid>) -> String {
+    tablet_id.map_or(String::from("-"), |id| id.to_simple().to_string())
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct LatencyKey {
+    bucket: Option<NaiveDateTime>,
+    node: Option<String>,
+    tablet: Option<String>,
+    operation: String,
+}
+
+/// One of the slowest samples, kept with where it came from.
+struct SlowSample {
+    millis: f64,
+    timestamp: NaiveDateTime,
+    operation: String,
+    node: Option<String>,
+    tablet_id: Option<Uuid>,
+}
+
+/// Prints, per operation (and per node with `per_node`, per tablet with `per_tablet`, and per time
+/// bucket with `bucket`), the number of samples and latency percentiles, followed by the `top`
+/// slowest samples. Samples from lines without a tablet go to a "-" tablet row.
+pub(crate) fn print_latency_report<I: Iterator<Item = YBLogLine>>(
+        lines: I,
+        top: usize,
+        per_node: bool,
+        per_tablet: bool,
+        bucket: Option<Duration>) {
+    let extractors = default_extractors();
+    let mut samples: BTreeMap<LatencyKey, Vec<f64>> = BTreeMap::new();
+    let mut slowest: Vec<SlowSample> = Vec::new();
+    let mut num_samples: usize = 0;
+    for line in lines {
+        let sample = match extract_latency(&extractors, &line) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        num_samples += 1;
+        let node = line.source.node.clone();
+        let key = LatencyKey {
+            bucket: bucket.map(|bucket| bucket_start(line.timestamp, bucket)),
+            node: if per_node { Some(node.clone().unwrap_or_default()) } else { None },
+            tablet: if per_tablet { Some(tablet_column(line.tablet_id)) } else { None },
+            operation: sample.operation.clone(),
+        };
+        samples.entry(key).or_default().push(sample.millis);
+
+        slowest.push(SlowSample {
+            millis: sample.millis,
+            timestamp: line.timestamp,
+            operation: sample.operation,
+            node,
+            tablet_id: line.tablet_id,
+        });
+        if slowest.len() >= 2 * top.max(1) {
+            slowest.sort_by(|a, b| b.millis.partial_cmp(&a.millis).unwrap());
+            slowest.truncate(top);
+        }
+    }
+    slowest.sort_by(|a, b| b.millis.partial_cmp(&a.millis).unwrap()
+        .then_with(|| a.timestamp.cmp(&b.timestamp)));
+    slowest.truncate(top);
+
+    let operations: BTreeSet<&str> = samples.keys().map(|key| key.operation.as_str()).collect();
+    println!("Found {} latency samples of {} operations", num_samples, operations.len());
+    println!("{}{}{}{:>8}  {:>10}  {:>10}  {:>10}  {:>10}  {:>12}  operation",
+             if bucket.is_some() { format!("{:<21}", "bucket") } else { String::new() },
+             if per_node { format!("{:<24}", "node") } else { String::new() },
+             if per_tablet { format!("{:<34}", "tablet") } else { String::new() },
+             "count", "p50 ms", "p90 ms", "p99 ms", "max ms", "total s");
+    for (key, values) in samples.iter_mut() {
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        println!("{}{}{}{:>8}  {:>10.1}  {:>10.1}  {:>10.1}  {:>10.1}  {:>12.3}  {}",
+                 key.bucket.map_or(String::new(), |ts| format!("{:<21}", ts.to_string())),
+                 key.node.as_ref().map_or(String::new(), |node| format!("{:<24}", node)),
+                 key.tablet.as_ref().map_or(String::new(), |tablet| format!("{:<34}", tablet)),
+                 values.len(),
+                 percentile(values, 50.0),
+                 percentile(values, 90.0),
+                 percentile(values, 99.0),
+                 values[values.len() - 1],
+                 values.iter().sum::<f64>() / 1000.0,
+                 key.operation);
+    }
+
+    println!();
+    println!("Slowest {} samples", slowest.len());
+    println!("{:>10}  {:<26}  {:<24}  {:<32}  operation", "ms", "timestamp", "node", "tablet");
+    for sample in &slowest {
+        println!("{:>10.1}  {:<26}  {:<24}  {:<32}  {}",
+                 sample.millis,
+                 sample.timestamp.to_string(),
+                 sample.node.as_deref().unwrap_or("-"),
+                 tablet_column(sample.tablet_id),
+                 sample.operation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_lines::{glog_line, source};
+
+    fn extract(text: &str) -> Option<(String, f64)> {
+        let source = source(0, "node-1", "yb-tserver.INFO");
+        extract_latency(&default_extractors(), &glog_line(&source, text))
+            .map(|sample| (sample.operation, sample.millis))
+    }
+
+    #[test]
+    fn inbound_calls() {
+        assert_eq!(
+            extract("W0408 10:00:01.671530  2345 inbound_call.cc:118] \
+                     Call yb.tserver.TabletServerService.Write 10.150.0.39:41688 => \
+                     10.150.0.41:9100 (request call id 2451) took 1520ms \
+                     (client timeout 60000ms)."),
+            Some((String::from("rpc yb.tserver.TabletServerService.Write"), 1520.0)));
+        assert_eq!(
+            extract("W0408 10:00:01.671530  2345 yb_rpc.cc:439] \
+                     Call yb.consensus.ConsensusService.UpdateConsensus 10.150.0.41:56098 => \
+                     10.150.0.39:9100 (request call id 79) took 3012ms. Trace:"),
+            Some((String::from("rpc yb.consensus.ConsensusService.UpdateConsensus"), 3012.0)));
+        assert_eq!(
+            extract("W0408 10:00:01.671530  2345 inbound_call.cc:118] \
+                     Call yb.tserver.TabletServerService.Read from 10.1.2.3:45678 took 999ms"),
+            Some((String::from("rpc yb.tserver.TabletServerService.Read"), 999.0)));
+    }
+
+    #[test]
+    fn time_spent_and_long_operations() {
+        assert_eq!(
+            extract("W0408 10:00:05.822479  1047 tablet.cc:1264] \
+                     T 35bf992dc9e9c616612e7696a6cecc1b P 8d4b2f0a1c9e4e57b6f3a2d1c0e9f8a7: \
+                     Time spent Apply: real 0.791s\tuser 0.001s\tsys 0.000s"),
+            Some((String::from("time spent Apply"), 791.0)));
+        assert_eq!(
+            extract("W0408 10:00:05.822479  1047 long_operation_tracker.cc:114] \
+                     UpdateReplica running for 1.000s in thread 1234:"),
+            Some((String::from("long operation UpdateReplica"), 1000.0)));
+    }
+
+    #[test]
+    fn generic_took() {
+        assert_eq!(
+            extract("W0408 10:00:05.822479  1047 log.cc:512] Log append took 1200 ms"),
+            Some((String::from("Log append"), 1200.0)));
+        assert_eq!(
+            extract("I0408 10:00:05.822479  1047 db_impl.cc:3040] Flush took 350us"),
+            Some((String::from("Flush"), 0.35)));
+        assert_eq!(extract("I0408 10:00:05.822479  1047 raft_consensus.cc:738] \
+                            T 35bf992dc9e9c616612e7696a6cecc1b P 8d4b2f0a1c9e4e57b6f3a2d1c0e9f8a7: \
+                            Starting election for term 71"),
+                   None);
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(percentile(&values, 50.0), 5.0);
+        assert_eq!(percentile(&values, 90.0), 9.0);
+        assert_eq!(percentile(&values, 99.0), 10.0);
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&[42.0], 99.0), 42.0);
+    }
+}
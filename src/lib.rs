@@ -1,6 +1,8 @@
 use std::str::FromStr;
+use std::sync::Arc;
 use regex::Regex;
-use chrono::{NaiveDateTime, NaiveDate};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveDate, NaiveTime, Timelike, Utc};
+use uuid::Uuid;
 
 pub fn parse_regex(s: &str) -> Regex {
     Regex::new(s).unwrap()
@@ -14,33 +16,139 @@ pub fn parse_capture<T: FromStr>(capture: Option<regex::Match>) -> T {
     }
 }
 
-pub fn parse_filter_timestamp(s_raw: &str) -> Result<NaiveDateTime, String> {
+/// What relative and year-less filter timestamps are resolved against.
+#[derive(Clone, Copy, Debug)]
+pub struct FilterTimestampBase {
+    /// The current time in UTC, like filter timestamps given with a time zone.
+    pub now: NaiveDateTime,
+    /// The year of glog-style timestamps, which have none.
+    pub default_year: i32,
+}
+
+impl FilterTimestampBase {
+    pub fn current(default_year: i32) -> FilterTimestampBase {
+        FilterTimestampBase { now: Utc::now().naive_utc(), default_year }
+    }
+}
+
+/// Parses a timestamp given on the command line, in any of these formats:
+/// - YYYY-MM-DD, or YYYY-MM-DD HH:MM:SS (or with a T instead of the space) with optional
+///   fractional seconds
+/// - glog-style MMDD HH:MM:SS[.ffffff], in the default year
+/// - either of the above followed by a time zone, an offset such as Z, +02, +0530 or -07:00 or a
+///   name that `utc_offset` knows such as UTC or PST, in which case the timestamp is converted to
+///   UTC
+/// - now, or a duration before or after it such as -2h, now-30m or now+1d
+pub fn parse_filter_timestamp(
+        s_raw: &str, base: &FilterTimestampBase) -> Result<NaiveDateTime, String> {
     let s = s_raw.trim();
-    let ymd_regex_str = r"^(\d{4})-(\d{2})-(\d{2})";
-    let ymd_regex = parse_regex((String::from(ymd_regex_str) + "$").as_str());
-    if let Some(captures) = ymd_regex.captures(s) {
-        return Ok(
-            NaiveDate::from_ymd(
-                parse_capture(captures.get(1)),
-                parse_capture(captures.get(2)),
-                parse_capture(captures.get(3))
-            ).and_hms(0, 0, 0));
+    if s.eq_ignore_ascii_case("now") {
+        return Ok(base.now);
     }
-    let ymdhms_regex = parse_regex(
-        (String::from(ymd_regex_str) + r"[ tT]*(\d{2}):(\d{2}):(\d{2})$").as_str());
-    if let Some(captures) = ymdhms_regex.captures(s) {
-        return Ok(
-            NaiveDate::from_ymd(
-                parse_capture(captures.get(1)),
-                parse_capture(captures.get(2)),
-                parse_capture(captures.get(3))
-            ).and_hms(
-                parse_capture(captures.get(4)),
-                parse_capture(captures.get(5)),
-                parse_capture(captures.get(6))));
+    let relative_regex = parse_regex(r"^(?i:now)?\s*([+-])\s*(\d.*)$");
+    if let Some(captures) = relative_regex.captures(s) {
+        let duration = parse_duration(captures.get(2).unwrap().as_str())?;
+        return Ok(if &captures[1] == "-" { base.now - duration } else { base.now + duration });
+    }
+
+    let absolute_regex = parse_regex(concat!(
+        r"^(?:(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})|",
+        r"(?P<glog_month>\d{2})(?P<glog_day>\d{2}) )",
+        r"(?:[ tT]*(?P<time>\d{2}:\d{2}:\d{2}(?:\.\d+)?)",
+        r"\s*(?P<time_zone>[A-Za-z]+|[+-]\d{2}(?::?\d{2})?)?)?$"
+    ));
+    let error = || format!(
+        "Could not parse timestamp '{}': expected YYYY-MM-DD, YYYY-MM-DD[ tT]HH:MM:SS[.ffffff] or \
+         MMDD HH:MM:SS[.ffffff], optionally followed by a time zone such as Z, +02, -07:00 or PST, \
+         or a time relative to now such as -2h or now-30m", s);
+    let captures = absolute_regex.captures(s).ok_or_else(error)?;
+    let date = match captures.name("year") {
+        Some(year) => NaiveDate::from_ymd_opt(
+            parse_capture(Some(year)),
+            parse_capture(captures.name("month")),
+            parse_capture(captures.name("day"))),
+        None => NaiveDate::from_ymd_opt(
+            base.default_year,
+            parse_capture(captures.name("glog_month")),
+            parse_capture(captures.name("glog_day"))),
+    }.ok_or_else(error)?;
+    let time = match captures.name("time") {
+        Some(time) => NaiveTime::parse_from_str(time.as_str(), "%H:%M:%S%.f")
+            .map_err(|_| error())?,
+        None => NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+    };
+    let offset = match captures.name("time_zone") {
+        Some(time_zone) => utc_offset(time_zone.as_str()).ok_or_else(error)?,
+        None => Duration::zero(),
+    };
+    Ok(date.and_time(time) - offset)
+}
+
+/// Parses a size such as 4096, 512K, 64M or 4G (binary units, an optional trailing B is allowed).
+pub fn parse_size(s_raw: &str) -> Result<u64, String> {
+    let s = s_raw.trim();
+    let size_regex = parse_regex(r"^(\d+)\s*([kKmMgGtT]?)[iI]?[bB]?$");
+    if let Some(captures) = size_regex.captures(s) {
+        let number: u64 = parse_capture(captures.get(1));
+        let shift = match captures.get(2).unwrap().as_str().to_ascii_uppercase().as_str() {
+            "K" => 10,
+            "M" => 20,
+            "G" => 30,
+            "T" => 40,
+            _ => 0,
+        };
+        if let Some(size) = number.checked_mul(1 << shift) {
+            return Ok(size);
+        }
     }
-    Err(format!(
-        "Could not parse timestamp '{}': expected YYYY-MM-DD or YYYY-MM-DD[ tT]HH:MM:SS format", s))
+    Err(format!("Could not parse size '{}': expected a number of bytes with an optional K, M, G \
+                 or T suffix", s))
+}
+
+/// Parses a duration such as 500ms, 30s, 5m, 1h or 1d. A number without a unit is in seconds.
+pub fn parse_duration(s_raw: &str) -> Result<Duration, String> {
+    let s = s_raw.trim();
+    let duration_regex = parse_regex(r"^(\d+)\s*(ms|s|m|h|d)?$");
+    if let Some(captures) = duration_regex.captures(s) {
+        let number: i64 = parse_capture(captures.get(1));
+        let duration = match captures.get(2).map(|unit| unit.as_str()) {
+            Some("ms") => Duration::milliseconds(number),
+            Some("m") => Duration::minutes(number),
+            Some("h") => Duration::hours(number),
+            Some("d") => Duration::days(number),
+            _ => Duration::seconds(number),
+        };
+        if duration > Duration::zero() {
+            return Ok(duration);
+        }
+    }
+    Err(format!("Could not parse duration '{}': expected a positive number followed by ms, s, m, \
+                 h or d", s))
+}
+
+pub fn unix_epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+}
+
+/// Returns the start of the `bucket`-sized time interval containing `timestamp`. Buckets are
+/// aligned to the Unix epoch, so e.g. one-minute buckets start at whole minutes.
+pub fn bucket_start(timestamp: NaiveDateTime, bucket: Duration) -> NaiveDateTime {
+    let bucket_ms = bucket.num_milliseconds();
+    let timestamp_ms = (timestamp - unix_epoch()).num_milliseconds();
+    unix_epoch() + Duration::milliseconds(timestamp_ms - timestamp_ms.rem_euclid(bucket_ms))
+}
+
+/// Splits a glog file name such as `yb-tserver.host.user.log.INFO.20210408-100000.1234` into the
+/// program name and, if present, the process id. Symlinks such as `yb-tserver.INFO` only give the
+/// program name.
+pub fn parse_glog_file_name(file_name: &str) -> (String, Option<u32>) {
+    let program = file_name.split('.').next().unwrap_or(file_name);
+    let pid = if file_name.contains(".log.") {
+        file_name.rsplit('.').next().and_then(|last| last.parse::<u32>().ok())
+    } else {
+        None
+    };
+    (String::from(program), pid)
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -54,6 +162,13 @@ pub struct RegexHolder {
     pub running_on_machine_re: Regex,
     pub application_fingerprint_re: Regex,
     pub application_fingerprint_details_re: Regex,
+    pub running_duration_re: Regex,
+}
+
+impl Default for RegexHolder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RegexHolder {
@@ -124,8 +239,667 @@ impl RegexHolder {
                 r"built at (.*)"
                 )
             ),
+            running_duration_re: parse_regex(
+                r"^Running duration \(h:mm:ss\): (\d+):(\d{2}):(\d{2})$"
+            ),
             // version 2.4.0.0 build 60 revision 4a56a6497b3bbc559f995d30f20f3859debce629 build_type
             // RELEASE built at 21 Jan 2021 02:12:34 UTC
+
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// TimestampWithoutYear
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialOrd, PartialEq, Clone, Copy)]
+pub struct TimestampWithoutYear {
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub microsecond: u32,
+}
+
+impl TimestampWithoutYear {
+    pub fn with_year(&self, year: i32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, u32::from(self.month), u32::from(self.day)).and_hms_micro(
+            u32::from(self.hour), u32::from(self.minute), u32::from(self.second),
+            self.microsecond)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// LogLineRef -- a parsed glog line borrowing its text fields from the line buffer
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogLineRef<'a> {
+    pub log_level: char,
+    pub timestamp: TimestampWithoutYear,
+    /// The year of the timestamp, for formats that log it. Glog lines take the year from the
+    /// file preamble or --default-year instead.
+    pub year: Option<i32>,
+    pub thread_id: i64,
+    pub file_name: &'a str,
+    pub line_number: i32,
+    pub message: &'a str,
+}
+
+/// Reads exactly `n` ASCII digits starting at `*pos` and advances `*pos` past them.
+fn read_fixed_digits(bytes: &[u8], pos: &mut usize, n: usize) -> Option<u32> {
+    let digits = bytes.get(*pos..*pos + n)?;
+    let mut value: u32 = 0;
+    for &b in digits {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + u32::from(b - b'0');
+    }
+    *pos += n;
+    Some(value)
+}
+
+/// Reads one or more ASCII digits starting at `*pos` as a number, advancing `*pos` past them.
+fn read_number<T: FromStr>(line: &str, pos: &mut usize) -> Option<T> {
+    let bytes = line.as_bytes();
+    let start = *pos;
+    while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+    if *pos == start {
+        return None;
+    }
+    line[start..*pos].parse::<T>().ok()
+}
+
+/// Skips one or more whitespace bytes. Fails if there is no whitespace at `*pos`.
+fn skip_whitespace(bytes: &[u8], pos: &mut usize) -> Option<()> {
+    let start = *pos;
+    while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    if *pos == start { None } else { Some(()) }
+}
+
+fn expect_byte(bytes: &[u8], pos: &mut usize, expected: u8) -> Option<()> {
+    if bytes.get(*pos) == Some(&expected) {
+        *pos += 1;
+        Some(())
+    } else {
+        None
+    }
+}
+
+fn is_file_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+/// Matches the `[0-9a-zA-Z_-]+[.][0-9a-zA-Z_-]+` file name part of the glog header.
+fn read_file_name<'a>(line: &'a str, pos: &mut usize) -> Option<&'a str> {
+    let bytes = line.as_bytes();
+    let start = *pos;
+    let mut i = start;
+    while i < bytes.len() && is_file_name_byte(bytes[i]) {
+        i += 1;
+    }
+    if i == start || bytes.get(i) != Some(&b'.') {
+        return None;
+    }
+    i += 1;
+    let extension_start = i;
+    while i < bytes.len() && is_file_name_byte(bytes[i]) {
+        i += 1;
+    }
+    if i == extension_start {
+        return None;
+    }
+    *pos = i;
+    Some(&line[start..i])
+}
+
+impl<'a> LogLineRef<'a> {
+    /// Parses a glog line by walking its fixed-width header byte by byte. Accepts the same lines
+    /// as `RegexHolder::yb_log_line_re`, except for header separators that are non-ASCII
+    /// whitespace, which are left to the regex fallback in `parse`.
+    pub fn parse_fast(line: &'a str) -> Option<LogLineRef<'a>> {
+        let bytes = line.as_bytes();
+        let log_level = match bytes.first()? {
+            b @ (b'I' | b'W' | b'E' | b'F') => char::from(*b),
+            _ => return None,
+        };
+        let mut pos = 1;
+        let month = read_fixed_digits(bytes, &mut pos, 2)?;
+        let day = read_fixed_digits(bytes, &mut pos, 2)?;
+        skip_whitespace(bytes, &mut pos)?;
+        let hour = read_fixed_digits(bytes, &mut pos, 2)?;
+        expect_byte(bytes, &mut pos, b':')?;
+        let minute = read_fixed_digits(bytes, &mut pos, 2)?;
+        expect_byte(bytes, &mut pos, b':')?;
+        let second = read_fixed_digits(bytes, &mut pos, 2)?;
+        expect_byte(bytes, &mut pos, b'.')?;
+        let microsecond = read_fixed_digits(bytes, &mut pos, 6)?;
+        skip_whitespace(bytes, &mut pos)?;
+        let thread_id: i64 = read_number(line, &mut pos)?;
+        skip_whitespace(bytes, &mut pos)?;
+        let file_name = read_file_name(line, &mut pos)?;
+        expect_byte(bytes, &mut pos, b':')?;
+        let line_number: i32 = read_number(line, &mut pos)?;
+        expect_byte(bytes, &mut pos, b']')?;
+        expect_byte(bytes, &mut pos, b' ')?;
+        Some(LogLineRef {
+            log_level,
+            timestamp: TimestampWithoutYear {
+                month: month as u8,
+                day: day as u8,
+                hour: hour as u8,
+                minute: minute as u8,
+                second: second as u8,
+                microsecond,
+            },
+            year: None,
+            thread_id,
+            file_name,
+            line_number,
+            message: &line[pos..],
+        })
+    }
+
+    /// Parses a glog line using `RegexHolder::yb_log_line_re`.
+    pub fn parse_with_regex(line: &'a str, regexes: &RegexHolder) -> Option<LogLineRef<'a>> {
+        let captures = regexes.yb_log_line_re.captures(line)?;
+        Some(LogLineRef {
+            log_level: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_LOG_LEVEL)),
+            timestamp: TimestampWithoutYear {
+                month: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MONTH)),
+                day: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_DAY)),
+                hour: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_HOUR)),
+                minute: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MINUTE)),
+                second: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_SECOND)),
+                microsecond: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MICROSECOND)),
+            },
+            year: None,
+            thread_id: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_THREAD_ID)),
+            file_name: captures.get(RegexHolder::CAPTURE_INDEX_FILE_NAME).unwrap().as_str(),
+            line_number: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_LINE_NUMBER)),
+            message: captures.get(RegexHolder::CAPTURE_INDEX_MESSAGE).unwrap().as_str(),
+        })
+    }
+
+    /// Tries the hand-written parser first and falls back to the regex for anything it rejects.
+    pub fn parse(line: &'a str, regexes: &RegexHolder) -> Option<LogLineRef<'a>> {
+        LogLineRef::parse_fast(line).or_else(|| LogLineRef::parse_with_regex(line, regexes))
+    }
+
+    /// Finds the first `T <32 hex digits>` tablet reference in the message, mirroring
+    /// `RegexHolder::tablet_id_re` without running a regex over the line.
+    pub fn tablet_id(&self) -> Option<Uuid> {
+        parse_tablet_id_fast(self.message)
+    }
+}
+
+pub fn parse_tablet_id_fast(s: &str) -> Option<Uuid> {
+    const TABLET_ID_LEN: usize = 32;
+    let bytes = s.as_bytes();
+    let mut start = 0;
+    while let Some(offset) = s[start..].find("T ") {
+        let id_start = start + offset + 2;
+        let id_end = id_start + TABLET_ID_LEN;
+        start = start + offset + 1;
+        let candidate = bytes.get(id_start..id_end)?;
+        if !candidate.iter().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            continue;
+        }
+        if let Some(&next) = bytes.get(id_end) {
+            if next.is_ascii_alphanumeric() || next == b'_' {
+                continue;
+            }
+        }
+        return Uuid::parse_str(&s[id_start..id_end]).ok();
+    }
+    None
+}
+
+// ------------------------------------------------------------------------------------------------
+// LogFilePreamble -- metadata from the first lines of a log file
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Default, Debug, Clone)]
+pub struct LogFilePreamble {
+    pub created_at: Option<NaiveDateTime>,
+    pub running_on_machine: Option<String>,
+    pub application_fingerprint: Option<String>,
+    pub version: Option<String>,
+    pub build_number: Option<u64>,
+    pub revision: Option<String>,
+    pub build_type: Option<String>,
+    pub built_at: Option<String>,
+    pub running_duration: Option<Duration>,
+}
+
+impl LogFilePreamble {
+    /// When the process that wrote this file started. Every file that glog rotates to gets a
+    /// preamble with the running duration so far, so all files of one process agree on this, up
+    /// to rounding to whole seconds.
+    pub fn process_started_at(&self) -> Option<NaiveDateTime> {
+        Some(self.created_at? - self.running_duration?)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// LogFormat -- how the lines of a log file are laid out
+// ------------------------------------------------------------------------------------------------
+
+/// A log line format. The reader picks a format per file with `detect`, then hands every line of
+/// the file to it, so supporting another format only takes another implementation of this trait
+/// added to `builtin_log_formats`.
+pub trait LogFormat: Send + Sync {
+    /// A short name for the format, e.g. "glog".
+    fn name(&self) -> &'static str;
+
+    /// Whether one of the first lines of a file shows that the file is in this format.
+    fn detect(&self, line: &str) -> bool;
+
+    /// Picks up metadata from one of the first lines of a file.
+    fn parse_preamble_line(&self, _line: &str, _preamble: &mut LogFilePreamble) {}
+
+    /// The program that wrote a file and its process id, as far as the file name tells.
+    fn process_from_file_name(&self, file_name: &str) -> (String, Option<u32>);
+
+    /// Parses the first line of an entry.
+    fn parse_line<'a>(&self, line: &'a str) -> Option<LogLineRef<'a>>;
+
+    /// Like `parse_line`, but may skip slow fallbacks and miss some lines. Good enough to tell
+    /// lines that are filtered out anyway apart from continuation lines.
+    fn parse_line_fast<'a>(&self, line: &'a str) -> Option<LogLineRef<'a>> {
+        self.parse_line(line)
+    }
+
+    /// Whether a line that `parse_line` rejects belongs to the entry before it, like a stack
+    /// trace frame, rather than being a line that could not be parsed.
+    fn is_continuation(&self, _line: &str) -> bool {
+        true
+    }
+}
+
+/// The formats the reader knows, in the order they are tried when detecting the format of a file.
+pub fn builtin_log_formats() -> Vec<Arc<dyn LogFormat>> {
+    vec![Arc::new(GlogFormat::new()), Arc::new(PostgresFormat::new())]
+}
+
+/// Returns the first of `formats` that recognizes `line`.
+pub fn detect_log_format(formats: &[Arc<dyn LogFormat>], line: &str) -> Option<Arc<dyn LogFormat>> {
+    formats.iter().find(|format| format.detect(line)).cloned()
+}
+
+/// YugabyteDB master and tserver logs, written by glog.
+pub struct GlogFormat {
+    regexes: RegexHolder,
+}
+
+impl GlogFormat {
+    pub fn new() -> GlogFormat {
+        GlogFormat { regexes: RegexHolder::new() }
+    }
+}
+
+impl Default for GlogFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFormat for GlogFormat {
+    fn name(&self) -> &'static str {
+        "glog"
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        self.regexes.log_file_created_at_re.is_match(line) || self.parse_line(line).is_some()
+    }
+
+    fn parse_preamble_line(&self, line: &str, preamble: &mut LogFilePreamble) {
+        let regexes = &self.regexes;
+        if let Some(captures) = regexes.log_file_created_at_re.captures(line) {
+            preamble.created_at = Some(NaiveDate::from_ymd(
+                parse_capture(captures.get(1)),
+                parse_capture(captures.get(2)),
+                parse_capture(captures.get(3))
+            ).and_hms(
+                parse_capture(captures.get(4)),
+                parse_capture(captures.get(5)),
+                parse_capture(captures.get(6))
+            ));
+        }
+        if let Some(captures) = regexes.running_on_machine_re.captures(line) {
+            preamble.running_on_machine = Some(String::from(captures.get(1).unwrap().as_str()));
+        }
+        if let Some(captures) = regexes.application_fingerprint_re.captures(line) {
+            let fingerprint = captures.get(1).unwrap().as_str();
+            preamble.application_fingerprint = Some(String::from(fingerprint));
+            let details_re = &regexes.application_fingerprint_details_re;
+            if let Some(details) = details_re.captures(fingerprint) {
+                preamble.version = Some(String::from(details.get(1).unwrap().as_str()));
+                preamble.build_number = Some(parse_capture(details.get(2)));
+                preamble.revision = Some(String::from(details.get(3).unwrap().as_str()));
+                preamble.build_type = Some(String::from(details.get(4).unwrap().as_str()));
+                preamble.built_at = Some(String::from(details.get(5).unwrap().as_str()));
+            }
+        }
+        if let Some(captures) = regexes.running_duration_re.captures(line) {
+            preamble.running_duration = Some(
+                Duration::hours(parse_capture(captures.get(1))) +
+                Duration::minutes(parse_capture(captures.get(2))) +
+                Duration::seconds(parse_capture(captures.get(3))));
+        }
+    }
+
+    fn process_from_file_name(&self, file_name: &str) -> (String, Option<u32>) {
+        parse_glog_file_name(file_name)
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Option<LogLineRef<'a>> {
+        LogLineRef::parse(line, &self.regexes)
+    }
+
+    fn parse_line_fast<'a>(&self, line: &'a str) -> Option<LogLineRef<'a>> {
+        LogLineRef::parse_fast(line)
+    }
+}
+
+/// YSQL logs written by the PostgreSQL backends.
+pub struct PostgresFormat {
+    line_re: Regex,
+}
+
+impl PostgresFormat {
+    pub fn new() -> PostgresFormat {
+        PostgresFormat {
+            // With the %m [%p] log_line_prefix YugabyteDB uses.
+            // Example: 2021-04-08 10:34:43.355 UTC [12345] LOG:  database system is ready
+            line_re: parse_regex(
+                concat!(
+                r"^",
+                r"(\d{4})-(\d{2})-(\d{2}) ", // Capture groups 1-3: year, month, day
+                r"(\d{2}):(\d{2}):(\d{2})", // Capture groups 4-6: hour, minute, second
+                r"(?:[.](\d{1,6}))? ", // Capture group 7: fraction of a second
+                r"([A-Za-z]+|[+-]\d{2}(?::?\d{2})?) ", // Capture group 8: time zone
+                r"\[(\d+)\] ", // Capture group 9: pid
+                r"(([A-Z]+)[0-9]?:  ?.*)", // Capture groups 10, 11: message, severity
+                ),
+            ),
+        }
+    }
+}
+
+impl Default for PostgresFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The offset from UTC of a time zone such as "+05:30", "+0530" or "-08", as in PostgreSQL log
+/// line prefixes, or of a common time zone abbreviation such as "UTC", "PST" or "CEST". Returns
+/// `None` for other names, including ambiguous ones such as "IST". "CST" is taken as US Central.
+fn utc_offset(time_zone: &str) -> Option<Duration> {
+    let sign = match time_zone.as_bytes().first() {
+        Some(b'+') => 1,
+        Some(b'-') => -1,
+        _ => {
+            let hours = match time_zone.to_ascii_uppercase().as_str() {
+                "Z" | "UTC" | "GMT" | "WET" => 0,
+                "BST" | "CET" | "WEST" => 1,
+                "CEST" | "EET" => 2,
+                "EEST" | "MSK" => 3,
+                "HKT" | "SGT" => 8,
+                "JST" | "KST" => 9,
+                "AEST" => 10,
+                "AEDT" => 11,
+                "NZST" => 12,
+                "NZDT" => 13,
+                "EDT" => -4,
+                "EST" | "CDT" => -5,
+                "CST" | "MDT" => -6,
+                "MST" | "PDT" => -7,
+                "PST" => -8,
+                _ => return None,
+            };
+            return Some(Duration::hours(hours));
+        }
+    };
+    let digits: String = time_zone[1..].chars().filter(char::is_ascii_digit).collect();
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+    Some(Duration::minutes(sign * (hours * 60 + minutes)))
+}
+
+impl LogFormat for PostgresFormat {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn detect(&self, line: &str) -> bool {
+        self.line_re.is_match(line)
+    }
+
+    /// Each YSQL backend is its own process, so the pid is taken per line instead.
+    fn process_from_file_name(&self, _file_name: &str) -> (String, Option<u32>) {
+        (String::from("postgres"), None)
+    }
+
+    /// The pid goes into `thread_id`, and the message keeps its severity prefix, e.g.
+    /// "LOG:  ...", since severities such as STATEMENT or DETAIL have no glog log level of their
+    /// own. Timestamps are converted to UTC. Lines in a time zone `utc_offset` does not know are
+    /// not parsed, so they are counted as unparsed rather than placed at the wrong time.
+    fn parse_line<'a>(&self, line: &'a str) -> Option<LogLineRef<'a>> {
+        let captures = self.line_re.captures(line)?;
+        // One to six digits, e.g. milliseconds with log_line_prefix's %m.
+        let microsecond: u32 = match captures.get(7) {
+            Some(fraction) => {
+                let digits = fraction.as_str();
+                digits.parse::<u32>().ok()? * 10u32.pow(6 - digits.len() as u32)
+            }
+            None => 0,
+        };
+        let local = NaiveDate::from_ymd_opt(
+            parse_capture(captures.get(1)),
+            parse_capture(captures.get(2)),
+            parse_capture(captures.get(3))
+        )?.and_hms_micro_opt(
+            parse_capture(captures.get(4)),
+            parse_capture(captures.get(5)),
+            parse_capture(captures.get(6)),
+            microsecond)?;
+        let timestamp = local - utc_offset(captures.get(8).unwrap().as_str())?;
+        // A FATAL error only ends one session; PANIC is what brings the server down.
+        let log_level = match captures.get(11).unwrap().as_str() {
+            "WARNING" => 'W',
+            "ERROR" | "FATAL" => 'E',
+            "PANIC" => 'F',
+            _ => 'I',
+        };
+        Some(LogLineRef {
+            log_level,
+            timestamp: TimestampWithoutYear {
+                month: timestamp.month() as u8,
+                day: timestamp.day() as u8,
+                hour: timestamp.hour() as u8,
+                minute: timestamp.minute() as u8,
+                second: timestamp.second() as u8,
+                microsecond: timestamp.nanosecond() / 1000,
+            },
+            year: Some(timestamp.year()),
+            thread_id: parse_capture(captures.get(9)),
+            file_name: "postgres",
+            line_number: 0,
+            message: captures.get(10).unwrap().as_str(),
+        })
+    }
+
+    /// A line with the shape of a log line prefix that `parse_line` still rejects, e.g. for its
+    /// time zone, starts an entry of its own rather than continuing the one before it.
+    fn is_continuation(&self, line: &str) -> bool {
+        !self.line_re.is_match(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both glog parsers must agree on every line, accepting or rejecting it alike.
+    fn assert_parsers_agree(line: &str) -> Option<LogLineRef<'_>> {
+        let regexes = RegexHolder::new();
+        let fast = LogLineRef::parse_fast(line);
+        assert_eq!(fast, LogLineRef::parse_with_regex(line, &regexes), "line {:?}", line);
+        fast
+    }
+
+    #[test]
+    fn parse_fast_matches_regex_on_valid_lines() {
+        let parsed = assert_parsers_agree(
+            "W0408 10:34:43.355123  1234 raft_consensus.cc:875] T \
+             0123456789abcdef0123456789abcdef P 3f1a: Leader election won").unwrap();
+        assert_eq!(parsed.log_level, 'W');
+        assert_eq!(parsed.timestamp, TimestampWithoutYear {
+            month: 4, day: 8, hour: 10, minute: 34, second: 43, microsecond: 355123 });
+        assert_eq!(parsed.thread_id, 1234);
+        assert_eq!(parsed.file_name, "raft_consensus.cc");
+        assert_eq!(parsed.line_number, 875);
+        assert!(parsed.message.ends_with("Leader election won"));
+        assert_eq!(parsed.tablet_id(),
+                   Uuid::parse_str("0123456789abcdef0123456789abcdef").ok());
+
+        for line in &[
+            "I0408 10:34:43.355123 1 a.b:1] ",
+            "E1231 23:59:59.999999\t\t42 yb-master_main.cc:100] tabs as separators",
+            "F0101 00:00:00.000000 7 file_name-1.cc:2147483647] ] nested ] brackets",
+        ] {
+            assert!(assert_parsers_agree(line).is_some(), "line {:?}", line);
+        }
+    }
+
+    #[test]
+    fn parse_fast_matches_regex_on_short_and_malformed_headers() {
+        for line in &[
+            "",
+            "I",
+            "I0408",
+            "I0408 10:34",
+            "I0408 10:34:43.355123",
+            "I0408 10:34:43.355123  1234 raft_consensus.cc:875]",
+            "I0408 10:34:43.35512  1234 raft_consensus.cc:875] five-digit microseconds",
+            "X0408 10:34:43.355123  1234 raft_consensus.cc:875] unknown level",
+            "I0408 10:34:43.355123  abc raft_consensus.cc:875] thread id not a number",
+            "I0408 10:34:43.355123  1234 raft_consensus:875] no file extension",
+            "I0408 10:34:43.355123  1234 raft_consensus.cc:] no line number",
+            "I0408 10:34:43.355123  1234 raft_consensus.cc:875]no space after bracket",
+            "    @     0x7f1234567890  yb::Foo()",
+            "2021-04-08 10:34:43.355 UTC [1234] LOG:  a postgres line",
+        ] {
+            assert!(assert_parsers_agree(line).is_none(), "line {:?}", line);
+        }
+    }
+
+    #[test]
+    fn tablet_id_requires_a_whole_hex_id() {
+        let parse = |message: &str| parse_tablet_id_fast(message);
+        assert_eq!(parse("T 0123456789abcdef0123456789abcdefX"), None);
+        assert_eq!(parse("T 0123456789ABCDEF0123456789abcdef"), None);
+        assert_eq!(parse("T 0123"), None);
+        assert_eq!(parse("AT x T 0123456789abcdef0123456789abcdef: ok"),
+                   Uuid::parse_str("0123456789abcdef0123456789abcdef").ok());
+    }
+
+    #[test]
+    fn postgres_timestamps_are_converted_to_utc() {
+        let format = PostgresFormat::new();
+        let hour_of = |time_zone: &str| {
+            let line = format!("2021-04-08 10:34:43.355 {} [1234] LOG:  ready", time_zone);
+            format.parse_line(&line).map(|parsed| (parsed.timestamp.day, parsed.timestamp.hour))
+        };
+        assert_eq!(hour_of("UTC"), Some((8, 10)));
+        assert_eq!(hour_of("+05:30"), Some((8, 5)));
+        assert_eq!(hour_of("-0800"), Some((8, 18)));
+        assert_eq!(hour_of("PST"), Some((8, 18)));
+        assert_eq!(hour_of("EDT"), Some((8, 14)));
+        assert_eq!(hour_of("CEST"), Some((8, 8)));
+        assert_eq!(hour_of("JST"), Some((8, 1)));
+        assert_eq!(hour_of("AEDT"), Some((7, 23)));
+        // Unknown and ambiguous names are not guessed at.
+        assert_eq!(hour_of("IST"), None);
+        assert_eq!(hour_of("XYZ"), None);
+    }
+
+    #[test]
+    fn postgres_fractions_of_a_second() {
+        let format = PostgresFormat::new();
+        let microsecond_of = |timestamp: &str| {
+            let line = format!("2021-04-08 {} UTC [1234] LOG:  ready", timestamp);
+            format.parse_line(&line).map(|parsed| parsed.timestamp.microsecond)
+        };
+        assert_eq!(microsecond_of("10:34:43"), Some(0));
+        assert_eq!(microsecond_of("10:34:43.3"), Some(300000));
+        assert_eq!(microsecond_of("10:34:43.355"), Some(355000));
+        assert_eq!(microsecond_of("10:34:43.035"), Some(35000));
+        assert_eq!(microsecond_of("10:34:43.000123"), Some(123));
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn filter_base() -> FilterTimestampBase {
+        FilterTimestampBase { now: at(2021, 4, 8, 12, 0, 0), default_year: 2020 }
+    }
+
+    #[test]
+    fn filter_timestamps_relative_to_now() {
+        let parse = |s: &str| parse_filter_timestamp(s, &filter_base());
+        assert_eq!(parse("now"), Ok(at(2021, 4, 8, 12, 0, 0)));
+        assert_eq!(parse(" NOW "), Ok(at(2021, 4, 8, 12, 0, 0)));
+        assert_eq!(parse("-2h"), Ok(at(2021, 4, 8, 10, 0, 0)));
+        assert_eq!(parse("now-30m"), Ok(at(2021, 4, 8, 11, 30, 0)));
+        assert_eq!(parse("now + 1d"), Ok(at(2021, 4, 9, 12, 0, 0)));
+        assert!(parse("now-soon").is_err());
+    }
+
+    #[test]
+    fn filter_timestamps_absolute_and_glog_style() {
+        let parse = |s: &str| parse_filter_timestamp(s, &filter_base());
+        assert_eq!(parse("2021-04-08"), Ok(at(2021, 4, 8, 0, 0, 0)));
+        assert_eq!(parse("2021-04-08 10:34:43"), Ok(at(2021, 4, 8, 10, 34, 43)));
+        assert_eq!(parse("2021-04-08T10:34:43.250"),
+                   Ok(at(2021, 4, 8, 10, 34, 43) + Duration::milliseconds(250)));
+        // Glog-style timestamps have no year, so the default year is used.
+        assert_eq!(parse("0408 10:34:43.355123"),
+                   Ok(at(2020, 4, 8, 10, 34, 43) + Duration::microseconds(355123)));
+    }
+
+    #[test]
+    fn filter_timestamps_with_time_zones_are_converted_to_utc() {
+        let parse = |s: &str| parse_filter_timestamp(s, &filter_base());
+        for s in &["2021-04-08 10:00:00Z", "2021-04-08 10:00:00 UTC", "2021-04-08T12:00:00+02",
+                   "2021-04-08 15:30:00 +0530", "2021-04-08 03:00:00-07:00",
+                   "2021-04-08 03:00:00 PDT"] {
+            assert_eq!(parse(s), Ok(at(2021, 4, 8, 10, 0, 0)), "{}", s);
+        }
+        assert_eq!(parse("0408 12:00:00 CEST"), Ok(at(2020, 4, 8, 10, 0, 0)));
+        assert!(parse("2021-04-08 10:00:00 IST").is_err());
+    }
+
+    #[test]
+    fn filter_timestamps_reject_invalid_dates_and_times() {
+        let parse = |s: &str| parse_filter_timestamp(s, &filter_base());
+        for s in &["2021-02-30", "2021-13-01 10:00:00", "1301 10:00:00", "2021-04-08 25:00:00",
+                   "2021-04-08 10:61:00", "2021-04-08 10:00", "04-08", "yesterday", ""] {
+            assert!(parse(s).is_err(), "{}", s);
         }
+        // February 29 only exists in leap years, including the default year of glog timestamps.
+        assert_eq!(parse("0229 00:00:00"), Ok(at(2020, 2, 29, 0, 0, 0)));
+        assert!(parse("2021-02-29").is_err());
     }
 }
@@ -0,0 +1,60 @@
+// ------------------------------------------------------------------------------------------------
+// CSV output of the lines report: one row per line, with a column per user-defined field.
+// ------------------------------------------------------------------------------------------------
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::rules::FieldValue;
+use crate::YBLogLine;
+
+/// Quotes a value if it contains a separator, quote or line break, doubling any quotes.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+pub(crate) struct LinesCsvWriter {
+    writer: BufWriter<File>,
+    field_names: Vec<String>,
+}
+
+impl LinesCsvWriter {
+    pub(crate) fn create(path: &str, field_names: Vec<String>) -> io::Result<LinesCsvWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut header: Vec<String> =
+            ["timestamp", "node", "level", "thread_id", "file", "line", "tablet_id", "message"]
+                .iter().map(|column| String::from(*column)).collect();
+        header.extend(field_names.iter().map(|name| csv_escape(name)));
+        writeln!(writer, "{}", header.join(","))?;
+        Ok(LinesCsvWriter { writer, field_names })
+    }
+
+    pub(crate) fn write(
+            &mut self, line: &YBLogLine, fields: &[(String, FieldValue)]) -> io::Result<()> {
+        let mut row: Vec<String> = vec![
+            line.timestamp.format("%Y-%m-%d %H:%M:%S%.6f").to_string(),
+            csv_escape(line.source.node.as_deref().unwrap_or("")),
+            line.log_level.to_string(),
+            line.thread_id.to_string(),
+            csv_escape(&line.file_name),
+            line.line_number.to_string(),
+            line.tablet_id.map_or(String::new(), |id| id.to_simple().to_string()),
+            csv_escape(&line.message),
+        ];
+        for name in &self.field_names {
+            row.push(match fields.iter().find(|(field, _)| field == name) {
+                Some((_, value)) => csv_escape(&value.to_string()),
+                None => String::new(),
+            });
+        }
+        writeln!(self.writer, "{}", row.join(","))
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,359 @@
+// ------------------------------------------------------------------------------------------------
+// Tserver liveness: heartbeat failures logged by tservers, tservers the master marked dead or
+// alive, and tserver restarts, as an availability timeline per tserver.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDateTime;
+use regex::Regex;
+
+use yblp::parse_regex;
+
+use crate::external_sort::MergedLines;
+use crate::processes::ProcessInstances;
+use crate::restarts::format_duration;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum LivenessEventKind {
+    /// The master considers the tserver unavailable.
+    MarkedDead,
+    /// The master registered the tserver or considers it alive again.
+    MarkedAlive,
+    /// The tserver failed to heartbeat to the master.
+    HeartbeatFailed,
+    /// The tserver reached the master again.
+    HeartbeatRecovered,
+    ProcessStarted,
+}
+
+struct LivenessEvent {
+    timestamp: NaiveDateTime,
+    kind: LivenessEventKind,
+    /// The node that logged the event.
+    logged_by: String,
+    message: String,
+    /// Further events of the same kind folded into this one, and when the last of them was.
+    repeats: usize,
+    last_at: NaiveDateTime,
+}
+
+/// The tserver an event is about: a node for events logged by the tserver itself, or a UUID for
+/// events logged by the master, resolved to a node once all lines have been seen.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum TserverRef {
+    Node(String),
+    Uuid(String),
+}
+
+struct LivenessRegexes {
+    marked_dead_re: Regex,
+    marked_alive_re: Regex,
+    heartbeat_failed_re: Regex,
+    heartbeat_recovered_re: Regex,
+    raft_peer_re: Regex,
+}
+
+impl LivenessRegexes {
+    fn new() -> LivenessRegexes {
+        LivenessRegexes {
+            // TS 3f1a... (node-1:9100) has not heartbeated in the last 60000 ms
+            // Marking tablet server 3f1a... as dead
+            marked_dead_re: parse_regex(concat!(
+                r"(?i)(?:\bTS|tablet server|tserver) (?P<ts>[0-9a-f]{32})\b.*?",
+                r"(?:as dead|is dead|marked dead|not heartbeated|hasn't heartbeated|unresponsive)"
+            )),
+            // Registered new tablet server { permanent_uuid: "3f1a..." ... }
+            // TS 3f1a... (node-1:9100) is alive again
+            marked_alive_re: parse_regex(concat!(
+                r"(?i)registered new (?:tablet server|tserver)\b.*?\b(?P<ts>[0-9a-f]{32})\b|",
+                r"(?:\bTS|tablet server|tserver) (?P<alive_ts>[0-9a-f]{32})\b.*?",
+                r"(?:as alive|is alive|alive again|re-?registered)"
+            )),
+            heartbeat_failed_re: parse_regex(r"(?i)(?:unable|failed) to heartbeat"),
+            heartbeat_recovered_re: parse_regex(concat!(
+                r"(?i)connected to a leader master|registering ts with master|",
+                r"heartbeat(?:er)? .*(?:succeeded|recovered|resumed)"
+            )),
+            raft_peer_re: parse_regex(r"^T [0-9a-f]{32} P (?P<peer>[0-9a-f]+)\b"),
+        }
+    }
+
+    /// Returns the tserver UUID a master line registers or marks alive.
+    fn marked_alive(&self, message: &str) -> Option<String> {
+        let captures = self.marked_alive_re.captures(message)?;
+        captures.name("ts").or_else(|| captures.name("alive_ts"))
+            .map(|ts| String::from(ts.as_str()))
+    }
+}
+
+/// A period in which a tserver was unavailable, as seen by the master or by the tserver itself.
+struct UnavailablePeriod {
+    tserver: String,
+    from: NaiveDateTime,
+    /// When the tserver came back, or None if it had not by the end of the logs.
+    to: Option<NaiveDateTime>,
+    seen_by: &'static str,
+}
+
+/// Periods from an event of `start_kind` to the next event of any of `end_kinds`.
+fn unavailable_periods(tserver: &str, events: &[LivenessEvent], start_kind: LivenessEventKind,
+                       end_kinds: &[LivenessEventKind], seen_by: &'static str)
+        -> Vec<UnavailablePeriod> {
+    let mut periods: Vec<UnavailablePeriod> = Vec::new();
+    let mut from: Option<NaiveDateTime> = None;
+    for event in events {
+        if event.kind == start_kind {
+            from = from.or(Some(event.timestamp));
+        } else if end_kinds.contains(&event.kind) {
+            if let Some(from) = from.take() {
+                periods.push(UnavailablePeriod {
+                    tserver: String::from(tserver),
+                    from,
+                    to: Some(event.timestamp),
+                    seen_by,
+                });
+            }
+        }
+    }
+    if let Some(from) = from {
+        periods.push(UnavailablePeriod { tserver: String::from(tserver), from, to: None, seen_by });
+    }
+    periods
+}
+
+/// The liveness events of each tserver in time order, keyed by its node and UUID as far as they
+/// are known.
+fn liveness_timelines(lines: MergedLines) -> BTreeMap<String, Vec<LivenessEvent>> {
+    let regexes = LivenessRegexes::new();
+    let mut instances = ProcessInstances::new(lines.sources());
+    let mut events: HashMap<TserverRef, Vec<LivenessEvent>> = HashMap::new();
+    // Tserver UUID to the node it runs on, from the "P <uuid>" prefix of its tablet log lines.
+    let mut tserver_nodes: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        instances.observe(&line);
+        let node = line.source.node.clone().unwrap_or_default();
+        let message = &line.message;
+        let is_master = line.source.process.program.contains("master");
+        let event = if is_master {
+            if let Some(captures) = regexes.marked_dead_re.captures(message) {
+                Some((TserverRef::Uuid(String::from(&captures["ts"])),
+                      LivenessEventKind::MarkedDead))
+            } else {
+                regexes.marked_alive(message)
+                    .map(|ts| (TserverRef::Uuid(ts), LivenessEventKind::MarkedAlive))
+            }
+        } else {
+            if let Some(captures) = regexes.raft_peer_re.captures(message) {
+                tserver_nodes.entry(String::from(&captures["peer"]))
+                    .or_insert_with(|| node.clone());
+            }
+            if regexes.heartbeat_failed_re.is_match(message) {
+                Some((TserverRef::Node(node.clone()), LivenessEventKind::HeartbeatFailed))
+            } else if regexes.heartbeat_recovered_re.is_match(message) {
+                Some((TserverRef::Node(node.clone()), LivenessEventKind::HeartbeatRecovered))
+            } else {
+                None
+            }
+        };
+        if let Some((tserver, kind)) = event {
+            let tserver_events = events.entry(tserver).or_default();
+            match tserver_events.last_mut() {
+                // Heartbeats are retried every second or so, so fold repeated events together.
+                Some(last) if last.kind == kind => {
+                    last.repeats += 1;
+                    last.last_at = line.timestamp;
+                }
+                _ => tserver_events.push(LivenessEvent {
+                    timestamp: line.timestamp,
+                    kind,
+                    logged_by: node,
+                    message: String::from(message.lines().next().unwrap_or("")),
+                    repeats: 0,
+                    last_at: line.timestamp,
+                }),
+            }
+        }
+    }
+    for ((node, program), node_instances) in &instances.by_program {
+        if !program.contains("tserver") {
+            continue;
+        }
+        for instance in node_instances {
+            if let Some(started_at) = instance.started_at {
+                events.entry(TserverRef::Node(node.clone())).or_default().push(LivenessEvent {
+                    timestamp: started_at,
+                    kind: LivenessEventKind::ProcessStarted,
+                    logged_by: node.clone(),
+                    message: format!(
+                        "{} started, pid {}", program,
+                        instance.pid.map_or(String::from("unknown"), |pid| pid.to_string())),
+                    repeats: 0,
+                    last_at: started_at,
+                });
+            }
+        }
+    }
+
+    // Merge the master's view of each tserver UUID into the timeline of the node it runs on.
+    let uuids_by_node: HashMap<&String, &String> =
+        tserver_nodes.iter().map(|(uuid, node)| (node, uuid)).collect();
+    let mut timelines: BTreeMap<String, Vec<LivenessEvent>> = BTreeMap::new();
+    for (tserver, tserver_events) in events {
+        let name = match tserver {
+            TserverRef::Node(node) => match uuids_by_node.get(&node) {
+                Some(uuid) => format!("{} ({})", node, uuid),
+                None => node,
+            },
+            TserverRef::Uuid(uuid) => match tserver_nodes.get(&uuid) {
+                Some(node) => format!("{} ({})", node, uuid),
+                None => uuid,
+            },
+        };
+        timelines.entry(name).or_default().extend(tserver_events);
+    }
+    for tserver_events in timelines.values_mut() {
+        tserver_events.sort_by_key(|event| event.timestamp);
+    }
+    timelines
+}
+
+/// The periods in which a tserver was unavailable, as seen by the master and by itself.
+fn tserver_unavailable_periods(tserver: &str, events: &[LivenessEvent])
+        -> Vec<UnavailablePeriod> {
+    let mut periods = unavailable_periods(
+        tserver, events, LivenessEventKind::MarkedDead, &[LivenessEventKind::MarkedAlive],
+        "master");
+    // A restarted tserver has stopped failing to heartbeat, but the master does not know yet.
+    periods.extend(unavailable_periods(
+        tserver, events, LivenessEventKind::HeartbeatFailed,
+        &[LivenessEventKind::HeartbeatRecovered, LivenessEventKind::ProcessStarted],
+        "tserver"));
+    periods
+}
+
+/// Prints, per tserver, the heartbeat failures it logged, when the master marked it dead or
+/// alive, and its process starts, followed by the periods in which it was unavailable.
+pub(crate) fn print_liveness_report(lines: MergedLines) {
+    let timelines = liveness_timelines(lines);
+
+    let mut periods: Vec<UnavailablePeriod> = Vec::new();
+    println!("Liveness events of {} tservers", timelines.len());
+    for (tserver, tserver_events) in &timelines {
+        println!();
+        println!("{}", tserver);
+        for event in tserver_events.iter() {
+            let repeats = if event.repeats > 0 {
+                format!(" ({} more until {})", event.repeats, event.last_at)
+            } else {
+                String::new()
+            };
+            println!("  {:<26}  {:<18}  {:<24}  {}{}",
+                     event.timestamp.to_string(), format!("{:?}", event.kind), event.logged_by,
+                     event.message, repeats);
+        }
+        periods.extend(tserver_unavailable_periods(tserver, tserver_events));
+    }
+
+    periods.sort_by(|a, b| a.from.cmp(&b.from).then_with(|| a.tserver.cmp(&b.tserver)));
+    println!();
+    println!("Unavailable periods ({})", periods.len());
+    println!("{:<26}  {:<26}  {:>12}  {:<8}  tserver", "from", "to", "duration", "seen by");
+    for period in &periods {
+        println!("{:<26}  {:<26}  {:>12}  {:<8}  {}",
+                 period.from.to_string(),
+                 period.to.map_or(String::from("end of logs"), |to| to.to_string()),
+                 period.to.map_or(String::from("-"), |to| format_duration(to - period.from)),
+                 period.seen_by, period.tserver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_lines::{glog_line, log_source, merged_lines};
+    use crate::LogSource;
+
+    const TABLET: &str = "0123456789abcdef0123456789abcdef";
+    const TS_1: &str = "3f1a2b3c4d5e4f60a1b2c3d4e5f60718";
+    const TS_3: &str = "5d6e7f8091a24b3c8d9e0f1a2b3c4d5e";
+
+    fn tserver(index: usize, node: &str, pid: u32, started_at: &str) -> Arc<LogSource> {
+        let mut source = log_source(index, node, &format!(
+            "yb-tserver.{}.yugabyte.log.INFO.20210408-100000.{}", node, pid));
+        source.process.started_at = Some(format!("2021-04-08T{}", started_at).parse().unwrap());
+        Arc::new(source)
+    }
+
+    #[test]
+    fn timelines_and_unavailable_periods() {
+        let master = Arc::new(
+            log_source(0, "m1", "yb-master.m1.yugabyte.log.INFO.20210408-100000.2000"));
+        let n1 = tserver(1, "n1", 1234, "10:00:00");
+        let n1_restarted = tserver(2, "n1", 1300, "10:05:00");
+        let n2 = tserver(3, "n2", 5678, "09:00:00");
+        let sources = [master.clone(), n1.clone(), n1_restarted.clone(), n2.clone()];
+        let heartbeat_failed = "Failed to heartbeat to 10.0.0.5:7100: Network error \
+                                (yb/rpc/outbound_call.cc:512): recvmsg error: Connection refused";
+        let lines = merged_lines(&sources, vec![
+            glog_line(&n1, &format!("I0408 10:00:01.000000  1240 raft_consensus.cc:900] \
+                                     T {} P {}: Leader election won for term 3", TABLET, TS_1)),
+            glog_line(&n1, &format!("W0408 10:01:00.000000  1250 heartbeater.cc:400] {}",
+                                    heartbeat_failed)),
+            glog_line(&n1, &format!("W0408 10:01:01.000000  1250 heartbeater.cc:400] {}",
+                                    heartbeat_failed)),
+            glog_line(&master, &format!("W0408 10:02:00.000000  2010 ts_manager.cc:200] \
+                                         TS {} (n1:9100) has not heartbeated in the last \
+                                         60000 ms", TS_1)),
+            glog_line(&n1_restarted, "I0408 10:05:02.000000  1310 heartbeater.cc:300] \
+                                      Connected to a leader master server at 10.0.0.5:7100"),
+            glog_line(&master, &format!("I0408 10:05:03.000000  2010 ts_manager.cc:100] \
+                                         Registered new tablet server {{ permanent_uuid: \
+                                         \"{}\" instance_seqno: 1617876300000000 }}", TS_1)),
+            glog_line(&n2, &format!("W0408 10:06:00.000000  5690 heartbeater.cc:400] {}",
+                                    heartbeat_failed)),
+            glog_line(&master, &format!("W0408 10:07:00.000000  2010 ts_manager.cc:200] \
+                                         Marking tablet server {} as dead", TS_3)),
+        ]);
+
+        let timelines = liveness_timelines(lines);
+        let n1_name = format!("n1 ({})", TS_1);
+        assert_eq!(timelines.keys().cloned().collect::<Vec<_>>(),
+                   [String::from(TS_3), n1_name.clone(), String::from("n2")]);
+        let n1_events: Vec<_> = timelines[&n1_name].iter()
+            .map(|event| (event.timestamp.time().to_string(), event.kind, event.repeats,
+                          event.logged_by.as_str()))
+            .collect();
+        let event = |time: &str, kind, repeats, logged_by| {
+            (String::from(time), kind, repeats, logged_by)
+        };
+        assert_eq!(n1_events, [
+            event("10:00:00", LivenessEventKind::ProcessStarted, 0, "n1"),
+            event("10:01:00", LivenessEventKind::HeartbeatFailed, 1, "n1"),
+            event("10:02:00", LivenessEventKind::MarkedDead, 0, "m1"),
+            event("10:05:00", LivenessEventKind::ProcessStarted, 0, "n1"),
+            event("10:05:02", LivenessEventKind::HeartbeatRecovered, 0, "n1"),
+            event("10:05:03", LivenessEventKind::MarkedAlive, 0, "m1"),
+        ]);
+
+        let periods = |tserver: &str| -> Vec<(String, Option<String>, &'static str)> {
+            tserver_unavailable_periods(tserver, &timelines[tserver]).iter()
+                .map(|period| (period.from.time().to_string(),
+                               period.to.map(|to| to.time().to_string()),
+                               period.seen_by))
+                .collect()
+        };
+        let period = |from: &str, to: Option<&str>, seen_by| {
+            (String::from(from), to.map(String::from), seen_by)
+        };
+        // The restart ends the heartbeat failures before the tserver reaches the master again.
+        assert_eq!(periods(&n1_name), [
+            period("10:02:00", Some("10:05:03"), "master"),
+            period("10:01:00", Some("10:05:00"), "tserver"),
+        ]);
+        assert_eq!(periods("n2"), [period("10:06:00", None, "tserver")]);
+        assert_eq!(periods(TS_3), [period("10:07:00", None, "master")]);
+    }
+}
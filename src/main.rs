@@ -1,50 +1,186 @@
 #[macro_use]
 extern crate clap;
 
-use yblp::RegexHolder;
+use yblp::{builtin_log_formats, detect_log_format, LogFilePreamble, LogFormat, LogLineRef};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::metadata;
 
-use clap::{App, Arg, ArgMatches};
-use flate2;
+use clap::{App, Arg};
 use regex::Regex;
 use uuid::Uuid;
-use chrono::{NaiveDateTime, NaiveDate};
+use chrono::{Duration, NaiveDateTime};
 use walkdir::WalkDir;
+use std::fmt;
 use std::fs;
 use std::ffi::OsString;
 use threadpool::ThreadPool;
+use memmap2::Mmap;
 use chrono::Datelike;
 
-use std::str::FromStr;
 use std::collections::BTreeSet;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use std::cell::RefCell;
+use std::sync::mpsc::{channel, Sender};
+use std::time::Instant;
 
 extern crate yblp;
 
-use self::yblp::parse_capture;
 use self::yblp::parse_regex;
-use self::yblp::parse_filter_timestamp;
+use self::yblp::{parse_filter_timestamp, FilterTimestampBase};
+use self::yblp::parse_size;
+use self::yblp::parse_duration;
+
+/// An inbound RPC call as the server logs it when the call took long, e.g.
+///   Call yb.consensus.ConsensusService.UpdateConsensus 10.0.0.1:52814 => 10.0.0.2:9100
+///   (request call id 12345) took 1520ms
+/// or, in older versions, "Call <method> from <remote> took ...". Captures the method as
+/// `method_group`, and the server's address as `server` and the call id as `call_id` if logged.
+/// Used by both the latency report and clock skew estimation.
+macro_rules! inbound_call_re {
+    ($method_group:literal) => {
+        concat!(
+            r"Call (?P<", $method_group, r">[\w.]+) (?:from \S+|\S+ => (?P<server>[^\s,}]+)) ",
+            r"(?:\(request call id (?P<call_id>\d+)\) )?"
+        )
+    };
+}
+
+mod anomalies;
+mod balancer;
+mod clock_skew;
+mod compactions;
+mod diff;
+mod external_sort;
+mod gaps;
+mod histogram;
+mod inventory;
+mod latency;
+mod lines_csv;
+mod liveness;
+mod memory;
+mod patterns;
+mod processes;
+mod query;
+mod restarts;
+mod rules;
+mod tablet_lifecycle;
+#[cfg(test)]
+mod test_lines;
+mod top_sources;
+
+use clock_skew::ClockOffsets;
+use external_sort::{estimated_line_size, MergedLines, SpilledRun};
+use query::WhereClause;
+use rules::{FieldFilter, Fields, UserRules};
 
 // ------------------------------------------------------------------------------------------------
 // OutputCollector -- collects output data
 // ------------------------------------------------------------------------------------------------
 
 struct OutputCollector {
-    output_lines: Vec<YBLogLine>
+    /// Runs of lines sorted by timestamp, typically one per input file. Each reader builds and
+    /// sorts its run without holding the lock, so the lock is only taken once per run.
+    sorted_runs: Vec<Vec<YBLogLine>>,
+    /// Approximate memory held by `sorted_runs`.
+    in_memory_bytes: usize,
+    /// Runs written to temporary files after `in_memory_bytes` went over `max_memory`.
+    spilled_runs: Vec<SpilledRun>,
+    max_memory: Option<usize>,
+    spill_dir: PathBuf,
+    /// All input files that lines have been read from, indexed by `LogSource::index`.
+    sources: Vec<Arc<LogSource>>,
 }
 
 impl OutputCollector {
-    fn new() -> OutputCollector {
+    fn new(max_memory: Option<usize>, spill_dir: PathBuf) -> OutputCollector {
         OutputCollector {
-            output_lines: Vec::new(),
+            sorted_runs: Vec::new(),
+            in_memory_bytes: 0,
+            spilled_runs: Vec::new(),
+            max_memory,
+            spill_dir,
+            sources: Vec::new(),
+        }
+    }
+
+    fn register_source(
+            context: &YBLogReaderContext,
+            path: &str,
+            preamble: &YBLogFilePreamble) -> Arc<LogSource> {
+        let clock_offset = match &preamble.metadata.running_on_machine {
+            Some(node) => context.clock_offsets.get(node).cloned().unwrap_or_else(Duration::zero),
+            None => Duration::zero(),
+        };
+        let mut locked_collector = context.output_collector.lock().unwrap();
+        let file_name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path);
+        let (program, pid) = preamble.format(context).process_from_file_name(file_name);
+        let source = Arc::new(LogSource {
+            node: preamble.metadata.running_on_machine.clone(),
+            path: String::from(path),
+            index: locked_collector.sources.len(),
+            process: ProcessInfo {
+                program,
+                pid,
+                started_at: preamble.metadata.process_started_at(),
+                build: preamble.build_info(),
+            },
+            clock_offset,
+        });
+        locked_collector.sources.push(source.clone());
+        source
+    }
+
+    fn sort_run(run: &mut [YBLogLine]) {
+        // Lines within one file are almost sorted already, which the stable sort handles in
+        // close to linear time.
+        run.sort_by(|a, b| a.ordering_key().cmp(&b.ordering_key()));
+    }
+
+    /// Adds a sorted run. If this takes the collector over its memory budget, takes all
+    /// in-memory runs back out and returns them so that the caller can spill them to disk
+    /// without holding the lock.
+    fn add_sorted_run(&mut self, run: Vec<YBLogLine>, run_bytes: usize) -> Vec<Vec<YBLogLine>> {
+        if run.is_empty() {
+            return Vec::new();
+        }
+        self.sorted_runs.push(run);
+        self.in_memory_bytes += run_bytes;
+        match self.max_memory {
+            Some(max_memory) if self.in_memory_bytes > max_memory => {
+                self.in_memory_bytes = 0;
+                std::mem::take(&mut self.sorted_runs)
+            }
+            _ => Vec::new(),
         }
     }
 
-    fn sort_lines(&mut self) {
+    fn hand_off_sorted_run(collector: &Mutex<OutputCollector>, run: Vec<YBLogLine>) {
+        let run_bytes: usize = run.iter().map(estimated_line_size).sum();
+        let (runs_to_spill, spill_dir) = {
+            let mut locked_collector = collector.lock().unwrap();
+            (locked_collector.add_sorted_run(run, run_bytes), locked_collector.spill_dir.clone())
+        };
+        if runs_to_spill.is_empty() {
+            return;
+        }
+        let merged_runs = MergedLines::new(Vec::new(), runs_to_spill, Vec::new()).unwrap();
+        let spilled_run = SpilledRun::write(merged_runs, &spill_dir).unwrap_or_else(
+            |e| panic!("Could not spill sorted lines to {:?}: {}", spill_dir, e));
+        println!("Spilled {} lines to {:?}", spilled_run.num_lines(), spill_dir);
+        collector.lock().unwrap().spilled_runs.push(spilled_run);
+    }
+
+    /// Returns an iterator merging all runs collected so far, including spilled ones, in the
+    /// order given by `YBLogLine::ordering_key`.
+    fn merged_lines(&mut self) -> MergedLines {
+        self.in_memory_bytes = 0;
+        MergedLines::new(
+            std::mem::take(&mut self.spilled_runs),
+            std::mem::take(&mut self.sorted_runs),
+            self.sources.clone(),
+        ).unwrap()
     }
 }
 
@@ -54,30 +190,41 @@ impl OutputCollector {
 
 
 struct YBLogReaderContext {
-    regexes: RegexHolder,
+    formats: Vec<Arc<dyn LogFormat>>,
     arg_info: ArgInfo,
     output_collector: Arc<Mutex<OutputCollector>>,
+    clock_offsets: ClockOffsets,
 }
 
-#[derive(Debug, PartialOrd, PartialEq, Clone)]
-struct TimestampWithoutYear {
-    month: u8,
-    day: u8,
-    hour: u8,
-    minute: u8,
-    second: u8,
-    microsecond: u32,
-}
-
-impl TimestampWithoutYear {
-    fn with_year(&self, year: i32) -> NaiveDateTime {
-        NaiveDate::from_ymd(year, u32::from(self.month), u32::from(self.day)).and_hms_micro(
-            u32::from(self.hour), u32::from(self.minute), u32::from(self.second),
-            u32::from(self.microsecond))
+impl YBLogReaderContext {
+    /// Applies the filters that need a whole entry, continuation lines included, such as --field
+    /// and --where. For the lines report, also stores the fields of --rules-file on the entry,
+    /// so that they are extracted once per line.
+    fn accepts_entry(&self, entry: &mut YBLogLine) -> bool {
+        let arg_info = &self.arg_info;
+        let keep_fields = arg_info.report == ReportKind::Lines;
+        let needs_fields = keep_fields || !arg_info.field_filters.is_empty() ||
+            arg_info.where_clause.as_ref().is_some_and(|clause| clause.uses_user_fields());
+        let fields: Fields = match &arg_info.rules {
+            Some(rules) if needs_fields => rules.extract(&entry.message),
+            _ => Fields::new(),
+        };
+        if !arg_info.field_filters.iter().all(|filter| filter.matches(&fields)) {
+            return false;
+        }
+        if let Some(where_clause) = &arg_info.where_clause {
+            if !where_clause.matches(entry, &fields) {
+                return false;
+            }
+        }
+        if keep_fields {
+            entry.fields = fields;
+        }
+        true
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 struct YBLogLine {
     log_level: char,
     timestamp: NaiveDateTime,
@@ -86,74 +233,110 @@ struct YBLogLine {
     line_number: i32,
     tablet_id: Option<Uuid>,
     message: String,
+    source: Arc<LogSource>,
+    /// Byte offset of the line's header within its (decompressed) source file.
+    file_offset: u64,
+    /// Fields extracted by --rules-file, kept only for the lines report, which prints them.
+    fields: Fields,
+}
+
+/// An input file. Lines with equal timestamps are ordered by node, then path, then position
+/// within the file, so that output does not depend on thread scheduling.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct LogSource {
+    node: Option<String>,
+    path: String,
+    /// Position in `OutputCollector::sources`, used to refer to this source in spilled runs.
+    /// Depends on the order in which files were opened, so it is left out of the Debug output.
+    index: usize,
+    process: ProcessInfo,
+    /// How far ahead of the reference node's clock this node's clock is, subtracted from all
+    /// timestamps with --skew-correct.
+    clock_offset: Duration,
+}
+
+/// The process that wrote a log file, as far as the file name and preamble tell.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct ProcessInfo {
+    program: String,
+    pid: Option<u32>,
+    /// File creation time minus the running duration from the preamble.
+    started_at: Option<NaiveDateTime>,
+    build: Option<BuildInfo>,
+}
+
+/// The build of YugabyteDB a process runs, from the application fingerprint in the preamble.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct BuildInfo {
+    version: String,
+    build_number: u64,
+    revision: String,
+    build_type: String,
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} build {} {}", self.version, self.build_number, self.build_type)
+    }
+}
+
+/// Leaves out `fields`, which the lines report prints on a line of their own.
+impl fmt::Debug for YBLogLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("YBLogLine")
+            .field("log_level", &self.log_level)
+            .field("timestamp", &self.timestamp)
+            .field("thread_id", &self.thread_id)
+            .field("file_name", &self.file_name)
+            .field("line_number", &self.line_number)
+            .field("tablet_id", &self.tablet_id)
+            .field("message", &self.message)
+            .field("source", &self.source)
+            .field("file_offset", &self.file_offset)
+            .finish()
+    }
 }
 
-struct LogChunk {
-    sorting_timestamp: TimestampWithoutYear,
+impl fmt::Debug for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LogSource")
+            .field("node", &self.node)
+            .field("path", &self.path)
+            .finish()
+    }
 }
 
+/// The format of a log file and the metadata from its first lines.
 #[derive(Default)]
 struct YBLogFilePreamble {
-    created_at: Option<NaiveDateTime>,
-    running_on_machine: Option<String>,
-    application_fingerprint: Option<String>,
-    version: Option<String>,
-    build_number: Option<u64>,
-    revision: Option<String>,
-    build_type: Option<String>,
-    built_at: Option<String>
+    /// Detected from the first line that any of the known formats recognizes.
+    format: Option<Arc<dyn LogFormat>>,
+    metadata: LogFilePreamble,
 }
 
 impl YBLogLine {
-    fn parse_tablet_id(line: &str, context: &YBLogReaderContext) -> Option<Uuid> {
-        match context.regexes.tablet_id_re.captures(line) {
-            Some(captures) => match Uuid::from_str(captures.get(1).unwrap().as_str()) {
-                Ok(parsed_uuid) => Some(parsed_uuid),
-                _ => None,
-            },
-            _ => None,
+    fn from_ref(
+            line_ref: &LogLineRef,
+            timestamp: NaiveDateTime,
+            source: &Arc<LogSource>,
+            file_offset: u64) -> YBLogLine {
+        YBLogLine {
+            log_level: line_ref.log_level,
+            timestamp,
+            thread_id: line_ref.thread_id,
+            file_name: String::from(line_ref.file_name),
+            line_number: line_ref.line_number,
+            tablet_id: line_ref.tablet_id(),
+            message: String::from(line_ref.message),
+            source: source.clone(),
+            file_offset,
+            fields: Fields::new(),
         }
     }
 
-    pub fn parse(
-            line: &str,
-            context: Arc<YBLogReaderContext>,
-            year: i32) -> Option<YBLogLine> {
-        match context.regexes.yb_log_line_re.captures(line) {
-            Some(captures) =>
-                {
-                    Some(YBLogLine {
-                        log_level: parse_capture(
-                            captures.get(RegexHolder::CAPTURE_INDEX_LOG_LEVEL),
-                        ),
-                        timestamp: NaiveDate::from_ymd(
-                                year,
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MONTH)),
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_DAY)),
-                            ).and_hms_micro(
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_HOUR)),
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MINUTE)),
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_SECOND)),
-                                parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MICROSECOND)),
-                            ),
-                        thread_id: parse_capture(
-                            captures.get(RegexHolder::CAPTURE_INDEX_THREAD_ID),
-                        ),
-                        file_name: String::from(
-                            captures
-                                .get(RegexHolder::CAPTURE_INDEX_FILE_NAME)
-                                .unwrap()
-                                .as_str(),
-                        ),
-                        line_number: parse_capture(
-                            captures.get(RegexHolder::CAPTURE_INDEX_LINE_NUMBER),
-                        ),
-                        tablet_id: YBLogLine::parse_tablet_id(line, context.as_ref()),
-                        message: parse_capture(captures.get(RegexHolder::CAPTURE_INDEX_MESSAGE)),
-                    })
-                }
-            _ => None,
-        }
+    /// The total order in which lines are output.
+    fn ordering_key(&self) -> (NaiveDateTime, &LogSource, u64) {
+        (self.timestamp, &self.source, self.file_offset)
     }
 }
 
@@ -162,29 +345,229 @@ enum FlexibleReader {
     GzipReader(BufReader<flate2::read::GzDecoder<File>>),
 }
 
-impl std::iter::Iterator for FlexibleReader {
-    type Item = std::io::Result<String>;
+impl FlexibleReader {
+    /// Reads the next line into `buf`, reusing its allocation, and strips the line terminator.
+    /// Returns the number of bytes consumed, including the terminator, or 0 at the end of input.
+    fn read_line(&mut self, buf: &mut String) -> std::io::Result<usize> {
+        buf.clear();
+        let num_bytes = match self {
+            FlexibleReader::RawReader(buf_reader) => buf_reader.read_line(buf),
+            FlexibleReader::GzipReader(buf_reader) => buf_reader.read_line(buf),
+        }?;
+        if buf.ends_with('\n') {
+            buf.pop();
+            if buf.ends_with('\r') {
+                buf.pop();
+            }
+        }
+        Ok(num_bytes)
+    }
+}
+
+const PREAMBLE_NUM_LINES: usize = 10;
+
+impl YBLogFilePreamble {
+    /// Picks up the format and metadata from one of the first `PREAMBLE_NUM_LINES` lines of a
+    /// log file.
+    fn parse_line(&mut self, line: &str, context: &YBLogReaderContext) {
+        if self.format.is_none() {
+            self.format = detect_log_format(&context.formats, line);
+        }
+        if let Some(format) = &self.format {
+            format.parse_preamble_line(line, &mut self.metadata);
+        }
+    }
+
+    /// The detected format, or glog if none was recognized.
+    fn format(&self, context: &YBLogReaderContext) -> Arc<dyn LogFormat> {
+        self.format.clone().unwrap_or_else(|| context.formats[0].clone())
+    }
+
+    fn build_info(&self) -> Option<BuildInfo> {
+        Some(BuildInfo {
+            version: self.metadata.version.clone()?,
+            build_number: self.metadata.build_number?,
+            revision: self.metadata.revision.clone()?,
+            build_type: self.metadata.build_type.clone()?,
+        })
+    }
+
+    fn year(&self, arg_info: &ArgInfo) -> i32 {
+        self.metadata.created_at.map(|d| d.year()).or(arg_info.default_year).unwrap()
+    }
+
+    /// Returns true, after printing the reason, if the whole file is outside the time range of
+    /// interest.
+    fn should_skip_file(&self, file_name: &str, arg_info: &ArgInfo) -> bool {
+        if let (Some(created_at), Some(ts_upper_limit)) =
+                (self.metadata.created_at, arg_info.highest_timestamp) {
+            if created_at > ts_upper_limit {
+                println!(
+                    "Skipping {} because it was created at {} but the user specified \
+                     {} as the highest timestamp of interest",
+                    file_name, created_at, ts_upper_limit
+                );
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Default)]
+struct ParseStats {
+    successfully_parsed_lines: u64,
+    continuation_lines: u64,
+    unsuccessfully_parsed_lines: u64,
+    skipped_lines: u64,
+}
+
+impl ParseStats {
+    fn print(&self, file_name: &str) {
+        println!(
+            "In file {}: successfully parsed lines: {}, \
+             continuation lines: {}, \
+             unsuccessfully parsed lines: {} \
+             skipped lines: {}",
+            file_name,
+            self.successfully_parsed_lines,
+            self.continuation_lines,
+            self.unsuccessfully_parsed_lines,
+            self.skipped_lines);
+    }
+}
+
+/// Where a line that is not a glog header (e.g. a stack trace frame) belongs.
+#[derive(Clone, Copy, PartialEq)]
+enum ContinuationTarget {
+    /// No glog header seen yet, e.g. in the preamble.
+    Nothing,
+    /// Start of a chunk other than the first one: the owner is in an earlier chunk.
+    PreviousChunk,
+    /// The last header line was filtered out, so its continuation lines are skipped too.
+    SkippedEntry,
+    /// The last header line was accepted and is still pending.
+    PendingEntry,
+}
+
+/// Groups lines into multi-line entries and applies the line-level filters. Used for a whole
+/// file when streaming, or for one newline-aligned chunk of a memory-mapped file.
+struct ChunkParser {
+    source: Arc<LogSource>,
+    format: Arc<dyn LogFormat>,
+    target: ContinuationTarget,
+    pending: Option<YBLogLine>,
+    /// Continuation lines at the start of a chunk, to be attached once chunks are stitched.
+    leading_continuation_lines: Vec<String>,
+    stats: ParseStats,
+}
+
+impl ChunkParser {
+    fn new(
+            target: ContinuationTarget,
+            source: Arc<LogSource>,
+            format: Arc<dyn LogFormat>) -> ChunkParser {
+        ChunkParser {
+            source,
+            format,
+            target,
+            pending: None,
+            leading_continuation_lines: Vec::new(),
+            stats: Default::default(),
+        }
+    }
+
+    /// Processes one line starting at `file_offset` and returns the previous entry if this line
+    /// completes it.
+    fn process_line(
+            &mut self,
+            line: &str,
+            file_offset: u64,
+            year: i32,
+            context: &YBLogReaderContext) -> Option<YBLogLine> {
+        let arg_info = &context.arg_info;
+        let contains_ok = match &arg_info.line_contains {
+            Some(line_contains) => line.contains(line_contains.as_str()),
+            None => true,
+        } && match &arg_info.where_clause {
+            Some(where_clause) => where_clause.may_match_line(line),
+            None => true,
+        };
+        // Lines rejected by --line-contains or --where only need to be told apart from continuation
+        // lines, so the regex fallback is not worth running on them.
+        let maybe_parsed_line = if contains_ok {
+            self.format.parse_line(line)
+        } else {
+            self.format.parse_line_fast(line)
+        };
 
-    fn next(&mut self) -> Option<std::io::Result<String>> {
-        let mut buf = String::new();
-        match {
-            match self {
-                FlexibleReader::RawReader(buf_reader) => buf_reader.read_line(&mut buf),
-                FlexibleReader::GzipReader(buf_reader) => buf_reader.read_line(&mut buf),
+        let parsed_line = match maybe_parsed_line {
+            Some(parsed_line) => parsed_line,
+            None if !self.format.is_continuation(line) => {
+                self.stats.unsuccessfully_parsed_lines += 1;
+                // Continuation lines of an entry that could not be parsed have nowhere to go.
+                self.target = ContinuationTarget::Nothing;
+                return None;
             }
-        } {
-            Ok(0) => None,
-            Ok(_n) => {
-                if buf.ends_with('\n') {
-                    buf.pop();
-                    if buf.ends_with('\r') {
-                        buf.pop();
+            None => {
+                match self.target {
+                    ContinuationTarget::PendingEntry => {
+                        let entry = self.pending.as_mut().unwrap();
+                        entry.message.push('\n');
+                        entry.message.push_str(line);
+                        self.stats.continuation_lines += 1;
+                    }
+                    ContinuationTarget::PreviousChunk => {
+                        self.leading_continuation_lines.push(String::from(line));
                     }
+                    ContinuationTarget::SkippedEntry => self.stats.skipped_lines += 1,
+                    ContinuationTarget::Nothing => self.stats.unsuccessfully_parsed_lines += 1,
                 }
-                Some(Ok(buf))
+                return None;
+            }
+        };
+
+        let completed_entry = self.pending.take();
+        self.target = ContinuationTarget::SkippedEntry;
+        if !contains_ok {
+            self.stats.skipped_lines += 1;
+            return completed_entry;
+        }
+        self.stats.successfully_parsed_lines += 1;
+
+        // Only build the timestamp here; the owned YBLogLine is created once the line has passed
+        // all filters, which apply to skew-corrected timestamps.
+        let timestamp = parsed_line.timestamp.with_year(parsed_line.year.unwrap_or(year)) -
+            self.source.clock_offset;
+        let mut in_range = true;
+        if let Some(highest_ts) = arg_info.highest_timestamp {
+            if timestamp > highest_ts {
+                in_range = false;
             }
-            Err(e) => Some(Err(e)),
         }
+        if let Some(lowest_ts) = arg_info.lowest_timestamp {
+            if timestamp < lowest_ts {
+                in_range = false;
+            }
+        }
+        if let Some(where_clause) = &arg_info.where_clause {
+            if !where_clause.may_match_timestamp(timestamp) {
+                in_range = false;
+            }
+        }
+        if in_range {
+            self.pending = Some(
+                YBLogLine::from_ref(&parsed_line, timestamp, &self.source, file_offset));
+            self.target = ContinuationTarget::PendingEntry;
+        } else {
+            self.stats.skipped_lines += 1;
+        }
+        completed_entry
+    }
+
+    /// Returns the last entry, which may still receive continuation lines from the next chunk.
+    fn finish(&mut self) -> Option<YBLogLine> {
+        self.pending.take()
     }
 }
 
@@ -214,121 +597,275 @@ impl YBLogReader {
     }
 
     pub fn load(&mut self) {
-        let mut line_index: usize = 1;
-        const PREAMBLE_NUM_LINES: usize = 10;
-        let mut successfully_parsed_lines: u64 = 0;
-        let mut unsuccessfully_parsed_lines: u64 = 0;
-        let mut skipped_lines: u64 = 0;
-
-        let mut year_from_preamble_opt: Option<i32> = None;
-        let output_collector_mutex: &Mutex<OutputCollector> = &self.context.output_collector;
-        for maybe_line in &mut self.reader {
-            let line = maybe_line.unwrap();
-
-            if line_index <= PREAMBLE_NUM_LINES {
-                if let Some(captures) = self.context.regexes.log_file_created_at_re.captures(
-                        line.as_str()) {
-                    let created_at = NaiveDate::from_ymd(
-                        parse_capture(captures.get(1)),
-                        parse_capture(captures.get(2)),
-                        parse_capture(captures.get(3))
-                    ).and_hms(
-                        parse_capture(captures.get(4)),
-                        parse_capture(captures.get(5)),
-                        parse_capture(captures.get(6))
-                    );
-                    self.preamble.created_at = Some(created_at);
-
-                    if let Some(ts_upper_limit) = self.context.arg_info.highest_timestamp {
-                        if created_at > ts_upper_limit {
-                            println!(
-                                "Skipping {} because it was created at {} but the user specified \
-                                 {} as the highest timestamp of interest",
-                                self.file_name, created_at, ts_upper_limit
-                            );
-                            break;
-                        }
-                    }
-                }
-                if let Some(captures) = self.context.regexes.running_on_machine_re.captures(
-                        line.as_str()) {
-                    self.preamble.running_on_machine = Some(
-                        String::from(captures.get(1).unwrap().as_str()));
-                }
+        // Read the preamble before anything else so that lines can be tagged with their node.
+        let mut file_offset: u64 = 0;
+        let mut preamble_lines: Vec<(u64, String)> = Vec::new();
+        let mut line = String::new();
+        while preamble_lines.len() < PREAMBLE_NUM_LINES {
+            let num_bytes = self.reader.read_line(&mut line).unwrap();
+            if num_bytes == 0 {
+                break;
             }
+            self.preamble.parse_line(line.as_str(), &self.context);
+            preamble_lines.push((file_offset, line.clone()));
+            file_offset += num_bytes as u64;
+        }
+        if self.preamble.should_skip_file(&self.file_name, &self.context.arg_info) {
+            return;
+        }
+
+        let source = OutputCollector::register_source(
+            &self.context, &self.file_name, &self.preamble);
+        let year = self.preamble.year(&self.context.arg_info);
+        let mut parser = ChunkParser::new(
+            ContinuationTarget::Nothing, source, self.preamble.format(&self.context));
+        let mut run = RunBuilder::new(&self.context);
+
+        for (line_offset, preamble_line) in &preamble_lines {
+            run.extend(parser.process_line(preamble_line, *line_offset, year, &self.context));
+        }
+        loop {
+            let num_bytes = self.reader.read_line(&mut line).unwrap();
+            if num_bytes == 0 {
+                break;
+            }
+            run.extend(parser.process_line(line.as_str(), file_offset, year, &self.context));
+            file_offset += num_bytes as u64;
+        }
+        run.extend(parser.finish());
+        parser.stats.print(&self.file_name);
+        run.finish();
+    }
+}
 
-            let line_str = line.as_str();
-            let mut should_skip = false;
-            if let Some(line_contains) = &self.context.arg_info.line_contains {
-                if !line.contains(line_contains.as_str()) {
-                    should_skip = true;
+/// Accumulates the lines of one file and hands them off to the output collector as a sorted run,
+/// in several parts if --max-memory is set, so that one huge file does not blow through it.
+struct RunBuilder<'a> {
+    context: &'a YBLogReaderContext,
+    lines: Vec<YBLogLine>,
+    bytes: usize,
+    max_bytes: Option<usize>,
+}
+
+impl<'a> RunBuilder<'a> {
+    fn new(context: &'a YBLogReaderContext) -> RunBuilder<'a> {
+        RunBuilder {
+            context,
+            lines: Vec::new(),
+            bytes: 0,
+            max_bytes: context.arg_info.max_memory.map(
+                |max_memory| max_memory / (2 * num_cpus::get())),
+        }
+    }
+
+    fn extend<I: IntoIterator<Item = YBLogLine>>(&mut self, entries: I) {
+        for mut entry in entries {
+            if !self.context.accepts_entry(&mut entry) {
+                continue;
+            }
+            if let Some(max_bytes) = self.max_bytes {
+                let entry_bytes = estimated_line_size(&entry);
+                if !self.lines.is_empty() && self.bytes + entry_bytes > max_bytes {
+                    self.hand_off();
                 }
+                // The entry starts the next run, so it counts towards that one.
+                self.bytes += entry_bytes;
             }
+            self.lines.push(entry);
+        }
+    }
 
-            if (!should_skip) {
-                let year = self.preamble.created_at.map(|d| d.year()).or(
-                    self.context.arg_info.default_year).unwrap();
-                let maybe_parsed_line = YBLogLine::parse(line_str, self.context.clone(), year);
-                if let Some(parsed_line) = maybe_parsed_line {
-                    // Parsing success
+    fn hand_off(&mut self) {
+        let mut run = std::mem::take(&mut self.lines);
+        self.bytes = 0;
+        OutputCollector::sort_run(&mut run);
+        OutputCollector::hand_off_sorted_run(&self.context.output_collector, run);
+    }
 
-                    let timestamp = &parsed_line.timestamp;
+    fn finish(mut self) {
+        self.hand_off();
+    }
+}
 
-                    if let Some(highest_ts) = self.context.arg_info.highest_timestamp {
-                        if *timestamp > highest_ts {
-                            should_skip = true;
-                        }
-                    }
-                    if let Some(lowest_ts) = self.context.arg_info.lowest_timestamp {
-                        if *timestamp < lowest_ts {
-                            should_skip = true;
-                        }
-                    }
+// ------------------------------------------------------------------------------------------------
+// MappedLogFile -- a large uncompressed log file parsed in parallel, one chunk per task
+// ------------------------------------------------------------------------------------------------
 
-                    successfully_parsed_lines += 1;
+/// What is left of a chunk once its complete entries have been handed off to the output
+/// collector: the parts that join it to its neighbours.
+struct ParsedChunk {
+    file_index: usize,
+    chunk_index: usize,
+    /// The last entry of the chunk, which continuation lines at the start of the next chunk may
+    /// still belong to.
+    last_entry: Option<YBLogLine>,
+    leading_continuation_lines: Vec<String>,
+    /// What a continuation line at the start of the next chunk would be attached to.
+    final_target: ContinuationTarget,
+    stats: ParseStats,
+}
 
-                    if (!should_skip) {
-                        let output_lock = output_collector_mutex.lock();
-                        output_lock.unwrap().output_lines.push(parsed_line);
-                    }
+struct MappedLogFile {
+    file_name: String,
+    mmap: Arc<Mmap>,
+    preamble: YBLogFilePreamble,
+}
+
+impl MappedLogFile {
+    fn open(
+            file_name: &str,
+            context: &YBLogReaderContext) -> Result<MappedLogFile, std::io::Error> {
+        let file = File::open(file_name)?;
+        // SAFETY: log files are not expected to be truncated while we read them. If one is, we
+        // may read garbage or crash, the same as with any other tool reading a log being rotated.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut preamble: YBLogFilePreamble = Default::default();
+        for line in mmap.split(|b| *b == b'\n').take(PREAMBLE_NUM_LINES) {
+            preamble.parse_line(&String::from_utf8_lossy(line), context);
+        }
+        Ok(MappedLogFile {
+            file_name: String::from(file_name),
+            mmap: Arc::new(mmap),
+            preamble,
+        })
+    }
+
+    /// Splits the file into ranges of at least `chunk_size` bytes, each ending right after a
+    /// newline (or at the end of the file).
+    fn chunk_ranges(&self, chunk_size: usize) -> Vec<Range<usize>> {
+        let data: &[u8] = &self.mmap;
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let tentative_end = std::cmp::min(start + chunk_size, data.len());
+            let end = match data[tentative_end..].iter().position(|b| *b == b'\n') {
+                Some(offset) => tentative_end + offset + 1,
+                None => data.len(),
+            };
+            ranges.push(start..end);
+            start = end;
+        }
+        ranges
+    }
+
+    /// Queues one parsing task per chunk. Each task hands off the complete entries of its chunk
+    /// as sorted runs right away, so that --max-memory applies, and sends back the rest through
+    /// `sender`.
+    fn submit_chunks(
+            &self,
+            file_index: usize,
+            chunk_size: usize,
+            context: &Arc<YBLogReaderContext>,
+            pool: &ThreadPool,
+            sender: &Sender<ParsedChunk>) -> usize {
+        if self.preamble.should_skip_file(&self.file_name, &context.arg_info) {
+            return 0;
+        }
+        let year = self.preamble.year(&context.arg_info);
+        let source = OutputCollector::register_source(context, &self.file_name, &self.preamble);
+        let format = self.preamble.format(context);
+        let ranges = self.chunk_ranges(chunk_size);
+        let num_chunks = ranges.len();
+        for (chunk_index, range) in ranges.into_iter().enumerate() {
+            let mmap = self.mmap.clone();
+            let context = context.clone();
+            let sender = sender.clone();
+            let source = source.clone();
+            let format = format.clone();
+            pool.execute(move || {
+                let target = if chunk_index == 0 {
+                    ContinuationTarget::Nothing
                 } else {
-                    // Parsing failure
-                    unsuccessfully_parsed_lines += 1;
+                    ContinuationTarget::PreviousChunk
+                };
+                let mut parser = ChunkParser::new(target, source, format);
+                let mut run = RunBuilder::new(&context);
+                let mut file_offset = range.start as u64;
+                for line_with_terminator in mmap[range].split_inclusive(|b| *b == b'\n') {
+                    let line = line_with_terminator.strip_suffix(b"\n").map_or(
+                        line_with_terminator, |line| line.strip_suffix(b"\r").unwrap_or(line));
+                    // Invalid UTF-8, e.g. a line cut short by a crash, is replaced rather than
+                    // failing the whole file, the same as in the preamble.
+                    let line = String::from_utf8_lossy(line);
+                    run.extend(parser.process_line(&line, file_offset, year, &context));
+                    file_offset += line_with_terminator.len() as u64;
                 }
-            }
+                run.finish();
+                sender.send(ParsedChunk {
+                    file_index,
+                    chunk_index,
+                    last_entry: parser.finish(),
+                    leading_continuation_lines: parser.leading_continuation_lines,
+                    final_target: parser.target,
+                    stats: parser.stats,
+                }).unwrap();
+            });
+        }
+        num_chunks
+    }
 
-            if (should_skip) {
-                skipped_lines += 1;
+    /// Attaches continuation lines found at the start of each chunk to the last entry of the
+    /// chunks before it, and returns those last entries, which are then complete.
+    fn stitch(&self, mut chunks: Vec<ParsedChunk>) -> Vec<YBLogLine> {
+        chunks.sort_by_key(|chunk| chunk.chunk_index);
+        let mut stats: ParseStats = Default::default();
+        let mut entries: Vec<YBLogLine> = Vec::new();
+        let mut pending: Option<YBLogLine> = None;
+        let mut target = ContinuationTarget::Nothing;
+        for chunk in chunks {
+            for line in chunk.leading_continuation_lines {
+                match (target, &mut pending) {
+                    (ContinuationTarget::PendingEntry, Some(entry)) => {
+                        entry.message.push('\n');
+                        entry.message.push_str(&line);
+                        stats.continuation_lines += 1;
+                    }
+                    (ContinuationTarget::SkippedEntry, _) => stats.skipped_lines += 1,
+                    _ => stats.unsuccessfully_parsed_lines += 1,
+                }
             }
-
-            line_index += 1;
+            // A chunk without any header line leaves the previous entry pending.
+            if chunk.final_target != ContinuationTarget::PreviousChunk {
+                entries.extend(pending.take());
+                pending = chunk.last_entry;
+                target = chunk.final_target;
+            }
+            stats.successfully_parsed_lines += chunk.stats.successfully_parsed_lines;
+            stats.continuation_lines += chunk.stats.continuation_lines;
+            stats.unsuccessfully_parsed_lines += chunk.stats.unsuccessfully_parsed_lines;
+            stats.skipped_lines += chunk.stats.skipped_lines;
         }
-        println!(
-            "In file {}: successfully parsed lines: {}, \
-             unsuccessfully parsed lines: {} \
-             skipped lines: {}",
-            self.file_name,
-            successfully_parsed_lines,
-            unsuccessfully_parsed_lines,
-            skipped_lines);
+        entries.extend(pending);
+        stats.print(&self.file_name);
+        entries
     }
 }
 
 fn timestamp_validator(v: String) -> Result<(), String> {
-    match parse_filter_timestamp(v.as_str()) {
+    // The default year is not known yet; a leap year accepts glog-style timestamps of Feb 29.
+    match parse_filter_timestamp(v.as_str(), &FilterTimestampBase::current(2020)) {
         Ok(_) => Ok(()),
         Err(s) => Err(s)
     }
 }
 
-fn get_timestamp_arg<'a>(values_opt: Option<clap::Values<'a>>) -> Option<NaiveDateTime> {
+fn size_validator(v: String) -> Result<(), String> {
+    parse_size(v.as_str()).map(|_| ())
+}
+
+fn duration_validator(v: String) -> Result<(), String> {
+    parse_duration(v.as_str()).map(|_| ())
+}
+
+fn field_filter_validator(v: String) -> Result<(), String> {
+    FieldFilter::parse(v.as_str()).map(|_| ())
+}
+
+fn get_timestamp_arg<'a>(
+        values_opt: Option<clap::Values<'a>>, base: &FilterTimestampBase) -> Option<NaiveDateTime> {
     match values_opt {
-        Some(mut values) => match values.next() {
-            Some(value_str) => {
-                Some(parse_filter_timestamp(value_str).unwrap())
-            },
-            None => None
-        },
+        Some(mut values) => values.next().map(
+            |value_str| parse_filter_timestamp(value_str, base).unwrap()),
         None => None
     }
 }
@@ -336,7 +873,7 @@ fn get_timestamp_arg<'a>(values_opt: Option<clap::Values<'a>>) -> Option<NaiveDa
 fn capitalize_string(input: &str) -> String {
     // From https://stackoverflow.com/questions/38406793/why-is-capitalizing-the-first-letter-of-a-string-so-convoluted-in-rust
     let mut s = input.to_string();
-    return s.remove(0).to_uppercase().to_string() + &s;
+    s.remove(0).to_uppercase().to_string() + &s
 }
 
 struct TimestampArgHelper {
@@ -348,20 +885,40 @@ struct TimestampArgHelper {
 impl TimestampArgHelper {
     fn new(lowest_or_highest: &str) -> TimestampArgHelper {
         TimestampArgHelper {
-            arg_name: String::from(lowest_or_highest.to_uppercase()) + "_TIMESTAMP",
-            long_option_name: String::from(lowest_or_highest.to_lowercase()) + "-timestamp",
+            arg_name: lowest_or_highest.to_uppercase() + "_TIMESTAMP",
+            long_option_name: lowest_or_highest.to_lowercase() + "-timestamp",
             help_text: std::format!(
-                    "{} timestamp (inclusive) of the log range to look at (YYYY-MM-DD HH:MM:SS, \
-                     YYYY-MM-DDTHH:MM:SS, or only a date of the YYYY-MM-DD format).",
+                    "{} timestamp (inclusive) of the log range to look at: YYYY-MM-DD HH:MM:SS \
+                     or YYYY-MM-DDTHH:MM:SS with optional fractional seconds, only a date of the \
+                     YYYY-MM-DD format, or glog-style MMDD HH:MM:SS in the --default-year. A \
+                     time zone such as Z, +02 or -07:00 may follow the time, converting it to \
+                     UTC. Also accepts times relative to the current UTC time, such as now, -2h \
+                     or now-30m.",
                     capitalize_string(lowest_or_highest))
         }
     }
 
+    /// The --compare-lowest-timestamp and --compare-highest-timestamp arguments of the diff
+    /// report.
+    fn for_comparison(lowest_or_highest: &str) -> TimestampArgHelper {
+        TimestampArgHelper {
+            arg_name: String::from("COMPARE_") + &lowest_or_highest.to_uppercase() + "_TIMESTAMP",
+            long_option_name: String::from("compare-") + &lowest_or_highest.to_lowercase() +
+                "-timestamp",
+            help_text: std::format!(
+                    "{} timestamp (inclusive) of the log range to compare against in the diff \
+                     report, in the same formats as --{}-timestamp.",
+                    capitalize_string(lowest_or_highest), lowest_or_highest.to_lowercase())
+        }
+    }
+
     fn create_arg<'a>(&'a self) -> Arg<'a, 'a> {
         Arg::with_name(self.arg_name.as_str())
             .long(self.long_option_name.as_str())
             .takes_value(true)
             .help(self.help_text.as_str())
+            // Relative timestamps such as -2h start with a hyphen.
+            .allow_hyphen_values(true)
             .validator(timestamp_validator)
     }
 }
@@ -370,6 +927,7 @@ impl TimestampArgHelper {
 // ArgInfo
 // ------------------------------------------------------------------------------------------------
 
+#[derive(Clone)]
 struct ArgInfo {
     lowest_timestamp: Option<NaiveDateTime>,
     highest_timestamp: Option<NaiveDateTime>,
@@ -377,6 +935,128 @@ struct ArgInfo {
     input_files: Vec<String>,
     name_regex: Option<Regex>,
     line_contains: Option<String>,
+    parallel_chunk_size: usize,
+    max_memory: Option<usize>,
+    spill_dir: PathBuf,
+    report: ReportKind,
+    top: usize,
+    per_node: bool,
+    per_tablet: bool,
+    skew_correct: bool,
+    bucket: Option<Duration>,
+    csv_file: Option<String>,
+    spike_factor: f64,
+    baseline_buckets: usize,
+    compare_to: Vec<String>,
+    compare_lowest_timestamp: Option<NaiveDateTime>,
+    compare_highest_timestamp: Option<NaiveDateTime>,
+    change_factor: f64,
+    min_gap: Duration,
+    rules: Option<Arc<UserRules>>,
+    field_filters: Vec<FieldFilter>,
+    where_clause: Option<Arc<WhereClause>>,
+}
+
+impl ArgInfo {
+    /// The arguments for the second side of the diff report: the --compare-to inputs, if any,
+    /// filtered by the --compare-*-timestamp range, if any.
+    fn comparison_side(&self) -> ArgInfo {
+        if self.compare_to.is_empty() && self.compare_lowest_timestamp.is_none() &&
+                self.compare_highest_timestamp.is_none() {
+            panic!("The diff report needs --compare-to, --compare-lowest-timestamp or \
+                    --compare-highest-timestamp to define what to compare against");
+        }
+        let mut comparison = self.clone();
+        if !self.compare_to.is_empty() {
+            comparison.input_files = self.compare_to.clone();
+        }
+        if self.compare_lowest_timestamp.is_some() || self.compare_highest_timestamp.is_some() {
+            comparison.lowest_timestamp = self.compare_lowest_timestamp;
+            comparison.highest_timestamp = self.compare_highest_timestamp;
+        }
+        comparison
+    }
+
+    /// The arguments for the pass that estimates clock offsets for --skew-correct: the same
+    /// files, but none of the line filters, as messages paired across nodes are what matters.
+    fn skew_estimation_side(&self) -> ArgInfo {
+        ArgInfo {
+            lowest_timestamp: None,
+            highest_timestamp: None,
+            line_contains: None,
+            field_filters: Vec::new(),
+            where_clause: None,
+            report: ReportKind::Skew,
+            ..self.clone()
+        }
+    }
+}
+
+/// What to do with the lines that pass all filters.
+#[derive(Clone, Copy, PartialEq)]
+enum ReportKind {
+    /// Print every line in timestamp order.
+    Lines,
+    /// Cluster messages into templates and print the most frequent ones.
+    Patterns,
+    /// Count lines by the source file and line of the logging statement.
+    TopSources,
+    /// Count lines per time bucket, by level and by node.
+    Histogram,
+    /// Find message templates with rate spikes or that newly appear.
+    Anomalies,
+    /// Compare templates and source locations between two bundles or time ranges.
+    Diff,
+    /// List process starts and crashes per node, with the downtime between them.
+    Restarts,
+    /// Show which version and build type each program on each node ran over time.
+    Inventory,
+    /// Estimate clock offsets between nodes from events logged on both ends.
+    Skew,
+    /// Find silences within each file and across the whole bundle.
+    Gaps,
+    /// Show latency percentiles of operations whose durations are logged.
+    Latency,
+    /// Summarize RocksDB flushes and compactions per tablet and node.
+    Compactions,
+    /// Show memory limit events and consumption per node over time.
+    Memory,
+    /// Follow load balancer replica and leader moves per tablet through master and tserver logs.
+    Balancer,
+    /// Follow each tablet through creation, remote bootstrap, tombstoning, deletion and splits.
+    TabletLifecycle,
+    /// Show when each tserver failed to heartbeat or was considered dead, along with restarts.
+    Liveness,
+}
+
+impl ReportKind {
+    const NAMES: &'static [&'static str] = &[
+        "lines", "patterns", "top-sources", "histogram", "anomalies", "diff", "restarts",
+        "inventory", "skew", "gaps", "latency", "compactions", "memory",
+        "balancer", "tablet-lifecycle", "liveness",
+    ];
+
+    fn from_name(name: &str) -> ReportKind {
+        match name {
+            "lines" => ReportKind::Lines,
+            "patterns" => ReportKind::Patterns,
+            "top-sources" => ReportKind::TopSources,
+            "histogram" => ReportKind::Histogram,
+            "anomalies" => ReportKind::Anomalies,
+            "diff" => ReportKind::Diff,
+            "restarts" => ReportKind::Restarts,
+            "inventory" => ReportKind::Inventory,
+            "skew" => ReportKind::Skew,
+            "gaps" => ReportKind::Gaps,
+            "latency" => ReportKind::Latency,
+            "compactions" => ReportKind::Compactions,
+            "memory" => ReportKind::Memory,
+            "balancer" => ReportKind::Balancer,
+            "tablet-lifecycle" => ReportKind::TabletLifecycle,
+            "liveness" => ReportKind::Liveness,
+            _ => panic!("Unknown report: {}", name),
+        }
+    }
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -386,17 +1066,21 @@ struct ArgInfo {
 struct ArgParsingHelper {
     lowest_helper: TimestampArgHelper,
     highest_helper: TimestampArgHelper,
+    compare_lowest_helper: TimestampArgHelper,
+    compare_highest_helper: TimestampArgHelper,
 }
 
 impl ArgParsingHelper {
     fn new() -> ArgParsingHelper {
         ArgParsingHelper {
             lowest_helper: TimestampArgHelper::new("lowest"),
-            highest_helper: TimestampArgHelper::new("highest")
+            highest_helper: TimestampArgHelper::new("highest"),
+            compare_lowest_helper: TimestampArgHelper::for_comparison("lowest"),
+            compare_highest_helper: TimestampArgHelper::for_comparison("highest"),
         }
     }
 
-    pub fn parse_args<'a>(&'a self) -> ArgInfo {
+    pub fn parse_args(&self) -> ArgInfo {
         let matches = App::new("Yugabyte log processor")
             .about("A tool for manipulating YugabyteDB logs")
             .version("1.0.0")
@@ -408,6 +1092,22 @@ impl ArgParsingHelper {
             )
             .arg(self.lowest_helper.create_arg())
             .arg(self.highest_helper.create_arg())
+            .arg(Arg::with_name("AROUND")
+                    .long("--around")
+                    .help("Look at the log range from --window before to --window after this \
+                           timestamp, in the same formats as --lowest-timestamp. Replaces \
+                           --lowest-timestamp and --highest-timestamp.")
+                    .validator(timestamp_validator)
+                    .allow_hyphen_values(true)
+                    .conflicts_with_all(&["LOWEST_TIMESTAMP", "HIGHEST_TIMESTAMP"])
+                    .takes_value(true))
+            .arg(Arg::with_name("WINDOW")
+                    .long("--window")
+                    .help("How far before and after the --around timestamp to look, e.g. 30s or \
+                           5m. Defaults to 5m.")
+                    .validator(duration_validator)
+                    .requires("AROUND")
+                    .takes_value(true))
             .arg(Arg::with_name("DEFAULT_YEAR")
                     .long("--default-year")
                     .help("Use this year when year is unknown in a glog timestamp")
@@ -426,32 +1126,239 @@ impl ArgParsingHelper {
                            we can identify some log file metadata. This can speed up log \
                            processing significantly.")
                     .takes_value(true))
+            .arg(Arg::with_name("PARALLEL_CHUNK_MB")
+                    .long("--parallel-chunk-mb")
+                    .help("Memory-map uncompressed files larger than this many MiB and parse \
+                           them in chunks of this size in parallel. Set to 0 to always read files \
+                           sequentially, one thread per file.")
+                    .default_value("64")
+                    .takes_value(true))
+            .arg(Arg::with_name("MAX_MEMORY")
+                    .long("--max-memory")
+                    .help("Approximate memory budget for collected lines, e.g. 512M or 4G. \
+                           Beyond this, sorted runs of lines are spilled to temporary files and \
+                           merged at the end.")
+                    .validator(size_validator)
+                    .takes_value(true))
+            .arg(Arg::with_name("SPILL_DIR")
+                    .long("--spill-dir")
+                    .help("Directory for temporary files written when --max-memory is exceeded. \
+                           Defaults to the system temporary directory.")
+                    .takes_value(true))
+            .arg(Arg::with_name("REPORT")
+                    .long("--report")
+                    .help("What to output: all matching lines in timestamp order (lines), the \
+                           most frequent message templates (patterns), line counts by the \
+                           source location of the logging statement (top-sources), line counts \
+                           per --bucket by level and node (histogram), message templates whose \
+                           rate spiked or that newly appeared (anomalies), differences from \
+                           the logs given by --compare-to and --compare-*-timestamp (diff), \
+                           process starts, crashes and downtime per node (restarts), or the \
+                           versions and build types run over time, with mixed-version periods \
+                           and non-release builds (inventory), clock offsets between nodes \
+                           estimated from messages logged on both ends (skew), silences of at \
+                           least --min-gap within each file and across all files (gaps), \
+                           percentiles of operation durations such as \"took N ms\" per \
+                           --bucket (latency), RocksDB flushes and compactions per tablet with \
+                           their concurrency per --bucket (compactions), memory limit events, \
+                           pressure periods and top memory consumers (memory), load balancer \
+                           replica and leader moves per tablet with the steps the source and \
+                           destination tservers logged for them (balancer), the creation, \
+                           remote bootstraps, tombstoning, deletion and splits of each tablet \
+                           on each node, with slow, failed and incomplete bootstraps \
+                           (tablet-lifecycle), or heartbeat failures, tservers marked dead or \
+                           alive by the master and tserver restarts, with the periods each \
+                           tserver was unavailable (liveness).")
+                    .possible_values(ReportKind::NAMES)
+                    .default_value("lines")
+                    .takes_value(true))
+            .arg(Arg::with_name("TOP")
+                    .long("--top")
+                    .help("Number of rows to show in reports that rank their results.")
+                    .default_value("50")
+                    .takes_value(true))
+            .arg(Arg::with_name("PER_NODE")
+                    .long("--per-node")
+                    .help("Break down report rows by node (the machine a log was written on)."))
+            .arg(Arg::with_name("PER_TABLET")
+                    .long("--per-tablet")
+                    .help("Break down latency report rows by tablet. Samples from lines without \
+                           a tablet id are shown under \"-\"."))
+            .arg(Arg::with_name("SKEW_CORRECT")
+                    .long("--skew-correct")
+                    .help("Estimate clock offsets between nodes as in the skew report, then \
+                           shift each node's timestamps onto the clock of the reference node \
+                           before filtering and merging."))
+            .arg(Arg::with_name("BUCKET")
+                    .long("--bucket")
+                    .help("Break down report rows by time buckets of this size, e.g. 30s, 1m or \
+                           1h. The histogram, anomalies, compactions and memory reports use 1m \
+                           buckets by default.")
+                    .validator(duration_validator)
+                    .takes_value(true))
+            .arg(Arg::with_name("CSV_FILE")
+                    .long("--csv-file")
+                    .help("Also write the report to this CSV file, for reports that support it \
+                           (lines, histogram).")
+                    .takes_value(true))
+            .arg(Arg::with_name("SPIKE_FACTOR")
+                    .long("--spike-factor")
+                    .help("In the anomalies report, flag a template in a bucket if its count is \
+                           more than this many times its rolling baseline.")
+                    .default_value("3")
+                    .takes_value(true))
+            .arg(Arg::with_name("BASELINE_BUCKETS")
+                    .long("--baseline-buckets")
+                    .help("In the anomalies report, the number of preceding buckets averaged \
+                           into a template's rolling baseline. Templates first seen this many \
+                           buckets or more after the start are reported as new.")
+                    .default_value("10")
+                    .takes_value(true))
+            .arg(Arg::with_name("COMPARE_TO")
+                    .long("--compare-to")
+                    .help("For the diff report, a file or directory to compare the input files \
+                           against. May be repeated. Defaults to the input files themselves, \
+                           for comparing two time ranges.")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+            .arg(self.compare_lowest_helper.create_arg())
+            .arg(self.compare_highest_helper.create_arg())
+            .arg(Arg::with_name("CHANGE_FACTOR")
+                    .long("--change-factor")
+                    .help("In the diff report, show templates and source locations whose rate \
+                           per minute changed by at least this factor in either direction.")
+                    .default_value("2")
+                    .takes_value(true))
+            .arg(Arg::with_name("MIN_GAP")
+                    .long("--min-gap")
+                    .help("In the gaps report, the shortest silence to show, e.g. 30s or 5m.")
+                    .default_value("30s")
+                    .validator(duration_validator)
+                    .takes_value(true))
+            .arg(Arg::with_name("RULES_FILE")
+                    .long("--rules-file")
+                    .help("A TOML file of [[rules]], each with a name, a regex with named capture \
+                           groups, and optionally a fields table giving each group the type \
+                           string, int or float. Capture groups of matching messages become \
+                           fields that --field can filter on and that the lines report and its \
+                           CSV output include.")
+                    .takes_value(true))
+            .arg(Arg::with_name("FIELD")
+                    .long("--field")
+                    .help("Only keep lines with a field from --rules-file that satisfies this \
+                           condition, e.g. rpc_method=Write or duration_ms>=1000. Numbers are \
+                           compared numerically. May be repeated; all conditions must hold.")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(field_filter_validator)
+                    .requires("RULES_FILE")
+                    .takes_value(true))
+            .arg(Arg::with_name("WHERE")
+                    .long("--where")
+                    .help("Only keep lines for which this expression holds, e.g. \
+                           \"level >= W and node in ('n1', 'n2') and message ~ /timed out/ and \
+                           ts between '10:00' and '10:05'\". Columns are level, ts, tablet, node, \
+                           program, file, line, thread, message, first_line (the message \
+                           without its continuation lines) and the fields of --rules-file. \
+                           Operators are =, !=, <, <=, >, >=, ~ and !~ (regex match), [not] in \
+                           (...) and between ... and ..., combined with and, or, not and \
+                           parentheses. Timestamps may be a time of day, matching any date. As \
+                           with --line-contains, plain strings that first_line must match are \
+                           looked for in each raw line before parsing it, which makes \
+                           first_line ~ /.../ faster than message ~ /.../.")
+                    .takes_value(true))
             .get_matches();
 
-        let lowest_timestamp = get_timestamp_arg(matches.values_of("LOWEST_TIMESTAMP"));
-        let highest_timestamp = get_timestamp_arg(matches.values_of("HIGHEST_TIMESTAMP"));
-
         // See https://github.com/clap-rs/clap/pull/74/files
         let default_year: Option<i32> = match value_t!(matches.value_of("DEFAULT_YEAR"), i32) {
             Ok(year) => Some(year),
             Err(err) => { panic!("Error parsing DEFAULT_YEAR: {:?}", err) }
         };
-        let name_regex = match matches.values_of("NAME_REGEX") {
-            Some(mut values) => {
-                Some(parse_regex(values.next().unwrap()))
-            },
-            _ => None
+        let timestamp_base = FilterTimestampBase::current(default_year.unwrap());
+        let (lowest_timestamp, highest_timestamp) =
+                match get_timestamp_arg(matches.values_of("AROUND"), &timestamp_base) {
+            Some(around) => {
+                let window = matches.value_of("WINDOW")
+                    .map_or_else(|| Duration::minutes(5), |value| parse_duration(value).unwrap());
+                (Some(around - window), Some(around + window))
+            }
+            None => (
+                get_timestamp_arg(matches.values_of("LOWEST_TIMESTAMP"), &timestamp_base),
+                get_timestamp_arg(matches.values_of("HIGHEST_TIMESTAMP"), &timestamp_base),
+            ),
         };
-        let mut input_files: Vec<String> = match matches.values_of("INPUT_FILES") {
+        let name_regex = matches.values_of("NAME_REGEX").map(
+            |mut values| parse_regex(values.next().unwrap()));
+        let input_files: Vec<String> = match matches.values_of("INPUT_FILES") {
             Some(values) => {
-                values.map(|s| String::from(s)).collect()
+                values.map(String::from).collect()
             },
             _ => panic!("No input files specified"),
         };
-        let line_contains = match matches.values_of("LINE_CONTAINS") {
-            Some(mut values) => { Some(String::from(values.next().unwrap())) },
-            _ => None
+        let line_contains = matches.values_of("LINE_CONTAINS").map(
+            |mut values| String::from(values.next().unwrap()));
+        let parallel_chunk_mb = match value_t!(matches.value_of("PARALLEL_CHUNK_MB"), usize) {
+            Ok(mb) => mb,
+            Err(err) => { panic!("Error parsing PARALLEL_CHUNK_MB: {:?}", err) }
+        };
+        let max_memory = matches.value_of("MAX_MEMORY").map(
+            |value| parse_size(value).unwrap() as usize);
+        let spill_dir = match matches.value_of("SPILL_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::temp_dir(),
+        };
+        let report = ReportKind::from_name(matches.value_of("REPORT").unwrap());
+        let top = match value_t!(matches.value_of("TOP"), usize) {
+            Ok(top) => top,
+            Err(err) => { panic!("Error parsing TOP: {:?}", err) }
+        };
+        let per_node = matches.is_present("PER_NODE");
+        let per_tablet = matches.is_present("PER_TABLET");
+        let skew_correct = matches.is_present("SKEW_CORRECT");
+        let bucket = matches.value_of("BUCKET").map(|value| parse_duration(value).unwrap());
+        let csv_file = matches.value_of("CSV_FILE").map(String::from);
+        let spike_factor = match value_t!(matches.value_of("SPIKE_FACTOR"), f64) {
+            Ok(factor) => factor,
+            Err(err) => { panic!("Error parsing SPIKE_FACTOR: {:?}", err) }
+        };
+        let baseline_buckets = match value_t!(matches.value_of("BASELINE_BUCKETS"), usize) {
+            Ok(num_buckets) => num_buckets,
+            Err(err) => { panic!("Error parsing BASELINE_BUCKETS: {:?}", err) }
         };
+        let compare_to: Vec<String> = match matches.values_of("COMPARE_TO") {
+            Some(values) => values.map(String::from).collect(),
+            None => Vec::new(),
+        };
+        let compare_lowest_timestamp = get_timestamp_arg(
+            matches.values_of("COMPARE_LOWEST_TIMESTAMP"), &timestamp_base);
+        let compare_highest_timestamp = get_timestamp_arg(
+            matches.values_of("COMPARE_HIGHEST_TIMESTAMP"), &timestamp_base);
+        let change_factor = match value_t!(matches.value_of("CHANGE_FACTOR"), f64) {
+            Ok(factor) => factor,
+            Err(err) => { panic!("Error parsing CHANGE_FACTOR: {:?}", err) }
+        };
+        let min_gap = parse_duration(matches.value_of("MIN_GAP").unwrap()).unwrap();
+        let rules = matches.value_of("RULES_FILE").map(
+            |path| Arc::new(UserRules::load(path).unwrap_or_else(|e| panic!("{}", e))));
+        let field_filters: Vec<FieldFilter> = match matches.values_of("FIELD") {
+            Some(values) => values.map(|value| FieldFilter::parse(value).unwrap()).collect(),
+            None => Vec::new(),
+        };
+        if let Some(rules) = &rules {
+            let field_names = rules.field_names();
+            println!("Loaded rules {} with fields {}",
+                     rules.rule_names().join(", "), field_names.join(", "));
+            for filter in &field_filters {
+                if !field_names.contains(&filter.name.as_str()) {
+                    panic!("--field refers to {}, which no rule in the rules file defines. \
+                            Known fields: {}", filter.name, field_names.join(", "));
+                }
+            }
+        }
+        let where_clause = matches.value_of("WHERE").map(|value| Arc::new(
+            WhereClause::parse(value, rules.as_deref(), timestamp_base)
+                .unwrap_or_else(|e| panic!("{}", e))));
         ArgInfo {
             lowest_timestamp,
             highest_timestamp,
@@ -459,17 +1366,33 @@ impl ArgParsingHelper {
             input_files,
             name_regex,
             line_contains,
+            parallel_chunk_size: parallel_chunk_mb << 20,
+            max_memory,
+            spill_dir,
+            report,
+            top,
+            per_node,
+            per_tablet,
+            skew_correct,
+            bucket,
+            csv_file,
+            spike_factor,
+            baseline_buckets,
+            compare_to,
+            compare_lowest_timestamp,
+            compare_highest_timestamp,
+            change_factor,
+            min_gap,
+            rules,
+            field_filters,
+            where_clause,
         }
     }
 }
 
-// ------------------------------------------------------------------------------------------------
-// Main program
-// ------------------------------------------------------------------------------------------------
-fn main() {
-    let parsing_helper = ArgParsingHelper::new();
-    let arg_info = parsing_helper.parse_args();
-
+/// Expands directories among the input paths into the files they contain and applies
+/// --name-regex.
+fn find_input_files(arg_info: &ArgInfo) -> BTreeSet<OsString> {
     let mut input_files: BTreeSet<OsString> = BTreeSet::new();
 
     for input_file_str in arg_info.input_files.iter() {
@@ -514,27 +1437,50 @@ fn main() {
     } else {
         println!("--name-regex not specified");
     }
+    input_files
+}
 
+/// Reads all input files using a thread pool and returns the lines that pass the filters, in
+/// output order.
+fn load_lines(arg_info: ArgInfo, clock_offsets: &ClockOffsets) -> MergedLines {
+    let input_files = find_input_files(&arg_info);
 
     let mut readers = Vec::<YBLogReader>::new();
 
-    let output_collector_ptr = Arc::new(Mutex::new(OutputCollector::new()));
+    let output_collector_ptr = Arc::new(Mutex::new(OutputCollector::new(
+        arg_info.max_memory, arg_info.spill_dir.clone())));
 
     let reader_context = Arc::new(YBLogReaderContext {
-        regexes: RegexHolder::new(),
+        formats: builtin_log_formats(),
         arg_info,
         output_collector: output_collector_ptr.clone(),
+        clock_offsets: clock_offsets.clone(),
     });
 
+    let start_time = Instant::now();
     let cpus = num_cpus::get();
     let pool = ThreadPool::new(cpus);
 
+    let chunk_size = reader_context.arg_info.parallel_chunk_size;
+    let mut mapped_files = Vec::<MappedLogFile>::new();
+
     println!("Processing {} files", input_files.len());
     for input_file in input_files {
         let input_file_str = input_file.to_str().unwrap();
-        readers.push(YBLogReader::new(input_file_str, reader_context.clone()).unwrap());
+        if chunk_size > 0 && !input_file_str.ends_with(".gz") &&
+                metadata(input_file_str).unwrap().len() > chunk_size as u64 {
+            mapped_files.push(MappedLogFile::open(input_file_str, &reader_context).unwrap());
+        } else {
+            readers.push(YBLogReader::new(input_file_str, reader_context.clone()).unwrap());
+        }
     }
 
+    let (chunk_sender, chunk_receiver) = channel::<ParsedChunk>();
+    for (file_index, mapped_file) in mapped_files.iter().enumerate() {
+        mapped_file.submit_chunks(file_index, chunk_size, &reader_context, &pool, &chunk_sender);
+    }
+    drop(chunk_sender);
+
     for mut reader in readers {
         pool.execute(move || {
             reader.load();
@@ -542,14 +1488,363 @@ fn main() {
     }
 
     pool.join();
-    let guard = output_collector_ptr.lock().unwrap();
 
-    // TODO: can we get data out of a mutex without cloning it?
-    let mut lines = guard.output_lines.clone();
+    let mut chunks_by_file: Vec<Vec<ParsedChunk>> = mapped_files.iter().map(|_| Vec::new()).collect();
+    for chunk in chunk_receiver {
+        chunks_by_file[chunk.file_index].push(chunk);
+    }
+    for (mapped_file, chunks) in mapped_files.iter().zip(chunks_by_file) {
+        if !chunks.is_empty() {
+            let mut run = RunBuilder::new(&reader_context);
+            run.extend(mapped_file.stitch(chunks));
+            run.finish();
+        }
+    }
+    println!("Collected lines in {:.3} s", start_time.elapsed().as_secs_f64());
+
+    let merged_lines = output_collector_ptr.lock().unwrap().merged_lines();
+    merged_lines
+}
+
+// ------------------------------------------------------------------------------------------------
+// Main program
+// ------------------------------------------------------------------------------------------------
+fn main() {
+    let parsing_helper = ArgParsingHelper::new();
+    let arg_info = parsing_helper.parse_args();
+    let start_time = Instant::now();
+
+    // Skew correction needs a first pass over the logs to estimate the clock offsets.
+    let clock_offsets = if arg_info.skew_correct {
+        let estimate = clock_skew::SkewEstimator::estimate(
+            load_lines(arg_info.skew_estimation_side(), &ClockOffsets::new()));
+        estimate.print_offsets();
+        estimate.offsets()
+    } else {
+        ClockOffsets::new()
+    };
+    let load = |arg_info: ArgInfo| load_lines(arg_info, &clock_offsets);
+
+    match arg_info.report {
+        ReportKind::Lines => {
+            let mut csv_writer = arg_info.csv_file.as_deref().map(|csv_file| {
+                let field_names = arg_info.rules.as_ref().map_or(Vec::new(), |rules| {
+                    rules.field_names().into_iter().map(String::from).collect()
+                });
+                lines_csv::LinesCsvWriter::create(csv_file, field_names).unwrap_or_else(
+                    |e| panic!("Could not write CSV file {}: {}", csv_file, e))
+            });
+            let mut num_output_lines: usize = 0;
+            for line in load(arg_info.clone()) {
+                println!("Output line: {:?}", line);
+                if !line.fields.is_empty() {
+                    let formatted: Vec<String> = line.fields.iter()
+                        .map(|(name, value)| format!("{}={}", name, value)).collect();
+                    println!("  fields: {}", formatted.join(", "));
+                }
+                if let Some(csv_writer) = &mut csv_writer {
+                    csv_writer.write(&line, &line.fields).unwrap();
+                }
+                num_output_lines += 1;
+            }
+            if let (Some(csv_writer), Some(csv_file)) = (csv_writer, &arg_info.csv_file) {
+                csv_writer.finish().unwrap();
+                println!("Wrote lines CSV to {}", csv_file);
+            }
+            let total_elapsed = start_time.elapsed();
+            println!("Printed {} lines in {:.3} s ({:.0} lines/s overall)",
+                     num_output_lines,
+                     total_elapsed.as_secs_f64(),
+                     num_output_lines as f64 / total_elapsed.as_secs_f64());
+        }
+        ReportKind::Patterns => patterns::print_patterns_report(
+            load(arg_info.clone()), arg_info.top),
+        ReportKind::TopSources => top_sources::print_top_sources_report(
+            load(arg_info.clone()), arg_info.top, arg_info.per_node, arg_info.bucket),
+        ReportKind::Histogram => histogram::print_histogram_report(
+            load(arg_info.clone()),
+            arg_info.bucket.unwrap_or_else(|| Duration::minutes(1)),
+            arg_info.csv_file.as_deref()),
+        ReportKind::Anomalies => anomalies::print_anomalies_report(
+            load(arg_info.clone()),
+            &anomalies::AnomalyOptions {
+                bucket: arg_info.bucket.unwrap_or_else(|| Duration::minutes(1)),
+                spike_factor: arg_info.spike_factor,
+                baseline_buckets: arg_info.baseline_buckets,
+                top: arg_info.top,
+            }),
+        ReportKind::Diff => {
+            let comparison_arg_info = arg_info.comparison_side();
+            // Side A is fully consumed before side B is loaded, to keep memory usage down.
+            let mut log_diff = diff::LogDiff::new();
+            log_diff.add_lines(diff::Side::A, load(arg_info.clone()));
+            log_diff.add_lines(diff::Side::B, load(comparison_arg_info));
+            log_diff.print(arg_info.top, arg_info.change_factor);
+        }
+        ReportKind::Restarts => restarts::print_restarts_report(
+            load(arg_info.clone()), arg_info.lowest_timestamp, arg_info.highest_timestamp),
+        ReportKind::Inventory => inventory::print_inventory_report(load(arg_info.clone())),
+        ReportKind::Gaps => gaps::print_gaps_report(
+            load(arg_info.clone()), arg_info.min_gap, arg_info.top),
+        ReportKind::Latency => latency::print_latency_report(
+            load(arg_info.clone()),
+            arg_info.top,
+            arg_info.per_node,
+            arg_info.per_tablet,
+            arg_info.bucket),
+        ReportKind::Compactions => compactions::print_compactions_report(
+            load(arg_info.clone()),
+            arg_info.top,
+            arg_info.bucket.unwrap_or_else(|| Duration::minutes(1))),
+        ReportKind::Memory => memory::print_memory_report(
+            load(arg_info.clone()),
+            arg_info.top,
+            arg_info.bucket.unwrap_or_else(|| Duration::minutes(1))),
+        ReportKind::Balancer => balancer::print_balancer_report(load(arg_info.clone())),
+        ReportKind::TabletLifecycle => tablet_lifecycle::print_tablet_lifecycle_report(
+            load(arg_info.clone()), arg_info.top),
+        ReportKind::Liveness => liveness::print_liveness_report(load(arg_info.clone())),
+        ReportKind::Skew => clock_skew::SkewEstimator::estimate(load(arg_info.clone())).print(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    const PREAMBLE: &str = "\
+Log file created at: 2021/04/08 10:00:00
+Running on machine: node-1
+Application fingerprint: version 2.4.1.1 build 4 revision 1b7bb2fc3b910912ef758ffca83b076124051c10 build_type RELEASE built at 30 Mar 2021 16:14:23 UTC
+Running duration (h:mm:ss): 0:00:00
+Log line format: [IWEF]mmdd hh:mm:ss.uuuuuu threadid file:line] msg
+";
+
+    const ENTRIES: &str = "\
+W0408 10:00:01.671530  1020 tablet_peer.cc:22] Flush completed, 991188 bytes
+F0408 10:00:02.754355  1014 raft_consensus.cc:236] Check failed: leader_uuid != ''
+*** Check failure stack trace: ***
+    @     0x7f1234567890  yb::consensus::RaftConsensus::Update()
+    @     0x7f1234567891  yb::tserver::ConsensusServiceImpl::UpdateConsensus()
+    @     0x7f1234567892  yb::rpc::ServicePoolImpl::Handle()
+I0408 10:00:04.276250  1047 raft_consensus.cc:738] Starting election for term 71
+I0408 10:00:05.000001  1047 raft_consensus.cc:740] Election for term 71 won
+";
+
+    fn test_log() -> String {
+        format!("{}{}", PREAMBLE, ENTRIES)
+    }
 
-    lines.sort_by(|a: &YBLogLine, b: &YBLogLine| a.timestamp.partial_cmp(&b.timestamp).unwrap());
+    fn test_arg_info(line_contains: Option<&str>) -> ArgInfo {
+        ArgInfo {
+            lowest_timestamp: None,
+            highest_timestamp: None,
+            default_year: Some(2021),
+            input_files: Vec::new(),
+            name_regex: None,
+            line_contains: line_contains.map(String::from),
+            parallel_chunk_size: 0,
+            max_memory: None,
+            spill_dir: std::env::temp_dir(),
+            report: ReportKind::Lines,
+            top: 10,
+            per_node: false,
+            per_tablet: false,
+            skew_correct: false,
+            bucket: None,
+            csv_file: None,
+            spike_factor: 5.0,
+            baseline_buckets: 10,
+            compare_to: Vec::new(),
+            compare_lowest_timestamp: None,
+            compare_highest_timestamp: None,
+            change_factor: 2.0,
+            min_gap: Duration::seconds(30),
+            rules: None,
+            field_filters: Vec::new(),
+            where_clause: None,
+        }
+    }
+
+    /// A log file in the temporary directory, removed when dropped.
+    struct TestLogFile {
+        path: PathBuf,
+    }
+
+    impl TestLogFile {
+        fn create(name: &str, contents: &[u8]) -> TestLogFile {
+            let path = std::env::temp_dir().join(
+                format!("yblp-test-{}-{}.INFO", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TestLogFile { path }
+        }
+
+        fn path(&self) -> &str {
+            self.path.to_str().unwrap()
+        }
+
+        fn len(&self) -> usize {
+            metadata(&self.path).unwrap().len() as usize
+        }
+    }
+
+    impl Drop for TestLogFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn test_context(arg_info: ArgInfo) -> Arc<YBLogReaderContext> {
+        Arc::new(YBLogReaderContext {
+            formats: builtin_log_formats(),
+            output_collector: Arc::new(Mutex::new(OutputCollector::new(
+                arg_info.max_memory, arg_info.spill_dir.clone()))),
+            arg_info,
+            clock_offsets: ClockOffsets::new(),
+        })
+    }
+
+    fn collected_lines(context: &YBLogReaderContext) -> Vec<String> {
+        context.output_collector.lock().unwrap().merged_lines()
+            .map(|line| format!("{:?}", line))
+            .collect()
+    }
+
+    fn parse_sequentially(file: &TestLogFile, arg_info: ArgInfo) -> Vec<String> {
+        let context = test_context(arg_info);
+        YBLogReader::new(file.path(), context.clone()).unwrap().load();
+        collected_lines(&context)
+    }
+
+    /// Parses the file in chunks of `chunk_size` bytes the way `load_lines` does.
+    fn parse_in_chunks(file: &TestLogFile, arg_info: ArgInfo, chunk_size: usize) -> Vec<String> {
+        let context = test_context(arg_info);
+        let mapped_file = MappedLogFile::open(file.path(), &context).unwrap();
+        let pool = ThreadPool::new(2);
+        let (chunk_sender, chunk_receiver) = channel::<ParsedChunk>();
+        mapped_file.submit_chunks(0, chunk_size, &context, &pool, &chunk_sender);
+        drop(chunk_sender);
+        pool.join();
+        let mut run = RunBuilder::new(&context);
+        run.extend(mapped_file.stitch(chunk_receiver.into_iter().collect()));
+        run.finish();
+        collected_lines(&context)
+    }
+
+    fn assert_same_for_all_chunk_sizes(file: &TestLogFile, line_contains: Option<&str>) {
+        let expected = parse_sequentially(file, test_arg_info(line_contains));
+        // Building the formats' regexes dominates, so not every chunk size is worth trying.
+        for chunk_size in (1..=file.len() + 1).step_by(16) {
+            assert_eq!(parse_in_chunks(file, test_arg_info(line_contains), chunk_size), expected,
+                       "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn run_builder_counts_the_entry_that_starts_a_new_run() {
+        let file = TestLogFile::create("run-builder", test_log().as_bytes());
+        let context = test_context(test_arg_info(None));
+        let mapped_file = MappedLogFile::open(file.path(), &context).unwrap();
+        let source = OutputCollector::register_source(&context, file.path(), &mapped_file.preamble);
+        let entry = YBLogLine {
+            log_level: 'I',
+            timestamp: NaiveDate::from_ymd_opt(2021, 4, 8).unwrap().and_hms_opt(10, 0, 0).unwrap(),
+            thread_id: 1047,
+            file_name: String::from("raft_consensus.cc"),
+            line_number: 738,
+            tablet_id: None,
+            message: String::from("Starting election for term 71"),
+            source,
+            file_offset: 0,
+            fields: Fields::new(),
+        };
+        let entry_bytes = estimated_line_size(&entry);
+
+        let mut run = RunBuilder::new(&context);
+        run.max_bytes = Some(2 * entry_bytes);
+        run.extend(vec![entry.clone(), entry.clone()]);
+        assert_eq!((run.lines.len(), run.bytes), (2, 2 * entry_bytes));
+        run.extend(Some(entry));
+        assert_eq!((run.lines.len(), run.bytes), (1, entry_bytes));
+        run.finish();
+        assert_eq!(collected_lines(&context).len(), 3);
+    }
+
+    #[test]
+    fn chunk_ranges_end_after_newlines() {
+        let file = TestLogFile::create("ranges", test_log().as_bytes());
+        let context = test_context(test_arg_info(None));
+        let mapped_file = MappedLogFile::open(file.path(), &context).unwrap();
+        for chunk_size in 1..=file.len() {
+            let ranges = mapped_file.chunk_ranges(chunk_size);
+            assert_eq!(ranges.first().unwrap().start, 0);
+            assert_eq!(ranges.last().unwrap().end, file.len());
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].end, pair[1].start);
+                assert!(pair[0].len() >= chunk_size);
+                assert_eq!(mapped_file.mmap[pair[0].end - 1], b'\n');
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_size_larger_than_file() {
+        let file = TestLogFile::create("large-chunk", test_log().as_bytes());
+        let context = test_context(test_arg_info(None));
+        let mapped_file = MappedLogFile::open(file.path(), &context).unwrap();
+        assert_eq!(mapped_file.chunk_ranges(file.len() * 2), vec![0..file.len()]);
+        assert_eq!(parse_in_chunks(&file, test_arg_info(None), file.len() * 2),
+                   parse_sequentially(&file, test_arg_info(None)));
+    }
+
+    #[test]
+    fn chunk_boundary_inside_multi_line_message() {
+        let file = TestLogFile::create("multi-line", test_log().as_bytes());
+        let lines = parse_sequentially(&file, test_arg_info(None));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].contains("Check failure stack trace: ***\\n"));
+        assert!(lines[1].contains("ServicePoolImpl::Handle()\""));
+        assert_same_for_all_chunk_sizes(&file, None);
+    }
+
+    #[test]
+    fn chunk_of_only_continuation_lines() {
+        // Each frame is longer than the chunk size, so the frames between the FATAL line and the
+        // next entry each end up in a chunk of their own.
+        let file = TestLogFile::create("continuation", test_log().as_bytes());
+        let chunk_size = 16;
+        let context = test_context(test_arg_info(None));
+        let mapped_file = MappedLogFile::open(file.path(), &context).unwrap();
+        let only_frames = mapped_file.chunk_ranges(chunk_size).into_iter()
+            .filter(|range| mapped_file.mmap[range.clone()].starts_with(b"    @"))
+            .count();
+        assert_eq!(only_frames, 3);
+        assert_eq!(parse_in_chunks(&file, test_arg_info(None), chunk_size),
+                   parse_sequentially(&file, test_arg_info(None)));
+    }
+
+    #[test]
+    fn first_entry_rejected_by_filter() {
+        // The first entry and its continuation lines are filtered out, at any chunk boundary.
+        let file = TestLogFile::create(
+            "rejected", format!("{}{}", PREAMBLE, &ENTRIES[ENTRIES.find('\n').unwrap() + 1..])
+                .as_bytes());
+        let lines = parse_sequentially(&file, test_arg_info(Some("election")));
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("Starting election for term 71\""));
+        assert_same_for_all_chunk_sizes(&file, Some("election"));
+    }
 
-    for line in &lines {
-        println!("Output line: {:?}", line);
+    #[test]
+    fn invalid_utf8_is_replaced_in_chunks() {
+        let mut contents = test_log().into_bytes();
+        let position = contents.windows(5).position(|w| w == b"Flush").unwrap();
+        contents[position] = 0xff;
+        let file = TestLogFile::create("invalid-utf8", &contents);
+        let lines = parse_in_chunks(&file, test_arg_info(None), 64);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("\u{fffd}lush completed"));
     }
 }
@@ -0,0 +1,347 @@
+// ------------------------------------------------------------------------------------------------
+// Memory events: soft and hard memory limit messages, writes rejected under memory pressure,
+// memory tracker consumption and tcmalloc stats, as a time series per node.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use regex::Regex;
+
+use yblp::{bucket_start, parse_regex};
+
+use crate::YBLogLine;
+
+struct MemoryRegexes {
+    /// Soft memory limit exceeded (at 93.28% of capacity)
+    soft_limit_re: Regex,
+    hard_limit_re: Regex,
+    rejection_re: Regex,
+    /// A memory tracker in a consumption dump, e.g. "  Tablets: consumption 1.20G" or
+    /// "root  current: 3.10G  peak: 3.50G  limit: 4.00G".
+    tracker_re: Regex,
+    /// MALLOC:   123456789 (  117.7 MiB) Bytes in use by application
+    tcmalloc_in_use_re: Regex,
+}
+
+impl MemoryRegexes {
+    fn new() -> MemoryRegexes {
+        MemoryRegexes {
+            soft_limit_re: parse_regex(
+                r"Soft memory limit exceeded(?: \(at ([\d.]+)% of capacity\))?"),
+            hard_limit_re: parse_regex(r"(?i)hard memory limit exceeded"),
+            rejection_re: parse_regex(
+                r"(?i)(?:rejecting|rejected) .*(?:memory limit|memory pressure)"),
+            tracker_re: parse_regex(concat!(
+                r"^\s*(?P<name>[\w][\w\-.>/ ]*?):?\s+(?:consumption|current)[:=]?\s*",
+                r"(?P<value>[\d.]+)\s*(?P<unit>[KMGT]?)(?:i?B)?\b"
+            )),
+            tcmalloc_in_use_re: parse_regex(
+                r"^MALLOC:\s+(\d+) \(.*\) Bytes in use by application"),
+        }
+    }
+}
+
+fn size_in_bytes(value: f64, unit: &str) -> u64 {
+    let shift = match unit {
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => 0,
+    };
+    (value * (1u64 << shift) as f64) as u64
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Memory activity of one node in one time bucket.
+#[derive(Default)]
+struct MemoryBucket {
+    soft_limit_events: u64,
+    max_soft_limit_percent: Option<f64>,
+    hard_limit_events: u64,
+    rejections: u64,
+    max_tcmalloc_in_use: Option<u64>,
+    /// Largest consumption reported for the root memory tracker.
+    max_root_consumption: Option<u64>,
+}
+
+impl MemoryBucket {
+    fn under_pressure(&self) -> bool {
+        self.soft_limit_events > 0 || self.hard_limit_events > 0 || self.rejections > 0
+    }
+}
+
+/// Consecutive buckets of one node with memory pressure.
+struct PressurePeriod {
+    node: String,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    soft_limit_events: u64,
+    hard_limit_events: u64,
+    rejections: u64,
+    max_soft_limit_percent: Option<f64>,
+}
+
+fn max_option<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b > a { b } else { a }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn format_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::from("-"), |value| value.to_string())
+}
+
+/// Collects memory events into per-node buckets, and the largest consumption logged for each
+/// memory tracker.
+struct MemoryCollector {
+    regexes: MemoryRegexes,
+    bucket: Duration,
+    buckets: BTreeMap<(String, NaiveDateTime), MemoryBucket>,
+    /// Keyed by (tracker name, node), the largest consumption and when it was logged.
+    consumers: HashMap<(String, String), (u64, NaiveDateTime)>,
+}
+
+impl MemoryCollector {
+    fn new(bucket: Duration) -> MemoryCollector {
+        MemoryCollector {
+            regexes: MemoryRegexes::new(),
+            bucket,
+            buckets: BTreeMap::new(),
+            consumers: HashMap::new(),
+        }
+    }
+
+    fn add_line(&mut self, line: &YBLogLine) {
+        let regexes = &self.regexes;
+        let node = line.source.node.clone().unwrap_or_default();
+        // Numbers such as "2.4.1.1" match the number patterns but are not values, so they are
+        // treated as absent rather than parsed.
+        let soft_limit_percent: Option<Option<f64>> = regexes.soft_limit_re.captures(&line.message)
+            .map(|captures| captures.get(1).and_then(|m| m.as_str().parse().ok()));
+        let hard_limit = regexes.hard_limit_re.is_match(&line.message);
+        let rejection = regexes.rejection_re.is_match(&line.message);
+        // Tracker dumps and tcmalloc stats span continuation lines. The first line of a limit
+        // event, e.g. "Hard memory limit exceeded: consumption 4.10G ...", is not a tracker.
+        let is_limit_event = soft_limit_percent.is_some() || hard_limit || rejection;
+        let mut tcmalloc_in_use: Option<u64> = None;
+        let mut root_consumption: Option<u64> = None;
+        for message_line in line.message.lines().skip(if is_limit_event { 1 } else { 0 }) {
+            if let Some(captures) = regexes.tcmalloc_in_use_re.captures(message_line) {
+                tcmalloc_in_use = max_option(
+                    tcmalloc_in_use, captures.get(1).unwrap().as_str().parse().ok());
+            } else if let Some(captures) = regexes.tracker_re.captures(message_line) {
+                let name = captures.name("name").unwrap().as_str().trim();
+                let value: f64 = match captures.name("value").unwrap().as_str().parse() {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                let consumption = size_in_bytes(value, captures.name("unit").unwrap().as_str());
+                if name == "root" {
+                    root_consumption = max_option(root_consumption, Some(consumption));
+                }
+                let max = self.consumers.entry((String::from(name), node.clone()))
+                    .or_insert((consumption, line.timestamp));
+                if consumption > max.0 {
+                    *max = (consumption, line.timestamp);
+                }
+            }
+        }
+        if !is_limit_event && tcmalloc_in_use.is_none() && root_consumption.is_none() {
+            return;
+        }
+
+        let entry = self.buckets.entry((node, bucket_start(line.timestamp, self.bucket)))
+            .or_default();
+        if let Some(percent) = soft_limit_percent {
+            entry.soft_limit_events += 1;
+            entry.max_soft_limit_percent = max_option(entry.max_soft_limit_percent, percent);
+        }
+        if hard_limit {
+            entry.hard_limit_events += 1;
+        }
+        if rejection {
+            entry.rejections += 1;
+        }
+        entry.max_tcmalloc_in_use = max_option(entry.max_tcmalloc_in_use, tcmalloc_in_use);
+        entry.max_root_consumption = max_option(entry.max_root_consumption, root_consumption);
+    }
+
+    /// Merges consecutive buckets of a node with memory pressure into periods.
+    fn pressure_periods(&self) -> Vec<PressurePeriod> {
+        // Buckets are ordered by node, then time, so each node's periods come out in order.
+        let mut periods: Vec<PressurePeriod> = Vec::new();
+        for ((node, bucket_ts), entry) in
+                self.buckets.iter().filter(|(_, entry)| entry.under_pressure()) {
+            let bucket_end = *bucket_ts + self.bucket;
+            match periods.last_mut() {
+                Some(period) if &period.node == node && period.to == *bucket_ts => {
+                    period.to = bucket_end;
+                    period.soft_limit_events += entry.soft_limit_events;
+                    period.hard_limit_events += entry.hard_limit_events;
+                    period.rejections += entry.rejections;
+                    period.max_soft_limit_percent =
+                        max_option(period.max_soft_limit_percent, entry.max_soft_limit_percent);
+                }
+                _ => periods.push(PressurePeriod {
+                    node: node.clone(),
+                    from: *bucket_ts,
+                    to: bucket_end,
+                    soft_limit_events: entry.soft_limit_events,
+                    hard_limit_events: entry.hard_limit_events,
+                    rejections: entry.rejections,
+                    max_soft_limit_percent: entry.max_soft_limit_percent,
+                }),
+            }
+        }
+        periods
+    }
+}
+
+/// Prints the memory time series per node and `bucket`, the periods with memory pressure, and
+/// the `top` memory trackers by the largest consumption logged for them.
+pub(crate) fn print_memory_report<I: Iterator<Item = YBLogLine>>(
+        lines: I, top: usize, bucket: Duration) {
+    let mut collector = MemoryCollector::new(bucket);
+    for line in lines {
+        collector.add_line(&line);
+    }
+
+    println!("Memory events per node and {} s bucket", bucket.num_seconds());
+    println!("{:<24}  {:<21}  {:>10}  {:>7}  {:>10}  {:>10}  {:>14}  {:>12}",
+             "node", "bucket", "soft limit", "max %", "hard limit", "rejections",
+             "tcmalloc MB", "root MB");
+    for ((node, bucket_ts), entry) in &collector.buckets {
+        println!("{:<24}  {:<21}  {:>10}  {:>7}  {:>10}  {:>10}  {:>14}  {:>12}",
+                 node, bucket_ts.to_string(), entry.soft_limit_events,
+                 format_option(entry.max_soft_limit_percent.map(|p| format!("{:.2}", p))),
+                 entry.hard_limit_events, entry.rejections,
+                 format_option(entry.max_tcmalloc_in_use.map(|b| format!("{:.1}", megabytes(b)))),
+                 format_option(entry.max_root_consumption.map(|b| format!("{:.1}", megabytes(b)))));
+    }
+
+    let periods = collector.pressure_periods();
+    println!();
+    println!("Periods of memory pressure ({})", periods.len());
+    println!("{:<24}  {:<21}  {:<21}  {:>10}  {:>7}  {:>10}  {:>10}",
+             "node", "from", "to", "soft limit", "max %", "hard limit", "rejections");
+    for period in &periods {
+        println!("{:<24}  {:<21}  {:<21}  {:>10}  {:>7}  {:>10}  {:>10}",
+                 period.node, period.from.to_string(), period.to.to_string(),
+                 period.soft_limit_events,
+                 format_option(period.max_soft_limit_percent.map(|p| format!("{:.2}", p))),
+                 period.hard_limit_events, period.rejections);
+    }
+
+    let mut consumer_rows: Vec<((String, String), (u64, NaiveDateTime))> =
+        collector.consumers.into_iter().collect();
+    consumer_rows.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then_with(|| a.0.cmp(&b.0)));
+    println!();
+    println!("Top memory consumers by largest logged consumption (showing up to {} of {})",
+             top, consumer_rows.len());
+    println!("{:>12}  {:<26}  {:<24}  tracker", "MB", "logged at", "node");
+    for ((name, node), (consumption, timestamp)) in consumer_rows.iter().take(top) {
+        println!("{:>12.1}  {:<26}  {:<24}  {}",
+                 megabytes(*consumption), timestamp.to_string(), node, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    fn collect(lines: &[(&str, &str)]) -> MemoryCollector {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let n2 = source(1, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        let mut collector = MemoryCollector::new(Duration::minutes(1));
+        for (node, text) in lines {
+            collector.add_line(&glog_line(if *node == "n1" { &n1 } else { &n2 }, text));
+        }
+        collector
+    }
+
+    #[test]
+    fn limit_events_trackers_and_tcmalloc_stats() {
+        let collector = collect(&[
+            ("n1", "W0408 10:00:01.000000  1240 tablet_service.cc:345] \
+                    Soft memory limit exceeded (at 93.28% of capacity), score: 0.87"),
+            ("n1", "W0408 10:00:30.000000  1241 tablet_service.cc:351] \
+                    Rejecting Write request: Soft memory limit exceeded \
+                    (at 95.12% of capacity), score: 0.95 [suppressed 12 similar messages]"),
+            ("n1", "I0408 10:00:40.000000  1234 mem_tracker.cc:282] Memory usage:\n\
+                    root  current: 3.10G  peak: 3.50G  limit: 4.00G\n\
+                    \x20 Tablets: consumption 1.20G\n\
+                    \x20 Call: consumption 12.5M"),
+            ("n1", "I0408 10:00:45.000000  1234 tcmalloc_util.cc:80] tcmalloc stats:\n\
+                    ------------------------------------------------\n\
+                    MALLOC:     3328599896 ( 3174.4 MiB) Bytes in use by application"),
+            ("n1", "I0408 10:00:50.000000  1234 tablet_server.cc:100] \
+                    Running on version 2.4.1.1"),
+            ("n2", "W0408 10:00:50.000000  5690 tablet_service.cc:345] \
+                    Soft memory limit exceeded (at 90.00% of capacity), score: 0.50"),
+            ("n1", "E0408 10:01:10.000000  1240 mem_tracker.cc:300] \
+                    Hard memory limit exceeded: consumption 4.10G exceeds limit 4.00G"),
+        ]);
+
+        let summary: Vec<_> = collector.buckets.iter()
+            .map(|((node, bucket_ts), entry)| (
+                node.as_str(), bucket_ts.time().to_string(), entry.soft_limit_events,
+                entry.max_soft_limit_percent, entry.hard_limit_events, entry.rejections,
+                entry.max_tcmalloc_in_use, entry.max_root_consumption))
+            .collect();
+        assert_eq!(summary, [
+            ("n1", String::from("10:00:00"), 2, Some(95.12), 0, 1, Some(3328599896),
+             Some(3328599654)),
+            ("n1", String::from("10:01:00"), 0, None, 1, 0, None, None),
+            ("n2", String::from("10:00:00"), 1, Some(90.0), 0, 0, None, None),
+        ]);
+
+        let mut consumers: Vec<(&str, &str, u64)> = collector.consumers.iter()
+            .map(|((name, node), (consumption, _))| (name.as_str(), node.as_str(), *consumption))
+            .collect();
+        consumers.sort();
+        assert_eq!(consumers, [
+            ("Call", "n1", 13107200),
+            ("Tablets", "n1", 1288490188),
+            ("root", "n1", 3328599654),
+        ]);
+    }
+
+    #[test]
+    fn consecutive_buckets_under_pressure_form_a_period() {
+        let collector = collect(&[
+            ("n1", "W0408 10:00:01.000000  1240 tablet_service.cc:345] \
+                    Soft memory limit exceeded (at 93.28% of capacity), score: 0.87"),
+            ("n1", "E0408 10:01:10.000000  1240 mem_tracker.cc:300] \
+                    Hard memory limit exceeded: consumption 4.10G exceeds limit 4.00G"),
+            ("n1", "W0408 10:03:00.000000  1240 tablet_service.cc:345] \
+                    Soft memory limit exceeded"),
+            ("n2", "W0408 10:01:50.000000  5690 tablet_service.cc:345] \
+                    Soft memory limit exceeded (at 90.00% of capacity), score: 0.50"),
+            // Stats alone are not memory pressure.
+            ("n2", "I0408 10:02:30.000000  5678 tcmalloc_util.cc:80] tcmalloc stats:\n\
+                    MALLOC:     3328599896 ( 3174.4 MiB) Bytes in use by application"),
+        ]);
+        let periods = collector.pressure_periods();
+        let periods: Vec<_> = periods.iter()
+            .map(|period| (period.node.as_str(), period.from.time().to_string(),
+                           period.to.time().to_string(), period.soft_limit_events,
+                           period.hard_limit_events, period.max_soft_limit_percent))
+            .collect();
+        let period = |node, from: &str, to: &str, soft, hard, percent| {
+            (node, String::from(from), String::from(to), soft, hard, percent)
+        };
+        assert_eq!(periods, [
+            period("n1", "10:00:00", "10:02:00", 1, 1, Some(93.28)),
+            period("n1", "10:03:00", "10:04:00", 1, 0, None),
+            period("n2", "10:01:00", "10:02:00", 1, 0, Some(90.0)),
+        ]);
+    }
+}
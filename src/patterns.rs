@@ -0,0 +1,303 @@
+// ------------------------------------------------------------------------------------------------
+// Log template mining: clusters messages into patterns, in the spirit of the Drain algorithm.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::NaiveDateTime;
+use regex::{Captures, Regex};
+
+use yblp::parse_regex;
+
+use crate::processes::without_severity_copies;
+use crate::YBLogLine;
+
+/// The token that replaces the variable parts of messages sharing a template.
+const WILDCARD: &str = "<*>";
+
+/// Messages are only merged into the same template if at least this fraction of their tokens
+/// are equal, position by position.
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+/// Replaces identifiers, addresses, durations and numbers in a message with placeholders, so
+/// that messages differing only in these values have the same tokens.
+pub(crate) struct MessageMasker {
+    mask_re: Regex,
+}
+
+impl MessageMasker {
+    pub(crate) fn new() -> MessageMasker {
+        MessageMasker {
+            mask_re: parse_regex(concat!(
+                r"(?P<uuid>\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-",
+                r"[0-9a-fA-F]{12}\b)",
+                r"|(?P<id>\b[0-9a-f]{32}\b)",
+                r"|(?P<host>\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b",
+                r"|\b[a-zA-Z][\w-]*(?:\.[\w-]+)+:\d+\b)",
+                r"|(?P<duration>\b\d+(?:\.\d+)?\s?(?:ns|us|ms|s|sec|secs|seconds|min|h)\b)",
+                r"|(?P<hex>\b0x[0-9a-fA-F]+\b)",
+                r"|(?P<num>-?\b\d+(?:\.\d+)?\b)",
+            )),
+        }
+    }
+
+    /// Masks the first line of a message. Continuation lines (e.g. stack traces) are ignored.
+    pub(crate) fn mask(&self, message: &str) -> String {
+        let first_line = message.lines().next().unwrap_or("");
+        self.mask_re.replace_all(first_line, |captures: &Captures| {
+            for name in &["uuid", "id", "host", "duration", "hex", "num"] {
+                if captures.name(name).is_some() {
+                    return format!("<{}>", name);
+                }
+            }
+            String::from(WILDCARD)
+        }).into_owned()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct PatternStats {
+    pub(crate) count: u64,
+    pub(crate) first_timestamp: NaiveDateTime,
+    pub(crate) last_timestamp: NaiveDateTime,
+    pub(crate) level_counts: BTreeMap<char, u64>,
+    pub(crate) nodes: BTreeSet<String>,
+}
+
+impl PatternStats {
+    fn new(line: &YBLogLine) -> PatternStats {
+        PatternStats {
+            count: 0,
+            first_timestamp: line.timestamp,
+            last_timestamp: line.timestamp,
+            level_counts: BTreeMap::new(),
+            nodes: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, line: &YBLogLine) {
+        self.count += 1;
+        if line.timestamp < self.first_timestamp {
+            self.first_timestamp = line.timestamp;
+        }
+        if line.timestamp > self.last_timestamp {
+            self.last_timestamp = line.timestamp;
+        }
+        *self.level_counts.entry(line.log_level).or_insert(0) += 1;
+        let node = line.source.node.as_deref().unwrap_or("?");
+        if !self.nodes.contains(node) {
+            self.nodes.insert(String::from(node));
+        }
+    }
+
+    pub(crate) fn levels_str(&self) -> String {
+        self.level_counts.iter()
+            .map(|(level, count)| format!("{}:{}", level, count))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+struct Cluster {
+    template: Vec<String>,
+    stats: PatternStats,
+}
+
+impl Cluster {
+    /// Fraction of positions where the template has the same, non-wildcard, token.
+    fn similarity(&self, tokens: &[&str]) -> f64 {
+        let num_equal = self.template.iter().zip(tokens)
+            .filter(|(template_token, token)| template_token.as_str() == **token &&
+                                              template_token.as_str() != WILDCARD)
+            .count();
+        num_equal as f64 / tokens.len() as f64
+    }
+
+    fn merge(&mut self, tokens: &[&str]) {
+        for (template_token, token) in self.template.iter_mut().zip(tokens) {
+            if template_token.as_str() != *token {
+                *template_token = String::from(WILDCARD);
+            }
+        }
+    }
+
+    fn template_str(&self) -> String {
+        self.template.join(" ")
+    }
+}
+
+/// Groups messages into templates. Like Drain, messages are first partitioned by their number of
+/// tokens and their first token, and within a partition each message joins the most similar
+/// existing template, turning the tokens that differ into wildcards.
+pub(crate) struct TemplateMiner {
+    masker: MessageMasker,
+    partitions: HashMap<(usize, String), Vec<usize>>,
+    clusters: Vec<Cluster>,
+}
+
+impl TemplateMiner {
+    pub(crate) fn new() -> TemplateMiner {
+        TemplateMiner {
+            masker: MessageMasker::new(),
+            partitions: HashMap::new(),
+            clusters: Vec::new(),
+        }
+    }
+
+    /// Adds a line and returns the id of the template it was assigned to. Ids are stable, but the
+    /// text of a template may become more general as more lines are added.
+    pub(crate) fn add(&mut self, line: &YBLogLine) -> usize {
+        let masked = self.masker.mask(&line.message);
+        let tokens: Vec<&str> = masked.split_whitespace().collect();
+        let first_token = match tokens.first() {
+            Some(token) if !token.starts_with('<') => String::from(*token),
+            _ => String::from(WILDCARD),
+        };
+        let partition = self.partitions.entry((tokens.len(), first_token)).or_default();
+
+        let mut best_match: Option<(usize, f64)> = None;
+        for &cluster_id in partition.iter() {
+            let similarity = self.clusters[cluster_id].similarity(&tokens);
+            let is_better = match best_match {
+                Some((_, best_similarity)) => similarity > best_similarity,
+                None => true,
+            };
+            if is_better {
+                best_match = Some((cluster_id, similarity));
+            }
+        }
+        let cluster_id = match best_match {
+            Some((cluster_id, similarity))
+                    if tokens.is_empty() || similarity >= SIMILARITY_THRESHOLD => {
+                self.clusters[cluster_id].merge(&tokens);
+                cluster_id
+            }
+            _ => {
+                let cluster_id = self.clusters.len();
+                self.clusters.push(Cluster {
+                    template: tokens.iter().map(|token| String::from(*token)).collect(),
+                    stats: PatternStats::new(line),
+                });
+                partition.push(cluster_id);
+                cluster_id
+            }
+        };
+        self.clusters[cluster_id].stats.add(line);
+        cluster_id
+    }
+
+    pub(crate) fn template(&self, template_id: usize) -> String {
+        self.clusters[template_id].template_str()
+    }
+
+    pub(crate) fn stats(&self, template_id: usize) -> &PatternStats {
+        &self.clusters[template_id].stats
+    }
+
+    /// All templates with their statistics, most frequent first.
+    pub(crate) fn templates(&self) -> Vec<(String, &PatternStats)> {
+        let mut templates: Vec<(String, &PatternStats)> = self.clusters.iter()
+            .map(|cluster| (cluster.template_str(), &cluster.stats))
+            .collect();
+        templates.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(&b.0)));
+        templates
+    }
+}
+
+/// Lines that glog copies into the log files of lower severity are counted once.
+pub(crate) fn print_patterns_report<I: Iterator<Item = YBLogLine>>(lines: I, top: usize) {
+    let mut miner = TemplateMiner::new();
+    let mut num_lines: u64 = 0;
+    let mut lines = without_severity_copies(lines);
+    for line in &mut lines {
+        miner.add(&line);
+        num_lines += 1;
+    }
+    let templates = miner.templates();
+    lines.print_note();
+    println!("Found {} patterns in {} lines, showing the top {}",
+             templates.len(), num_lines, std::cmp::min(top, templates.len()));
+    println!("{:>10}  {:<26}  {:<26}  {:<20}  {:<20}  template",
+             "count", "first", "last", "levels", "nodes");
+    for (template, stats) in templates.iter().take(top) {
+        println!("{:>10}  {:<26}  {:<26}  {:<20}  {:<20}  {}",
+                 stats.count,
+                 stats.first_timestamp.to_string(),
+                 stats.last_timestamp.to_string(),
+                 stats.levels_str(),
+                 stats.nodes.iter().cloned().collect::<Vec<_>>().join(","),
+                 template);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    #[test]
+    fn masking() {
+        let masker = MessageMasker::new();
+        assert_eq!(
+            masker.mask("T 5c3a4b0b7c2d4a8e9f0a1b2c3d4e5f60 P 0f1e2d3c4b5a69788796a5b4c3d2e1f0: \
+                         Leader election won for term 3"),
+            "T <id> P <id>: Leader election won for term <num>");
+        assert_eq!(
+            masker.mask("Call yb.tserver.TabletServerService.Write from 10.1.2.3:45678 took \
+                         1234ms\nthe rest of the message"),
+            "Call yb.tserver.TabletServerService.Write from <host> took <duration>");
+        assert_eq!(masker.mask("Opened namespace 00004000-0000-8000-8000-000000000000 at 0x7f12"),
+                   "Opened namespace <uuid> at <hex>");
+    }
+
+    #[test]
+    fn messages_differing_in_values_share_a_template() {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let n2 = source(1, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        let mut miner = TemplateMiner::new();
+        let lines = [
+            glog_line(&n1, "I0408 10:00:01.000000  1234 log.cc:100] \
+                            Rolled over log segment to 12 (size 67108864)"),
+            glog_line(&n2, "W0408 10:00:03.000000  5678 log.cc:100] \
+                            Rolled over log segment to 13 (size 134217728)"),
+            glog_line(&n1, "I0408 10:00:02.000000  1234 tablet_peer.cc:200] \
+                            Tablet bootstrap started"),
+            glog_line(&n1, "I0408 10:00:04.000000  1234 tablet_peer.cc:200] \
+                            Tablet bootstrap finished"),
+            glog_line(&n1, "I0408 10:00:05.000000  1234 tablet_peer.cc:200] \
+                            Tablet bootstrap failed badly"),
+        ];
+        let ids: Vec<usize> = lines.iter().map(|line| miner.add(line)).collect();
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[2], ids[3]);
+        // Different numbers of tokens go into different partitions.
+        assert_ne!(ids[2], ids[4]);
+        assert_ne!(ids[0], ids[2]);
+
+        assert_eq!(miner.template(ids[0]), "Rolled over log segment to <num> (size <num>)");
+        assert_eq!(miner.template(ids[2]), "Tablet bootstrap <*>");
+        let stats = miner.stats(ids[0]);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.first_timestamp.to_string(), "2021-04-08 10:00:01");
+        assert_eq!(stats.last_timestamp.to_string(), "2021-04-08 10:00:03");
+        assert_eq!(stats.levels_str(), "I:1,W:1");
+        assert_eq!(stats.nodes.iter().cloned().collect::<Vec<_>>(), ["n1", "n2"]);
+
+        let templates = miner.templates();
+        assert_eq!(templates.len(), 3);
+        assert_eq!(templates[0].1.count, 2);
+        assert_eq!(templates[2].0, "Tablet bootstrap failed badly");
+    }
+
+    #[test]
+    fn dissimilar_messages_get_their_own_template() {
+        let n1 = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let mut miner = TemplateMiner::new();
+        let first = miner.add(&glog_line(&n1, "I0408 10:00:01.000000  1234 a.cc:1] \
+                                               Starting tablet server on port 9100"));
+        let second = miner.add(&glog_line(&n1, "I0408 10:00:02.000000  1234 a.cc:1] \
+                                                Starting the periodic flush of memtables"));
+        assert_ne!(first, second);
+        assert_eq!(miner.template(first), "Starting tablet server on port <num>");
+    }
+}
@@ -0,0 +1,207 @@
+// ------------------------------------------------------------------------------------------------
+// Process instances: input files grouped by the run of a program on a node that wrote them.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+
+use crate::{BuildInfo, LogSource, YBLogLine};
+
+/// Log files of one node and program whose process start times differ by at most this much are
+/// taken to be from the same process, as the preamble only has whole seconds.
+const START_TIME_TOLERANCE_SECONDS: i64 = 2;
+
+/// A program on a node, e.g. ("node-1", "yb-tserver").
+pub(crate) type NodeProgram = (String, String);
+
+/// One run of a program on a node, made up of the log files it wrote.
+pub(crate) struct ProcessInstance {
+    pub(crate) pid: Option<u32>,
+    pub(crate) started_at: Option<NaiveDateTime>,
+    pub(crate) build: Option<BuildInfo>,
+    pub(crate) num_files: usize,
+    pub(crate) first_line_at: Option<NaiveDateTime>,
+    pub(crate) last_line_at: Option<NaiveDateTime>,
+}
+
+impl ProcessInstance {
+    /// The time range this instance is known to have been running, if any.
+    pub(crate) fn time_range(&self) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let start = self.started_at.or(self.first_line_at)?;
+        Some((start, self.last_line_at.unwrap_or(start).max(start)))
+    }
+}
+
+pub(crate) struct ProcessInstances {
+    /// Instances of each program on each node, ordered by start time.
+    pub(crate) by_program: BTreeMap<NodeProgram, Vec<ProcessInstance>>,
+    /// The program and position in `by_program` of the instance of each source, by source index.
+    by_source: HashMap<usize, (NodeProgram, usize)>,
+}
+
+impl ProcessInstances {
+    /// Groups input files into process instances.
+    pub(crate) fn new(sources: &[Arc<LogSource>]) -> ProcessInstances {
+        let mut sources = sources.to_vec();
+        sources.sort_by(|a, b| (&a.node, &a.process.program, a.process.started_at, &a.path)
+            .cmp(&(&b.node, &b.process.program, b.process.started_at, &b.path)));
+
+        let mut by_program: BTreeMap<NodeProgram, Vec<ProcessInstance>> = BTreeMap::new();
+        let mut by_source: HashMap<usize, (NodeProgram, usize)> = HashMap::new();
+        for source in sources {
+            let process = &source.process;
+            let key = (source.node.clone().unwrap_or_default(), process.program.clone());
+            let node_instances = by_program.entry(key.clone()).or_default();
+            let same_process = match node_instances.last() {
+                Some(last) => match (last.started_at, process.started_at) {
+                    (Some(last_started_at), Some(started_at)) => {
+                        let start_delta = started_at - last_started_at;
+                        start_delta.num_seconds() <= START_TIME_TOLERANCE_SECONDS &&
+                            (last.pid.is_none() || process.pid.is_none() || last.pid == process.pid)
+                    }
+                    // Without a preamble there is no telling which process a file belongs to.
+                    _ => false,
+                },
+                None => false,
+            };
+            if same_process {
+                let instance = node_instances.last_mut().unwrap();
+                instance.num_files += 1;
+                instance.pid = instance.pid.or(process.pid);
+            } else {
+                node_instances.push(ProcessInstance {
+                    pid: process.pid,
+                    started_at: process.started_at,
+                    build: process.build.clone(),
+                    num_files: 1,
+                    first_line_at: None,
+                    last_line_at: None,
+                });
+            }
+            by_source.insert(source.index, (key, node_instances.len() - 1));
+        }
+        ProcessInstances { by_program, by_source }
+    }
+
+    /// The program and position in `by_program` of the instance that wrote a line.
+    pub(crate) fn instance_of(&self, line: &YBLogLine) -> &(NodeProgram, usize) {
+        &self.by_source[&line.source.index]
+    }
+
+    /// Extends the time range of the instance that wrote a line. Lines must come in timestamp
+    /// order.
+    pub(crate) fn observe(&mut self, line: &YBLogLine) {
+        let (key, instance_index) = &self.by_source[&line.source.index];
+        let instance = &mut self.by_program.get_mut(key).unwrap()[*instance_index];
+        if instance.first_line_at.is_none() {
+            instance.first_line_at = Some(line.timestamp);
+        }
+        instance.last_line_at = Some(line.timestamp);
+    }
+}
+
+/// Leaves out the copies glog writes of a line to the log files of lower severity of the same
+/// process: a WARNING line also goes to the INFO file, an ERROR line to the INFO and WARNING files,
+/// and so on. Copies come from different files but have the same node, program, pid, timestamp,
+/// thread, source location and message. Lines must come in timestamp order.
+pub(crate) struct WithoutSeverityCopies<I> {
+    lines: I,
+    /// Lines above INFO level at the timestamp of the last such line.
+    seen: Vec<YBLogLine>,
+    num_copies: u64,
+}
+
+pub(crate) fn without_severity_copies<I: Iterator<Item = YBLogLine>>(
+        lines: I) -> WithoutSeverityCopies<I> {
+    WithoutSeverityCopies { lines, seen: Vec::new(), num_copies: 0 }
+}
+
+fn is_severity_copy(line: &YBLogLine, other: &YBLogLine) -> bool {
+    line.source.index != other.source.index &&
+        line.source.node == other.source.node &&
+        line.source.process.program == other.source.process.program &&
+        line.source.process.pid == other.source.process.pid &&
+        line.thread_id == other.thread_id &&
+        line.line_number == other.line_number &&
+        line.file_name == other.file_name &&
+        line.message == other.message
+}
+
+impl<I> WithoutSeverityCopies<I> {
+    /// States how severity copies were counted, for the header of a report.
+    pub(crate) fn print_note(&self) {
+        println!("Lines above INFO level are counted once; left out {} copies of them in log files \
+                  of lower severity", self.num_copies);
+    }
+}
+
+impl<I: Iterator<Item = YBLogLine>> Iterator for WithoutSeverityCopies<I> {
+    type Item = YBLogLine;
+
+    fn next(&mut self) -> Option<YBLogLine> {
+        loop {
+            let line = self.lines.next()?;
+            // INFO lines only ever go to the INFO file.
+            if line.log_level == 'I' {
+                return Some(line);
+            }
+            if self.seen.first().is_some_and(|seen| seen.timestamp != line.timestamp) {
+                self.seen.clear();
+            }
+            if self.seen.iter().any(|seen| is_severity_copy(&line, seen)) {
+                self.num_copies += 1;
+                continue;
+            }
+            self.seen.push(line.clone());
+            return Some(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_lines::{glog_line, merged_lines, source};
+
+    #[test]
+    fn severity_copies_are_left_out() {
+        let file_name = |severity: &str| {
+            format!("yb-tserver.host.yugabyte.log.{}.20210408-100000.4321", severity)
+        };
+        let info = source(0, "node-1", &file_name("INFO"));
+        let warning = source(1, "node-1", &file_name("WARNING"));
+        let error = source(2, "node-1", &file_name("ERROR"));
+        let other_process =
+            source(3, "node-1", "yb-tserver.host.yugabyte.log.INFO.20210408-110000.5555");
+        let other_node = source(4, "node-2", &file_name("INFO"));
+        let warning_text = "W0408 10:00:01.671530  1020 log.cc:512] Log append took 1200ms";
+        let error_text = "E0408 10:00:02.000001  1014 tablet_service.cc:318] \
+                          Tablet 35bf992dc9e9c616612e7696a6cecc1b not found";
+        let info_text = "I0408 10:00:02.000001  1014 tablet_service.cc:318] \
+                         Tablet 35bf992dc9e9c616612e7696a6cecc1b not found";
+        let sources = vec![info.clone(), warning.clone(), error.clone(), other_process.clone(),
+                           other_node.clone()];
+        let lines = vec![
+            glog_line(&info, warning_text),
+            glog_line(&warning, warning_text),
+            glog_line(&other_process, warning_text),
+            glog_line(&other_node, warning_text),
+            glog_line(&info, error_text),
+            glog_line(&warning, error_text),
+            glog_line(&error, error_text),
+            // INFO lines are never copies, even if they look the same.
+            glog_line(&info, info_text),
+            glog_line(&info, info_text),
+        ];
+        let mut deduped = without_severity_copies(merged_lines(&sources, lines));
+        let kept: Vec<(usize, char)> = (&mut deduped)
+            .map(|line| (line.source.index, line.log_level))
+            .collect();
+        // Of equal timestamps, the ERROR file comes first as lines are ordered by path.
+        assert_eq!(kept, vec![(0, 'W'), (3, 'W'), (4, 'W'), (2, 'E'), (0, 'I'), (0, 'I')]);
+        assert_eq!(deduped.num_copies, 3);
+    }
+}
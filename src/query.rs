@@ -0,0 +1,690 @@
+// ------------------------------------------------------------------------------------------------
+// --where expressions: a small query language over the columns of a log line and the fields of
+// the rules file, e.g.
+//
+//   level >= W and node in ('n1', 'n2') and message ~ /timed out/
+//       and ts between '10:00' and '10:05'
+//
+// Predicates that can be checked before a line is fully parsed are pushed down to the parser:
+// timestamp comparisons, and literal substrings that the first line of every matching entry must
+// contain. The latter only come from the first_line column, since a match on message may be in a
+// continuation line that the parser has not seen yet.
+// ------------------------------------------------------------------------------------------------
+
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+
+use chrono::{NaiveDateTime, NaiveTime};
+use regex::Regex;
+
+use yblp::{parse_filter_timestamp, FilterTimestampBase};
+
+use crate::rules::{CompareOp, FieldValue, Fields, UserRules};
+use crate::YBLogLine;
+
+/// What a predicate looks at.
+#[derive(Clone, PartialEq, Debug)]
+enum Column {
+    Level,
+    Timestamp,
+    Tablet,
+    Node,
+    Program,
+    File,
+    Line,
+    Thread,
+    Message,
+    /// The first line of the message, without continuation lines.
+    FirstLine,
+    /// A field defined in the rules file.
+    UserField(String),
+}
+
+impl Column {
+    /// Built-in column names are case-insensitive, rule field names case-sensitive.
+    fn from_name(name: &str, rules: Option<&UserRules>) -> Result<Column, String> {
+        Ok(match name.to_lowercase().as_str() {
+            "level" => Column::Level,
+            "ts" | "timestamp" => Column::Timestamp,
+            "tablet" | "tablet_id" => Column::Tablet,
+            "node" => Column::Node,
+            "program" => Column::Program,
+            "file" => Column::File,
+            "line" => Column::Line,
+            "thread" | "thread_id" => Column::Thread,
+            "message" => Column::Message,
+            "first_line" => Column::FirstLine,
+            _ => match rules {
+                Some(rules) if rules.field_names().contains(&name) =>
+                    Column::UserField(String::from(name)),
+                _ => return Err(format!(
+                    "Unknown column '{}': expected level, ts, tablet, node, program, file, line, \
+                     thread, message, first_line or a field from --rules-file", name)),
+            },
+        })
+    }
+
+    /// Converts a literal from the expression to the type of this column.
+    fn literal(&self, text: &str, base: &FilterTimestampBase) -> Result<Literal, String> {
+        match self {
+            Column::Level => level_rank(text).map(Literal::Level)
+                .ok_or_else(|| format!("Unknown log level '{}': expected I, W, E or F", text)),
+            Column::Timestamp => parse_time_literal(text, base),
+            Column::Line | Column::Thread => text.parse().map(Literal::Int)
+                .map_err(|_| format!("Expected an integer, got '{}'", text)),
+            _ => Ok(Literal::Text(String::from(text))),
+        }
+    }
+}
+
+/// Orders log levels by severity, accepting both glog letters and full names.
+fn level_rank(level: &str) -> Option<u8> {
+    match level.to_uppercase().as_str() {
+        "INFO" => Some(0),
+        "WARNING" => Some(1),
+        "ERROR" => Some(2),
+        "FATAL" => Some(3),
+        _ => {
+            let mut chars = level.chars();
+            match (chars.next(), chars.next()) {
+                (Some(letter), None) => level_letter_rank(letter),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// The rank of a glog level letter, as in `level_rank`.
+fn level_letter_rank(letter: char) -> Option<u8> {
+    match letter.to_ascii_uppercase() {
+        'I' => Some(0),
+        'W' => Some(1),
+        'E' => Some(2),
+        'F' => Some(3),
+        _ => None,
+    }
+}
+
+/// Parses a timestamp literal: a timestamp as accepted by --lowest-timestamp, or a time of day
+/// such as 10:05 or 10:05:30.250 that matches lines of any date.
+fn parse_time_literal(text: &str, base: &FilterTimestampBase) -> Result<Literal, String> {
+    if let Ok(timestamp) = parse_filter_timestamp(text, base) {
+        return Ok(Literal::Timestamp(timestamp));
+    }
+    for format in &["%H:%M:%S%.f", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(text, format) {
+            return Ok(Literal::TimeOfDay(time));
+        }
+    }
+    Err(format!("Could not parse timestamp '{}': expected a timestamp as for --lowest-timestamp \
+                 or a time of day such as HH:MM or HH:MM:SS", text))
+}
+
+#[derive(Clone, Debug)]
+enum Literal {
+    Level(u8),
+    Timestamp(NaiveDateTime),
+    TimeOfDay(NaiveTime),
+    Int(i64),
+    Text(String),
+}
+
+/// Compares a timestamp with a timestamp literal.
+fn compare_timestamp(timestamp: NaiveDateTime, literal: &Literal) -> Option<Ordering> {
+    match literal {
+        Literal::Timestamp(other) => Some(timestamp.cmp(other)),
+        Literal::TimeOfDay(other) => Some(timestamp.time().cmp(other)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Test {
+    Compare(CompareOp, Literal),
+    Matches { regex: Regex, negated: bool },
+    In { values: Vec<Literal>, negated: bool },
+    Between(Literal, Literal),
+}
+
+#[derive(Clone, Debug)]
+struct Predicate {
+    column: Column,
+    test: Test,
+}
+
+impl Predicate {
+    /// Evaluates the test given how the column's value compares to a literal, and its text for
+    /// regex matches. A missing value, e.g. the tablet of a line about no tablet, matches nothing.
+    fn evaluate<C, T, S>(&self, compare: C, text: T) -> bool
+            where C: Fn(&Literal) -> Option<Ordering>, T: FnOnce() -> Option<S>, S: AsRef<str> {
+        match &self.test {
+            Test::Compare(op, literal) => compare(literal).is_some_and(|o| op.holds(o)),
+            Test::Matches { regex, negated } => match text() {
+                Some(text) => regex.is_match(text.as_ref()) != *negated,
+                None => false,
+            },
+            Test::In { values, negated } => {
+                let orderings: Vec<Option<Ordering>> = values.iter().map(&compare).collect();
+                if orderings.iter().any(Option::is_none) {
+                    return false;
+                }
+                orderings.contains(&Some(Ordering::Equal)) != *negated
+            }
+            Test::Between(low, high) => match (compare(low), compare(high)) {
+                (Some(low), Some(high)) => low != Ordering::Less && high != Ordering::Greater,
+                _ => false,
+            },
+        }
+    }
+
+    /// The column's value as text, borrowed if the line holds it as a string already.
+    fn text<'a>(&self, line: &'a YBLogLine, fields: &'a [(String, FieldValue)])
+            -> Option<Cow<'a, str>> {
+        match &self.column {
+            Column::Level => Some(Cow::Owned(line.log_level.to_string())),
+            Column::Timestamp => Some(Cow::Owned(line.timestamp.to_string())),
+            Column::Tablet => line.tablet_id.map(|id| Cow::Owned(id.to_simple().to_string())),
+            Column::Node => line.source.node.as_deref().map(Cow::Borrowed),
+            Column::Program => Some(Cow::Borrowed(&line.source.process.program)),
+            Column::File => Some(Cow::Borrowed(&line.file_name)),
+            Column::Line => Some(Cow::Owned(line.line_number.to_string())),
+            Column::Thread => Some(Cow::Owned(line.thread_id.to_string())),
+            Column::Message => Some(Cow::Borrowed(&line.message)),
+            Column::FirstLine => Some(Cow::Borrowed(line.message.lines().next().unwrap_or(""))),
+            Column::UserField(name) => fields.iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| match value {
+                    FieldValue::String(value) => Cow::Borrowed(value.as_str()),
+                    _ => Cow::Owned(value.to_string()),
+                }),
+        }
+    }
+
+    fn matches(&self, line: &YBLogLine, fields: &[(String, FieldValue)]) -> bool {
+        // Built the first time a test needs it, so e.g. not at all for a level comparison.
+        let text_cell: OnceCell<Option<Cow<str>>> = OnceCell::new();
+        let text = || text_cell.get_or_init(|| self.text(line, fields)).as_deref();
+        let compare = |literal: &Literal| -> Option<Ordering> {
+            match (&self.column, literal) {
+                (Column::Level, Literal::Level(rank)) =>
+                    level_letter_rank(line.log_level).map(|own| own.cmp(rank)),
+                (Column::Timestamp, _) => compare_timestamp(line.timestamp, literal),
+                (Column::Line, Literal::Int(value)) =>
+                    Some(i64::from(line.line_number).cmp(value)),
+                (Column::Thread, Literal::Int(value)) => Some(line.thread_id.cmp(value)),
+                (Column::UserField(name), Literal::Text(value)) => fields.iter()
+                    .find(|(field, _)| field == name)
+                    .and_then(|(_, own)| own.compare_to_str(value)),
+                (_, Literal::Text(value)) => text().map(|own| own.cmp(value.as_str())),
+                _ => None,
+            }
+        };
+        self.evaluate(compare, text)
+    }
+
+    /// The substring that the first line of every matching entry contains, if this predicate
+    /// is a regex match of the first line against a plain string.
+    fn required_substring(&self) -> Option<String> {
+        match (&self.column, &self.test) {
+            (Column::FirstLine, Test::Matches { regex, negated: false }) => {
+                let pattern = regex.as_str();
+                let is_literal = !pattern.is_empty() &&
+                    !pattern.contains(|c: char| r"\.+*?()|[]{}^$#".contains(c));
+                if is_literal {
+                    Some(String::from(pattern))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate),
+}
+
+impl Expr {
+    fn matches(&self, line: &YBLogLine, fields: &[(String, FieldValue)]) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(line, fields) && right.matches(line, fields),
+            Expr::Or(left, right) => left.matches(line, fields) || right.matches(line, fields),
+            Expr::Not(inner) => !inner.matches(line, fields),
+            Expr::Predicate(predicate) => predicate.matches(line, fields),
+        }
+    }
+
+    /// The predicates that must all hold for the expression to hold.
+    fn conjuncts<'a>(&'a self, out: &mut Vec<&'a Predicate>) {
+        match self {
+            Expr::And(left, right) => {
+                left.conjuncts(out);
+                right.conjuncts(out);
+            }
+            Expr::Predicate(predicate) => out.push(predicate),
+            _ => {}
+        }
+    }
+
+    fn uses_user_fields(&self) -> bool {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) =>
+                left.uses_user_fields() || right.uses_user_fields(),
+            Expr::Not(inner) => inner.uses_user_fields(),
+            Expr::Predicate(predicate) => matches!(predicate.column, Column::UserField(_)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Regex(String),
+    Op(CompareOp),
+    Match { negated: bool },
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LeftParen, 1),
+            (')', _) => (Token::RightParen, 1),
+            (',', _) => (Token::Comma, 1),
+            ('!', Some('=')) | ('<', Some('>')) => (Token::Op(CompareOp::Ne), 2),
+            ('!', Some('~')) => (Token::Match { negated: true }, 2),
+            ('=', Some('=')) => (Token::Op(CompareOp::Eq), 2),
+            ('<', Some('=')) => (Token::Op(CompareOp::Le), 2),
+            ('>', Some('=')) => (Token::Op(CompareOp::Ge), 2),
+            ('=', _) => (Token::Op(CompareOp::Eq), 1),
+            ('<', _) => (Token::Op(CompareOp::Lt), 1),
+            ('>', _) => (Token::Op(CompareOp::Gt), 1),
+            ('~', _) => (Token::Match { negated: false }, 1),
+            ('\'', _) | ('"', _) | ('/', _) => {
+                // Quoted strings and /regexes/, where a backslash escapes the closing delimiter.
+                let mut value = String::new();
+                let mut end = i + 1;
+                loop {
+                    match chars.get(end) {
+                        None => return Err(format!("Unterminated {} in '{}'", c, s)),
+                        Some(&d) if d == c => break,
+                        Some('\\') if chars.get(end + 1) == Some(&c) => {
+                            value.push(c);
+                            end += 2;
+                        }
+                        Some(&d) => {
+                            value.push(d);
+                            end += 1;
+                        }
+                    }
+                }
+                let token = if c == '/' { Token::Regex(value) } else { Token::Quoted(value) };
+                (token, end + 1 - i)
+            }
+            _ => {
+                let len = chars[i..].iter()
+                    .take_while(|c| c.is_alphanumeric() || "_.:-+".contains(**c))
+                    .count();
+                if len == 0 {
+                    return Err(format!("Unexpected '{}' in '{}'", c, s));
+                }
+                (Token::Word(chars[i..i + len].iter().collect()), len)
+            }
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser for
+///
+///   expr      := and ("or" and)*
+///   and       := unary ("and" unary)*
+///   unary     := "not" unary | "(" expr ")" | predicate
+///   predicate := column (op value | ("~" | "!~") regex | ["not"] "in" "(" value ("," value)* ")"
+///                        | "between" value "and" value)
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    rules: Option<&'a UserRules>,
+    base: FilterTimestampBase,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.position).cloned()
+            .ok_or_else(|| String::from("Unexpected end of expression"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the given keyword, in any case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("Expected {:?}, got {:?}", expected, token)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek() == Some(&Token::LeftParen) {
+            self.position += 1;
+            let expr = self.expr()?;
+            self.expect(Token::RightParen)?;
+            return Ok(expr);
+        }
+        self.predicate().map(Expr::Predicate)
+    }
+
+    fn value(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Word(value) | Token::Quoted(value) => Ok(value),
+            token => Err(format!("Expected a value, got {:?}", token)),
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, String> {
+        let column = match self.next()? {
+            Token::Word(name) => Column::from_name(&name, self.rules)?,
+            token => return Err(format!("Expected a column name, got {:?}", token)),
+        };
+        let negated = self.keyword("not");
+        let test = if self.keyword("in") {
+            self.expect(Token::LeftParen)?;
+            let mut values = vec![column.literal(&self.value()?, &self.base)?];
+            while self.peek() == Some(&Token::Comma) {
+                self.position += 1;
+                values.push(column.literal(&self.value()?, &self.base)?);
+            }
+            self.expect(Token::RightParen)?;
+            Test::In { values, negated }
+        } else if negated {
+            return Err(String::from("Expected 'in' after 'not'"));
+        } else if self.keyword("between") {
+            let low = column.literal(&self.value()?, &self.base)?;
+            if !self.keyword("and") {
+                return Err(String::from("Expected 'and' in 'between'"));
+            }
+            Test::Between(low, column.literal(&self.value()?, &self.base)?)
+        } else {
+            match self.next()? {
+                Token::Op(op) => Test::Compare(op, column.literal(&self.value()?, &self.base)?),
+                Token::Match { negated } => {
+                    let pattern = match self.next()? {
+                        Token::Regex(pattern) | Token::Quoted(pattern) => pattern,
+                        token => return Err(format!("Expected a /regex/, got {:?}", token)),
+                    };
+                    let regex = Regex::new(&pattern)
+                        .map_err(|e| format!("Invalid regex /{}/: {}", pattern, e))?;
+                    Test::Matches { regex, negated }
+                }
+                token => return Err(format!("Expected an operator, got {:?}", token)),
+            }
+        };
+        Ok(Predicate { column, test })
+    }
+}
+
+/// A parsed --where expression, with the parts of it that the parser checks early.
+#[derive(Clone, Debug)]
+pub(crate) struct WhereClause {
+    expr: Expr,
+    /// Substrings that the first line of every matching entry contains.
+    required_substrings: Vec<String>,
+    /// Timestamp predicates that every matching entry satisfies.
+    timestamp_predicates: Vec<Predicate>,
+    uses_user_fields: bool,
+}
+
+impl WhereClause {
+    pub(crate) fn parse(s: &str, rules: Option<&UserRules>, base: FilterTimestampBase)
+            -> Result<WhereClause, String> {
+        let mut parser = Parser { tokens: tokenize(s)?, position: 0, rules, base };
+        let expr = parser.expr()
+            .and_then(|expr| match parser.peek() {
+                None => Ok(expr),
+                Some(token) => Err(format!("Unexpected {:?} after the expression", token)),
+            })
+            .map_err(|e| format!("Could not parse --where expression '{}': {}", s, e))?;
+        let mut conjuncts = Vec::new();
+        expr.conjuncts(&mut conjuncts);
+        let required_substrings =
+            conjuncts.iter().filter_map(|predicate| predicate.required_substring()).collect();
+        let timestamp_predicates = conjuncts.iter()
+            .filter(|predicate| predicate.column == Column::Timestamp)
+            .map(|predicate| (*predicate).clone())
+            .collect();
+        let uses_user_fields = expr.uses_user_fields();
+        Ok(WhereClause { expr, required_substrings, timestamp_predicates, uses_user_fields })
+    }
+
+    /// Whether a raw line may start a matching entry, checked before parsing it.
+    pub(crate) fn may_match_line(&self, line: &str) -> bool {
+        self.required_substrings.iter().all(|substring| line.contains(substring.as_str()))
+    }
+
+    /// Whether an entry with this timestamp may match, checked before building the entry.
+    pub(crate) fn may_match_timestamp(&self, timestamp: NaiveDateTime) -> bool {
+        self.timestamp_predicates.iter().all(|predicate| predicate.evaluate(
+            |literal| compare_timestamp(timestamp, literal),
+            || Some(timestamp.to_string())))
+    }
+
+    pub(crate) fn uses_user_fields(&self) -> bool {
+        self.uses_user_fields
+    }
+
+    pub(crate) fn matches(&self, line: &YBLogLine, fields: &Fields) -> bool {
+        self.expr.matches(line, fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use crate::test_lines::{glog_line, source};
+
+    fn base() -> FilterTimestampBase {
+        FilterTimestampBase {
+            now: NaiveDate::from_ymd_opt(2021, 4, 8).unwrap().and_hms_opt(12, 0, 0).unwrap(),
+            default_year: 2021,
+        }
+    }
+
+    fn parse(s: &str) -> Result<WhereClause, String> {
+        WhereClause::parse(s, None, base())
+    }
+
+    /// The structure of an expression, with each predicate shown as its column.
+    fn shape(expr: &Expr) -> String {
+        match expr {
+            Expr::And(left, right) => format!("({} and {})", shape(left), shape(right)),
+            Expr::Or(left, right) => format!("({} or {})", shape(left), shape(right)),
+            Expr::Not(inner) => format!("not {}", shape(inner)),
+            Expr::Predicate(predicate) => format!("{:?}", predicate.column).to_lowercase(),
+        }
+    }
+
+    #[test]
+    fn tokenize_operators_strings_and_regexes() {
+        let word = |s: &str| Token::Word(String::from(s));
+        assert_eq!(tokenize("a!=b <> c<=d>=e==f<g>h=i").unwrap(), vec![
+            word("a"), Token::Op(CompareOp::Ne), word("b"), Token::Op(CompareOp::Ne), word("c"),
+            Token::Op(CompareOp::Le), word("d"), Token::Op(CompareOp::Ge), word("e"),
+            Token::Op(CompareOp::Eq), word("f"), Token::Op(CompareOp::Lt), word("g"),
+            Token::Op(CompareOp::Gt), word("h"), Token::Op(CompareOp::Eq), word("i"),
+        ]);
+        assert_eq!(tokenize(r"message ~ /a\/b/ and file !~ 'x.cc'").unwrap(), vec![
+            word("message"), Token::Match { negated: false }, Token::Regex(String::from("a/b")),
+            word("and"), word("file"), Token::Match { negated: true },
+            Token::Quoted(String::from("x.cc")),
+        ]);
+        assert_eq!(tokenize(r#"node in ("it's", 'n\'2')"#).unwrap(), vec![
+            word("node"), word("in"), Token::LeftParen, Token::Quoted(String::from("it's")),
+            Token::Comma, Token::Quoted(String::from("n'2")), Token::RightParen,
+        ]);
+        // Words keep the characters of timestamps and tablet ids together.
+        assert_eq!(tokenize("ts>2021-04-08T10:00:00.5+02").unwrap(), vec![
+            word("ts"), Token::Op(CompareOp::Gt), word("2021-04-08T10:00:00.5+02"),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_and_unexpected_input() {
+        assert!(tokenize("message ~ /abc").unwrap_err().contains("Unterminated /"));
+        assert!(tokenize("node = 'n1").unwrap_err().contains("Unterminated '"));
+        assert!(tokenize("level >= W; drop").unwrap_err().contains("Unexpected ';'"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tighter_than_and() {
+        let shape_of = |s: &str| shape(&parse(s).unwrap().expr);
+        assert_eq!(shape_of("level = W or node = n1 and file = a.cc"),
+                   "(level or (node and file))");
+        assert_eq!(shape_of("level = W and node = n1 or file = a.cc"),
+                   "((level and node) or file)");
+        assert_eq!(shape_of("not level = W and node = n1"), "(not level and node)");
+        assert_eq!(shape_of("level = W and (node = n1 or file = a.cc)"),
+                   "(level and (node or file))");
+        assert_eq!(shape_of("file = a.cc or NOT NOT (line > 3)"), "(file or not not line)");
+        // The "and" of between is not the boolean operator.
+        assert_eq!(shape_of("ts between 10:00 and 10:05 and thread = 1"), "(timestamp and thread)");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |s: &str| parse(s).unwrap_err();
+        assert!(error("severity = W").contains("Unknown column 'severity'"));
+        assert!(error("level = X").contains("Unknown log level 'X'"));
+        assert!(error("line > ten").contains("Expected an integer"));
+        assert!(error("ts > yesterday").contains("Could not parse timestamp"));
+        assert!(error("node not = n1").contains("Expected 'in' after 'not'"));
+        assert!(error("ts between 10:00 or 10:05").contains("Expected 'and' in 'between'"));
+        assert!(error("message ~ /(/").contains("Invalid regex"));
+        assert!(error("node = n1 node = n2").contains("after the expression"));
+        assert!(error("(node = n1").contains("Unexpected end of expression"));
+        assert!(error("node in (n1, n2").contains("Unexpected end of expression"));
+        assert!(error("").contains("Unexpected end of expression"));
+    }
+
+    #[test]
+    fn only_first_line_matches_are_pushed_down() {
+        let clause = parse("message ~ /timed out/ and first_line ~ /Write/").unwrap();
+        assert_eq!(clause.required_substrings, vec![String::from("Write")]);
+        assert!(clause.may_match_line("I0408 10:00:00.000000 1 a.cc:1] Write"));
+        assert!(!clause.may_match_line("I0408 10:00:00.000000 1 a.cc:1] timed out"));
+        // Substrings under "or" or "not", and regexes that are not plain strings, are not required.
+        for s in &["first_line ~ /a/ or level = W", "not first_line ~ /a/", "first_line ~ /a.b/",
+                   "first_line !~ /a/"] {
+            assert!(parse(s).unwrap().required_substrings.is_empty(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn timestamp_predicates_are_checked_early() {
+        let clause = parse("ts >= '2021-04-08 10:00:00' and ts < 10:05 and level = W").unwrap();
+        let at = |hour, minute| NaiveDate::from_ymd_opt(2021, 4, 8).unwrap()
+            .and_hms_opt(hour, minute, 0).unwrap();
+        assert!(!clause.may_match_timestamp(at(9, 59)));
+        assert!(clause.may_match_timestamp(at(10, 0)));
+        assert!(!clause.may_match_timestamp(at(10, 5)));
+        // Relative timestamps are resolved against the base.
+        let clause = parse("ts > -1h").unwrap();
+        assert!(!clause.may_match_timestamp(at(11, 0)));
+        assert!(clause.may_match_timestamp(at(11, 1)));
+    }
+
+    #[test]
+    fn predicates_on_columns() {
+        let source = source(0, "node-1", "yb-tserver.INFO");
+        let line = glog_line(&source, "W0408 10:00:01.671530  2345 inbound_call.cc:118] \
+            Call yb.tserver.TabletServerService.Write 10.0.0.1:41688 => 10.0.0.2:9100 \
+            (request call id 2451) took 1520ms\n    @ 0x7f1234567890  yb::rpc::Foo()");
+        let matches = |s: &str| parse(s).unwrap().matches(&line, &Fields::new());
+        assert!(matches("level >= W and level < error and LEVEL in (w, E)"));
+        assert!(!matches("level > W"));
+        assert!(matches("node in ('node-1', 'node-2') and program = 'yb-tserver'"));
+        assert!(!matches("node not in ('node-1')"));
+        assert!(matches("file = inbound_call.cc and line between 100 and 200 and thread = 2345"));
+        assert!(matches("message ~ /Foo\\(\\)/ and first_line !~ /Foo/"));
+        assert!(matches("ts ~ /^2021-04-08 10:00:01/"));
+        // The line is about no tablet, so tablet tests match nothing, not even negated ones.
+        assert!(!matches("tablet ~ /./") && !matches("tablet !~ /./"));
+    }
+
+    #[test]
+    fn rule_fields_are_case_sensitive() {
+        let rules = UserRules::parse(r#"
+[[rules]]
+name = "rpc"
+regex = 'Call (?P<Method>[\w.]+) .*took (?P<durationMs>\d+)ms'
+fields = { durationMs = "int" }
+"#).unwrap();
+        let parse = |s: &str| WhereClause::parse(s, Some(&rules), base());
+        let source = source(0, "node-1", "yb-tserver.INFO");
+        let line = glog_line(&source, "W0408 10:00:01.671530  2345 inbound_call.cc:118] \
+            Call yb.tserver.TabletServerService.Write 10.0.0.1:41688 => 10.0.0.2:9100 \
+            (request call id 2451) took 1520ms");
+        let fields = rules.extract(&line.message);
+        let matches = |s: &str| parse(s).unwrap().matches(&line, &fields);
+        assert!(matches("durationMs >= 1000 and Method ~ /Write$/ and Level = W"));
+        assert!(!matches("durationMs > 1520"));
+        assert!(parse("durationms > 1").unwrap_err().contains("Unknown column 'durationms'"));
+        assert!(parse("method = Write").unwrap_err().contains("Unknown column 'method'"));
+    }
+}
@@ -0,0 +1,235 @@
+// ------------------------------------------------------------------------------------------------
+// Process restarts and crashes: process starts from log file preambles, FATAL lines and crash
+// stack traces, and the downtime between one process instance and the next.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::external_sort::MergedLines;
+use crate::processes::{NodeProgram, ProcessInstance, ProcessInstances};
+use crate::YBLogLine;
+
+/// Messages containing any of these are crashes even if not logged at the FATAL level.
+const CRASH_MARKERS: &[&str] = &[
+    "*** Check failure stack trace: ***",
+    "*** Aborted at",
+    "*** SIGSEGV",
+    "*** SIGABRT",
+];
+
+/// Stack traces longer than this are cut short in the report.
+const MAX_STACK_TRACE_LINES: usize = 30;
+
+fn is_crash(line: &YBLogLine) -> bool {
+    line.log_level == 'F' || CRASH_MARKERS.iter().any(|marker| line.message.contains(marker))
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let total_seconds = duration.num_seconds();
+    if total_seconds < 60 {
+        return format!("{:.3} s", duration.num_milliseconds() as f64 / 1000.0);
+    }
+    let (hours, minutes, seconds) =
+        (total_seconds / 3600, total_seconds / 60 % 60, total_seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m {:02}s", minutes, seconds)
+    }
+}
+
+/// Crashes of each process instance, in time order.
+type CrashesByInstance = HashMap<(NodeProgram, usize), Vec<YBLogLine>>;
+
+/// Groups the input files into process instances and finds the crashes of each.
+fn find_crashes(lines: MergedLines) -> (ProcessInstances, CrashesByInstance) {
+    let mut instances = ProcessInstances::new(lines.sources());
+
+    // The same FATAL line shows up in the INFO, WARNING, ERROR and FATAL files of a process, not
+    // always with the stack trace, so keep the longest copy.
+    let mut crashes: HashMap<(String, NaiveDateTime, i64, i32), YBLogLine> = HashMap::new();
+    for line in lines {
+        instances.observe(&line);
+        if is_crash(&line) {
+            let ((node, _), _) = instances.instance_of(&line);
+            let crash_key = (node.clone(), line.timestamp, line.thread_id, line.line_number);
+            match crashes.get(&crash_key) {
+                Some(crash) if crash.message.len() >= line.message.len() => {}
+                _ => { crashes.insert(crash_key, line); }
+            }
+        }
+    }
+    let mut crashes: Vec<YBLogLine> = crashes.into_values().collect();
+    crashes.sort_by(|a, b| a.ordering_key().cmp(&b.ordering_key()));
+    let mut crashes_by_instance: CrashesByInstance = HashMap::new();
+    for crash in crashes {
+        crashes_by_instance.entry(instances.instance_of(&crash).clone()).or_default().push(crash);
+    }
+    (instances, crashes_by_instance)
+}
+
+/// The last line of the previous process instance and how long before `started_at` it was, if
+/// the previous instance logged anything before this one started.
+fn downtime(previous: Option<&ProcessInstance>, started_at: NaiveDateTime)
+        -> Option<(NaiveDateTime, Duration)> {
+    match previous.and_then(|previous| previous.last_line_at) {
+        Some(last_line_at) if last_line_at <= started_at =>
+            Some((last_line_at, started_at - last_line_at)),
+        _ => None,
+    }
+}
+
+/// Prints, per node and program, every process start, every crash with its stack trace, and how
+/// long the program was down before each restart. Starts outside of
+/// [`lowest_timestamp`, `highest_timestamp`] are left out.
+pub(crate) fn print_restarts_report(
+        lines: MergedLines,
+        lowest_timestamp: Option<NaiveDateTime>,
+        highest_timestamp: Option<NaiveDateTime>) {
+    let (instances, crashes_by_instance) = find_crashes(lines);
+    let no_crashes: Vec<YBLogLine> = Vec::new();
+    let instances = instances.by_program;
+
+    let in_range = |timestamp: NaiveDateTime| {
+        !matches!(lowest_timestamp, Some(lowest) if timestamp < lowest) &&
+            !matches!(highest_timestamp, Some(highest) if timestamp > highest)
+    };
+    let num_starts: usize = instances.values().flatten()
+        .filter(|instance| instance.started_at.filter(|ts| in_range(*ts)).is_some())
+        .count();
+    let num_crashes: usize = crashes_by_instance.values().map(Vec::len).sum();
+    println!("Found {} process starts and {} crashes of {} programs across nodes",
+             num_starts, num_crashes, instances.len());
+
+    for (key, node_instances) in &instances {
+        let (node, program) = key;
+        println!();
+        println!("{} on {}", program, if node.is_empty() { "unknown node" } else { node });
+        let mut previous: Option<&ProcessInstance> = None;
+        for (instance_index, instance) in node_instances.iter().enumerate() {
+            if let Some(started_at) = instance.started_at.filter(|ts| in_range(*ts)) {
+                let downtime = match downtime(previous, started_at) {
+                    Some((last_line_at, downtime)) => format!(
+                        ", down for {} since the last line of the previous process at {}",
+                        format_duration(downtime), last_line_at),
+                    None => String::new(),
+                };
+                println!("  {:<26}  START  pid {}, version {}, {} file(s){}",
+                         started_at.to_string(),
+                         instance.pid.map_or(String::from("unknown"), |pid| pid.to_string()),
+                         instance.build.as_ref()
+                             .map_or(String::from("unknown"), |build| build.to_string()),
+                         instance.num_files,
+                         downtime);
+            }
+            let crashes = crashes_by_instance.get(&(key.clone(), instance_index))
+                .unwrap_or(&no_crashes);
+            for crash in crashes {
+                let mut message_lines = crash.message.lines();
+                println!("  {:<26}  CRASH  {} {}:{}] {}",
+                         crash.timestamp.to_string(), crash.log_level, crash.file_name,
+                         crash.line_number, message_lines.next().unwrap_or(""));
+                let stack_trace: Vec<&str> = message_lines.collect();
+                for frame in stack_trace.iter().take(MAX_STACK_TRACE_LINES) {
+                    println!("  {:<26}         {}", "", frame);
+                }
+                if stack_trace.len() > MAX_STACK_TRACE_LINES {
+                    println!("  {:<26}         ... {} more lines", "",
+                             stack_trace.len() - MAX_STACK_TRACE_LINES);
+                }
+            }
+            previous = Some(instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_lines::{glog_line, log_source, merged_lines};
+    use crate::LogSource;
+
+    fn started_source(index: usize, file_name: &str, started_at: &str) -> Arc<LogSource> {
+        let mut source = log_source(index, "n1", file_name);
+        source.process.started_at = Some(started_at.parse().unwrap());
+        Arc::new(source)
+    }
+
+    const FATAL_LINE: &str = "F0408 10:05:00.000000  1240 raft_consensus.cc:1234] \
+        Check failed: _s.ok() Bad status: Illegal state (yb/consensus/consensus_queue.cc:567): \
+        Not the leader";
+
+    #[test]
+    fn crashes_are_deduplicated_and_assigned_to_their_process() {
+        let info = started_source(
+            0, "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234", "2021-04-08T10:00:00");
+        // Created a second later than the INFO file, but by the same process.
+        let fatal = started_source(
+            1, "yb-tserver.n1.yugabyte.log.FATAL.20210408-100500.1234", "2021-04-08T10:00:01");
+        let restarted = started_source(
+            2, "yb-tserver.n1.yugabyte.log.INFO.20210408-100600.5678", "2021-04-08T10:06:00");
+        let stack_trace = "\n*** Check failure stack trace: ***\n\
+            \x20   @     0x7f3a2b1c4d5e  yb::LogFatalHandlerSink::send()\n\
+            \x20   @     0x7f3a2b1c5e6f  google::LogMessage::Flush()";
+        let lines = merged_lines(&[info.clone(), fatal.clone(), restarted.clone()], vec![
+            glog_line(&info, "I0408 10:00:01.000000  1234 tablet_server_main.cc:100] \
+                              Starting tablet server"),
+            glog_line(&fatal, FATAL_LINE),
+            glog_line(&info, &format!("{}{}", FATAL_LINE, stack_trace)),
+            glog_line(&restarted, "I0408 10:06:01.000000  5678 tablet_server_main.cc:100] \
+                                   Starting tablet server"),
+            glog_line(&restarted, "E0408 10:07:00.000000  5690 signal_handler.cc:200] \
+                                   *** SIGSEGV (@0x0) received by PID 5678 (TID 0x7f3a2b1c4d5e) \
+                                   from PID 0; stack trace: ***"),
+        ]);
+
+        let (instances, crashes_by_instance) = find_crashes(lines);
+        let key = (String::from("n1"), String::from("yb-tserver"));
+        let node_instances = &instances.by_program[&key];
+        assert_eq!(node_instances.len(), 2);
+        assert_eq!(node_instances[0].num_files, 2);
+        assert_eq!(node_instances[0].pid, Some(1234));
+        assert_eq!(node_instances[1].pid, Some(5678));
+
+        let crashes = &crashes_by_instance[&(key.clone(), 0)];
+        assert_eq!(crashes.len(), 1);
+        assert_eq!(crashes[0].source.index, 0);
+        assert!(crashes[0].message.ends_with("google::LogMessage::Flush()"));
+        let crashes = &crashes_by_instance[&(key.clone(), 1)];
+        assert_eq!(crashes.len(), 1);
+        assert_eq!(crashes[0].log_level, 'E');
+
+        let downtime = downtime(Some(&node_instances[0]), node_instances[1].started_at.unwrap());
+        assert_eq!(downtime, Some(("2021-04-08T10:05:00".parse().unwrap(),
+                                   Duration::seconds(60))));
+    }
+
+    #[test]
+    fn no_downtime_without_lines_before_the_restart() {
+        let started_at: NaiveDateTime = "2021-04-08T10:06:00".parse().unwrap();
+        let mut previous = ProcessInstance {
+            pid: Some(1234),
+            started_at: Some("2021-04-08T10:00:00".parse().unwrap()),
+            build: None,
+            num_files: 1,
+            first_line_at: None,
+            last_line_at: None,
+        };
+        assert_eq!(downtime(None, started_at), None);
+        assert_eq!(downtime(Some(&previous), started_at), None);
+        // Clock skew between the files may put the last line after the next start.
+        previous.last_line_at = Some("2021-04-08T10:06:01".parse().unwrap());
+        assert_eq!(downtime(Some(&previous), started_at), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::milliseconds(1500)), "1.500 s");
+        assert_eq!(format_duration(Duration::seconds(61)), "1m 01s");
+        assert_eq!(format_duration(Duration::seconds(3 * 3600 + 61)), "3h 01m 01s");
+    }
+}
@@ -0,0 +1,379 @@
+// ------------------------------------------------------------------------------------------------
+// User-defined extraction rules: named regexes from a TOML rules file whose capture groups become
+// typed fields of the lines they match, and --field filters on those fields.
+// ------------------------------------------------------------------------------------------------
+//
+// Example rules file:
+//
+// [[rules]]
+// name = "rpc"
+// regex = 'Call (?P<rpc_method>[\w.]+) from \S+ .*took (?P<duration_ms>\d+)ms'
+// fields = { duration_ms = "int" }
+//
+// Capture groups not listed under `fields` are strings.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+
+use regex::Regex;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FieldType {
+    String,
+    Int,
+    Float,
+}
+
+impl FieldType {
+    fn from_name(name: &str) -> Result<FieldType, String> {
+        match name {
+            "string" => Ok(FieldType::String),
+            "int" => Ok(FieldType::Int),
+            "float" => Ok(FieldType::Float),
+            _ => Err(format!("Unknown field type '{}': expected string, int or float", name)),
+        }
+    }
+
+    fn parse(&self, s: &str) -> Option<FieldValue> {
+        match self {
+            FieldType::String => Some(FieldValue::String(String::from(s))),
+            FieldType::Int => s.parse().ok().map(FieldValue::Int),
+            FieldType::Float => s.parse().ok().map(FieldValue::Float),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum FieldValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+}
+
+impl FieldValue {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::String(_) => None,
+            FieldValue::Int(value) => Some(*value as f64),
+            FieldValue::Float(value) => Some(*value),
+        }
+    }
+
+    /// Compares with a value given on the command line: numerically if this is a number and
+    /// `other` parses as one, as strings otherwise.
+    pub(crate) fn compare_to_str(&self, other: &str) -> Option<Ordering> {
+        if let FieldValue::String(value) = self {
+            return Some(value.as_str().cmp(other));
+        }
+        match (self.as_f64(), other.parse::<f64>().ok()) {
+            (Some(value), Some(other)) => value.partial_cmp(&other),
+            _ => Some(self.to_string().as_str().cmp(other)),
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldValue::String(value) => write!(f, "{}", value),
+            FieldValue::Int(value) => write!(f, "{}", value),
+            FieldValue::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Fields extracted from one line, in the order the rules declare them.
+pub(crate) type Fields = Vec<(String, FieldValue)>;
+
+struct Rule {
+    name: String,
+    regex: Regex,
+    /// Capture group names with the types of the fields they fill.
+    fields: Vec<(String, FieldType)>,
+}
+
+pub(crate) struct UserRules {
+    rules: Vec<Rule>,
+}
+
+fn rule_str<'a>(rule: &'a toml::value::Table, key: &str, index: usize) -> Result<&'a str, String> {
+    match rule.get(key) {
+        Some(toml::Value::String(value)) => Ok(value),
+        Some(_) => Err(format!("Rule {}: '{}' must be a string", index + 1, key)),
+        None => Err(format!("Rule {}: missing '{}'", index + 1, key)),
+    }
+}
+
+impl UserRules {
+    pub(crate) fn load(path: &str) -> Result<UserRules, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read rules file {}: {}", path, e))?;
+        UserRules::parse(&text).map_err(|e| format!("In rules file {}: {}", path, e))
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<UserRules, String> {
+        let document: toml::Value = text.parse().map_err(|e| format!("{}", e))?;
+        let rule_values = match document.get("rules") {
+            Some(toml::Value::Array(rule_values)) => rule_values,
+            _ => return Err(String::from("expected one or more [[rules]] tables")),
+        };
+        let mut rules = Vec::new();
+        for (index, rule_value) in rule_values.iter().enumerate() {
+            let rule = rule_value.as_table()
+                .ok_or_else(|| format!("Rule {}: expected a table", index + 1))?;
+            let name = rule_str(rule, "name", index)?;
+            let regex = Regex::new(rule_str(rule, "regex", index)?)
+                .map_err(|e| format!("Rule {}: invalid regex: {}", name, e))?;
+            let types = match rule.get("fields") {
+                Some(toml::Value::Table(types)) => Some(types),
+                Some(_) => return Err(format!("Rule {}: 'fields' must be a table", name)),
+                None => None,
+            };
+            if let Some(types) = types {
+                for field in types.keys() {
+                    if !regex.capture_names().any(|group| group == Some(field.as_str())) {
+                        return Err(format!("Rule {}: the regex has no capture group named '{}'",
+                                           name, field));
+                    }
+                }
+            }
+            let mut fields = Vec::new();
+            for group in regex.capture_names().flatten() {
+                let field_type = match types.and_then(|types| types.get(group)) {
+                    Some(toml::Value::String(type_name)) => FieldType::from_name(type_name)
+                        .map_err(|e| format!("Rule {}, field {}: {}", name, group, e))?,
+                    Some(_) => return Err(format!(
+                        "Rule {}, field {}: the type must be a string", name, group)),
+                    None => FieldType::String,
+                };
+                fields.push((String::from(group), field_type));
+            }
+            if fields.is_empty() {
+                return Err(format!("Rule {}: the regex has no named capture groups", name));
+            }
+            rules.push(Rule { name: String::from(name), regex, fields });
+        }
+        Ok(UserRules { rules })
+    }
+
+    /// All field names, in the order the rules declare them, without duplicates.
+    pub(crate) fn field_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _) in self.rules.iter().flat_map(|rule| rule.fields.iter()) {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Applies all rules to a message. If several rules fill the same field, the first one that
+    /// matches wins. Captures that do not parse as their field's type are left out.
+    pub(crate) fn extract(&self, message: &str) -> Fields {
+        let mut fields: Fields = Vec::new();
+        for rule in &self.rules {
+            let captures = match rule.regex.captures(message) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            for (name, field_type) in &rule.fields {
+                if fields.iter().any(|(existing, _)| existing == name) {
+                    continue;
+                }
+                let value = captures.name(name).and_then(|m| field_type.parse(m.as_str()));
+                if let Some(value) = value {
+                    fields.push((name.clone(), value));
+                }
+            }
+        }
+        fields
+    }
+
+    pub(crate) fn rule_names(&self) -> Vec<&str> {
+        self.rules.iter().map(|rule| rule.name.as_str()).collect()
+    }
+}
+
+/// A comparison operator, shared by --field filters and --where.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub(crate) fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// A --field filter such as `rpc_method=yb.tserver.TabletServerService.Write` or
+/// `duration_ms>=1000`. Lines without the field do not pass.
+#[derive(Clone, Debug)]
+pub(crate) struct FieldFilter {
+    pub(crate) name: String,
+    op: CompareOp,
+    value: String,
+}
+
+impl FieldFilter {
+    pub(crate) fn parse(s: &str) -> Result<FieldFilter, String> {
+        // Two-character operators go first so that e.g. ">=" is not read as ">".
+        const OPS: &[(&str, CompareOp)] = &[
+            ("!=", CompareOp::Ne), (">=", CompareOp::Ge), ("<=", CompareOp::Le),
+            ("=", CompareOp::Eq), (">", CompareOp::Gt), ("<", CompareOp::Lt),
+        ];
+        for (op_str, op) in OPS {
+            if let Some(op_start) = s.find(op_str) {
+                let name = s[..op_start].trim();
+                if name.is_empty() || name.contains(|c: char| "!=<>".contains(c)) {
+                    continue;
+                }
+                return Ok(FieldFilter {
+                    name: String::from(name),
+                    op: *op,
+                    value: String::from(s[op_start + op_str.len()..].trim()),
+                });
+            }
+        }
+        Err(format!("Could not parse field filter '{}': expected NAME=VALUE, NAME!=VALUE, \
+                     NAME<VALUE, NAME<=VALUE, NAME>VALUE or NAME>=VALUE", s))
+    }
+
+    pub(crate) fn matches(&self, fields: &[(String, FieldValue)]) -> bool {
+        let value = match fields.iter().find(|(name, _)| *name == self.name) {
+            Some((_, value)) => value,
+            None => return false,
+        };
+        value.compare_to_str(&self.value).is_some_and(|ordering| self.op.holds(ordering))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+[[rules]]
+name = "write"
+regex = 'Call (?P<method>yb\.tserver\.\w+\.Write) \S+ => \S+ .*took (?P<duration>\d+)ms'
+fields = { duration = "int" }
+
+[[rules]]
+name = "any_call"
+regex = 'Call (?P<method>[\w.]+) .*took (?P<duration>[\d.]+)(?P<unit>m?s)'
+fields = { duration = "float" }
+"#;
+
+    fn string(s: &str) -> FieldValue {
+        FieldValue::String(String::from(s))
+    }
+
+    #[test]
+    fn duplicate_field_names_are_filled_by_the_first_matching_rule() {
+        let rules = UserRules::parse(RULES).unwrap();
+        assert_eq!(rules.rule_names(), vec!["write", "any_call"]);
+        assert_eq!(rules.field_names(), vec!["method", "duration", "unit"]);
+        assert_eq!(
+            rules.extract("Call yb.tserver.TabletServerService.Write 10.0.0.1:41688 => \
+                           10.0.0.2:9100 (request call id 2451) took 1520ms"),
+            vec![
+                (String::from("method"), string("yb.tserver.TabletServerService.Write")),
+                (String::from("duration"), FieldValue::Int(1520)),
+                (String::from("unit"), string("ms")),
+            ]);
+        // Only the second rule matches, so its type applies.
+        assert_eq!(
+            rules.extract("Call yb.consensus.ConsensusService.UpdateConsensus 10.0.0.1:52814 => \
+                           10.0.0.2:9100 (request call id 12345) took 1.5s"),
+            vec![
+                (String::from("method"),
+                 string("yb.consensus.ConsensusService.UpdateConsensus")),
+                (String::from("duration"), FieldValue::Float(1.5)),
+                (String::from("unit"), string("s")),
+            ]);
+        assert_eq!(rules.extract("Starting election for term 71"), Fields::new());
+    }
+
+    #[test]
+    fn captures_that_do_not_parse_are_left_out() {
+        let rules = UserRules::parse(r#"
+[[rules]]
+name = "memory"
+regex = 'consumption (?P<bytes>\d+) of (?P<limit>\S+)'
+fields = { bytes = "int", limit = "int" }
+"#).unwrap();
+        assert_eq!(rules.extract("consumption 123 of unlimited"),
+                   vec![(String::from("bytes"), FieldValue::Int(123))]);
+        // Too large for an i64.
+        assert_eq!(rules.extract("consumption 99999999999999999999 of 1024"),
+                   vec![(String::from("limit"), FieldValue::Int(1024))]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| UserRules::parse(text).err().unwrap();
+        assert_eq!(
+            error("[[rules]]\nname = \"r\"\nregex = '(?P<a>x)'\nfields = { b = \"int\" }\n"),
+            "Rule r: the regex has no capture group named 'b'");
+        assert_eq!(
+            error("[[rules]]\nname = \"r\"\nregex = '(?P<a>x)'\nfields = { a = \"date\" }\n"),
+            "Rule r, field a: Unknown field type 'date': expected string, int or float");
+        assert_eq!(error("[[rules]]\nname = \"r\"\nregex = '(x)'\n"),
+                   "Rule r: the regex has no named capture groups");
+        assert_eq!(error("[[rules]]\nregex = '(?P<a>x)'\n"), "Rule 1: missing 'name'");
+        assert!(error("[[rules]]\nname = \"r\"\nregex = '(?P<a>x'\n")
+            .starts_with("Rule r: invalid regex: "));
+        assert_eq!(error("[rules]\nname = \"r\"\n"), "expected one or more [[rules]] tables");
+    }
+
+    fn filter(s: &str) -> (String, CompareOp, String) {
+        let filter = FieldFilter::parse(s).unwrap();
+        (filter.name, filter.op, filter.value)
+    }
+
+    #[test]
+    fn field_filter_operators() {
+        let parsed = |name: &str, op: CompareOp, value: &str| {
+            (String::from(name), op, String::from(value))
+        };
+        assert_eq!(filter("duration>=1000"), parsed("duration", CompareOp::Ge, "1000"));
+        assert_eq!(filter("duration>1000"), parsed("duration", CompareOp::Gt, "1000"));
+        assert_eq!(filter("duration <= 5"), parsed("duration", CompareOp::Le, "5"));
+        assert_eq!(filter("method!=Write"), parsed("method", CompareOp::Ne, "Write"));
+        // Operators after the first one are part of the value.
+        assert_eq!(filter("msg=a>b"), parsed("msg", CompareOp::Eq, "a>b"));
+        assert_eq!(filter("msg>a=b"), parsed("msg", CompareOp::Gt, "a=b"));
+        assert_eq!(filter("msg=>=b"), parsed("msg", CompareOp::Eq, ">=b"));
+        assert!(FieldFilter::parse("=5").is_err());
+        assert!(FieldFilter::parse("duration").is_err());
+    }
+
+    #[test]
+    fn field_filters_compare_numbers_numerically() {
+        let fields = vec![
+            (String::from("duration"), FieldValue::Int(1520)),
+            (String::from("method"), string("Write")),
+        ];
+        let matches = |s: &str| FieldFilter::parse(s).unwrap().matches(&fields);
+        assert!(matches("duration>=1520"));
+        assert!(!matches("duration>1520"));
+        assert!(matches("duration>999"));
+        assert!(matches("duration=1520.0"));
+        assert!(matches("method=Write"));
+        assert!(!matches("method<Read"));
+        assert!(!matches("missing=1"));
+    }
+}
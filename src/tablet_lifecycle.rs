@@ -0,0 +1,383 @@
+// ------------------------------------------------------------------------------------------------
+// Tablet lifecycle: creation, remote bootstrap, tombstoning, deletion and splitting of each
+// tablet on each node, with the duration and outcome of every remote bootstrap.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use regex::Regex;
+use uuid::Uuid;
+
+use yblp::parse_regex;
+
+use crate::YBLogLine;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum LifecycleEventKind {
+    Created,
+    /// Created as a child of a split tablet.
+    CreatedBySplit,
+    RemoteBootstrapStarted,
+    RemoteBootstrapDone,
+    RemoteBootstrapFailed,
+    Tombstoned,
+    Deleted,
+    Split,
+}
+
+struct LifecycleEvent {
+    timestamp: NaiveDateTime,
+    kind: LifecycleEventKind,
+    /// The parent of a child tablet, or the children of a split tablet.
+    related_tablets: Vec<Uuid>,
+}
+
+#[derive(PartialEq, Debug)]
+enum BootstrapOutcome {
+    Succeeded,
+    Failed,
+    /// Started but never finished in the logs.
+    Incomplete,
+}
+
+/// A remote bootstrap of a tablet onto a node.
+struct RemoteBootstrap {
+    tablet_id: Uuid,
+    node: String,
+    started_at: NaiveDateTime,
+    finished_at: Option<NaiveDateTime>,
+    outcome: BootstrapOutcome,
+}
+
+impl RemoteBootstrap {
+    fn duration(&self) -> Option<Duration> {
+        self.finished_at.map(|finished_at| finished_at - self.started_at)
+    }
+}
+
+/// Remote bootstrap start, failure and completion messages, tagged with the given kinds. Failures
+/// go before completions, as failure messages often mention what did not complete.
+pub(crate) fn remote_bootstrap_events<K>(started: K, failed: K, done: K) -> Vec<(Regex, K)> {
+    vec![
+        (parse_regex(r"(?i)beginning remote bootstrap"), started),
+        (parse_regex(r"(?i)remote bootstrap.*(?:failed|error)"), failed),
+        (parse_regex(r"(?i)remote bootstrap (?:complete|finished|succeeded)"), done),
+    ]
+}
+
+struct LifecycleRegexes {
+    peer_prefix_re: Regex,
+    tablet_id_re: Regex,
+    /// Lifecycle events, in the order they are tried. Failures go before completions since
+    /// failure messages often mention what did not complete.
+    events: Vec<(Regex, LifecycleEventKind)>,
+}
+
+impl LifecycleRegexes {
+    fn new() -> LifecycleRegexes {
+        LifecycleRegexes {
+            peer_prefix_re: parse_regex(r"^T [0-9a-f]{32} P [0-9a-f]+(?: \[[^\]]*\])?: "),
+            tablet_id_re: parse_regex(r"\b[0-9a-f]{32}\b"),
+            events: remote_bootstrap_events(
+                LifecycleEventKind::RemoteBootstrapStarted,
+                LifecycleEventKind::RemoteBootstrapFailed,
+                LifecycleEventKind::RemoteBootstrapDone,
+            ).into_iter().chain(vec![
+                (parse_regex(r"TABLET_DATA_TOMBSTONED|(?i:tombstoned tablet)"),
+                 LifecycleEventKind::Tombstoned),
+                (parse_regex(r"TABLET_DATA_DELETED|(?i:deleted tablet\b|tablet deleted)"),
+                 LifecycleEventKind::Deleted),
+                (parse_regex(r"(?i)\bsplitting tablet\b|\btablet split\b|\bsplit tablet\b"),
+                 LifecycleEventKind::Split),
+                (parse_regex(r"(?i)\bcreat(?:ing|ed)(?: new)? tablet\b"),
+                 LifecycleEventKind::Created),
+            ]).collect(),
+        }
+    }
+}
+
+/// Events of each tablet on each node, in time order.
+type TabletEvents = BTreeMap<Uuid, BTreeMap<String, Vec<LifecycleEvent>>>;
+
+fn collect_events<I: Iterator<Item = YBLogLine>>(lines: I) -> TabletEvents {
+    let regexes = LifecycleRegexes::new();
+    let mut events: TabletEvents = BTreeMap::new();
+    for line in lines {
+        let kind = match regexes.events.iter().find(|(re, _)| re.is_match(&line.message)) {
+            Some((_, kind)) => *kind,
+            None => continue,
+        };
+        // Tablet ids after the "T <tablet> P <peer>: " prefix, e.g. the children of a split.
+        let body = match regexes.peer_prefix_re.find(&line.message) {
+            Some(prefix) => &line.message[prefix.end()..],
+            None => line.message.as_str(),
+        };
+        let mentioned: Vec<Uuid> = regexes.tablet_id_re.find_iter(body)
+            .filter_map(|m| Uuid::parse_str(m.as_str()).ok())
+            .filter(|id| Some(*id) != line.tablet_id)
+            .collect();
+        // Master lines name the tablet without the "T " prefix.
+        let tablet_id = match line.tablet_id.or_else(|| mentioned.first().copied()) {
+            Some(tablet_id) => tablet_id,
+            None => continue,
+        };
+        let node = line.source.node.clone().unwrap_or_default();
+        let related_tablets: Vec<Uuid> = if kind == LifecycleEventKind::Split {
+            mentioned.into_iter().filter(|id| *id != tablet_id).collect()
+        } else {
+            Vec::new()
+        };
+        for child in &related_tablets {
+            events.entry(*child).or_default().entry(node.clone()).or_default()
+                .push(LifecycleEvent {
+                    timestamp: line.timestamp,
+                    kind: LifecycleEventKind::CreatedBySplit,
+                    related_tablets: vec![tablet_id],
+                });
+        }
+        events.entry(tablet_id).or_default().entry(node).or_default().push(LifecycleEvent {
+            timestamp: line.timestamp,
+            kind,
+            related_tablets,
+        });
+    }
+    events
+}
+
+/// Pairs the remote bootstrap events of a tablet on a node. A start that is followed by another
+/// start before it finishes is counted as incomplete.
+fn remote_bootstraps(tablet_id: Uuid, node: &str, events: &[LifecycleEvent])
+        -> Vec<RemoteBootstrap> {
+    let mut bootstraps: Vec<RemoteBootstrap> = Vec::new();
+    let mut pending: Option<NaiveDateTime> = None;
+    for event in events {
+        let outcome = match event.kind {
+            LifecycleEventKind::RemoteBootstrapStarted => {
+                if let Some(started_at) = pending.replace(event.timestamp) {
+                    bootstraps.push(RemoteBootstrap {
+                        tablet_id,
+                        node: String::from(node),
+                        started_at,
+                        finished_at: None,
+                        outcome: BootstrapOutcome::Incomplete,
+                    });
+                }
+                continue;
+            }
+            LifecycleEventKind::RemoteBootstrapDone => BootstrapOutcome::Succeeded,
+            LifecycleEventKind::RemoteBootstrapFailed => BootstrapOutcome::Failed,
+            _ => continue,
+        };
+        bootstraps.push(RemoteBootstrap {
+            tablet_id,
+            node: String::from(node),
+            started_at: pending.take().unwrap_or(event.timestamp),
+            finished_at: Some(event.timestamp),
+            outcome,
+        });
+    }
+    if let Some(started_at) = pending {
+        bootstraps.push(RemoteBootstrap {
+            tablet_id,
+            node: String::from(node),
+            started_at,
+            finished_at: None,
+            outcome: BootstrapOutcome::Incomplete,
+        });
+    }
+    bootstraps
+}
+
+fn format_event(event: &LifecycleEvent) -> String {
+    let time = event.timestamp.time().to_string();
+    let related: Vec<String> =
+        event.related_tablets.iter().map(|id| id.to_simple().to_string()).collect();
+    match event.kind {
+        LifecycleEventKind::CreatedBySplit => format!("{} CreatedBySplit of {}", time, related[0]),
+        LifecycleEventKind::Split if !related.is_empty() =>
+            format!("{} Split into {}", time, related.join(", ")),
+        kind => format!("{} {:?}", time, kind),
+    }
+}
+
+/// Prints the lifecycle events of each tablet on each node, the `top` slowest remote bootstraps,
+/// and all remote bootstraps that failed or did not finish in the logs.
+pub(crate) fn print_tablet_lifecycle_report<I: Iterator<Item = YBLogLine>>(lines: I, top: usize) {
+    let events = collect_events(lines);
+
+    let mut bootstraps: Vec<RemoteBootstrap> = Vec::new();
+    let mut counts: HashMap<LifecycleEventKind, usize> = HashMap::new();
+    for (tablet_id, nodes) in &events {
+        for (node, node_events) in nodes {
+            bootstraps.extend(remote_bootstraps(*tablet_id, node, node_events));
+            for event in node_events {
+                *counts.entry(event.kind).or_default() += 1;
+            }
+        }
+    }
+    let count = |kind: LifecycleEventKind| counts.get(&kind).copied().unwrap_or(0);
+    let num_of = |outcome: fn(&BootstrapOutcome) -> bool| {
+        bootstraps.iter().filter(|bootstrap| outcome(&bootstrap.outcome)).count()
+    };
+    println!("Found lifecycle events of {} tablets: {} created, {} split, {} tombstoned, \
+              {} deleted",
+             events.len(),
+             count(LifecycleEventKind::Created) + count(LifecycleEventKind::CreatedBySplit),
+             count(LifecycleEventKind::Split),
+             count(LifecycleEventKind::Tombstoned),
+             count(LifecycleEventKind::Deleted));
+    println!("Remote bootstraps: {} succeeded, {} failed, {} incomplete",
+             num_of(|outcome| matches!(outcome, BootstrapOutcome::Succeeded)),
+             num_of(|outcome| matches!(outcome, BootstrapOutcome::Failed)),
+             num_of(|outcome| matches!(outcome, BootstrapOutcome::Incomplete)));
+
+    println!();
+    println!("Tablet lifecycles");
+    for (tablet_id, nodes) in &events {
+        println!("{}", tablet_id.to_simple());
+        for (node, node_events) in nodes {
+            let formatted: Vec<String> = node_events.iter().map(format_event).collect();
+            println!("  {:<24}  {}  {}",
+                     node, node_events[0].timestamp.date(), formatted.join(", "));
+        }
+    }
+
+    let mut finished: Vec<&RemoteBootstrap> =
+        bootstraps.iter().filter(|bootstrap| bootstrap.finished_at.is_some()).collect();
+    finished.sort_by(|a, b| b.duration().cmp(&a.duration())
+        .then_with(|| a.started_at.cmp(&b.started_at)));
+    println!();
+    println!("Slowest remote bootstraps (showing up to {} of {})", top, finished.len());
+    println!("{:>12}  {:<26}  {:<24}  {:<32}  outcome", "duration s", "started at", "node",
+             "tablet");
+    for bootstrap in finished.iter().take(top) {
+        println!("{:>12.3}  {:<26}  {:<24}  {:<32}  {:?}",
+                 bootstrap.duration().unwrap().num_milliseconds() as f64 / 1000.0,
+                 bootstrap.started_at.to_string(), bootstrap.node,
+                 bootstrap.tablet_id.to_simple().to_string(), bootstrap.outcome);
+    }
+
+    let mut unsuccessful: Vec<&RemoteBootstrap> = bootstraps.iter()
+        .filter(|bootstrap| !matches!(bootstrap.outcome, BootstrapOutcome::Succeeded))
+        .collect();
+    unsuccessful.sort_by_key(|bootstrap| bootstrap.started_at);
+    println!();
+    println!("Failed or incomplete remote bootstraps ({})", unsuccessful.len());
+    println!("{:<26}  {:<26}  {:<24}  {:<32}  outcome", "started at", "finished at", "node",
+             "tablet");
+    for bootstrap in &unsuccessful {
+        println!("{:<26}  {:<26}  {:<24}  {:<32}  {:?}",
+                 bootstrap.started_at.to_string(),
+                 bootstrap.finished_at.map_or(String::from("-"), |ts| ts.to_string()),
+                 bootstrap.node, bootstrap.tablet_id.to_simple().to_string(), bootstrap.outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    const TABLET_A: &str = "0123456789abcdef0123456789abcdef";
+    const TABLET_B: &str = "11111111111111111111111111111111";
+    const TABLET_C: &str = "22222222222222222222222222222222";
+    const PEER: &str = "3f1a2b3c4d5e4f60a1b2c3d4e5f60718";
+
+    fn tablet(id: &str) -> Uuid {
+        Uuid::parse_str(id).unwrap()
+    }
+
+    fn events(lines: &[(&str, String)]) -> TabletEvents {
+        let master = source(0, "m1", "yb-master.m1.yugabyte.log.INFO.20210408-100000.2000");
+        let n1 = source(1, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        collect_events(lines.iter()
+            .map(|(node, text)| glog_line(if *node == "m1" { &master } else { &n1 }, text))
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    fn tserver_line(time: &str, tablet_id: &str, message: &str) -> (&'static str, String) {
+        ("n1", format!("I0408 {}.000000  1240 ts_tablet_manager.cc:1000] T {} P {}: {}",
+                       time, tablet_id, PEER, message))
+    }
+
+    #[test]
+    fn remote_bootstrap_messages() {
+        let events = remote_bootstrap_events("started", "failed", "done");
+        let kind = |message: &str| {
+            events.iter().find(|(re, _)| re.is_match(message)).map(|(_, kind)| *kind)
+        };
+        assert_eq!(kind("Beginning remote bootstrap session from remote peer at address \
+                         10.0.0.1:9100"), Some("started"));
+        assert_eq!(kind("Remote bootstrap complete. Replacing tablet superblock."),
+                   Some("done"));
+        assert_eq!(kind("Remote bootstrap failed: Network error (yb/rpc/outbound_call.cc:512): \
+                         recvmsg error: Connection refused"), Some("failed"));
+        // A failure that mentions what did not finish.
+        assert_eq!(kind("Remote bootstrap finished with error: Timed out"), Some("failed"));
+        assert_eq!(kind("Tablet bootstrap complete"), None);
+    }
+
+    #[test]
+    fn events_of_tablets_on_nodes() {
+        let events = events(&[
+            ("m1", format!("I0408 10:00:00.000000  2000 catalog_manager.cc:4500] \
+                            Created tablet {} for table usertable", TABLET_A)),
+            tserver_line("10:00:01", TABLET_A, "Creating new tablet"),
+            tserver_line("10:01:00", TABLET_A,
+                         &format!("Starting tablet split, new tablet ids: {}, {}",
+                                  TABLET_B, TABLET_C)),
+            tserver_line("10:02:00", TABLET_A,
+                         "Deleting tablet data with delete state TABLET_DATA_TOMBSTONED"),
+            tserver_line("10:03:00", TABLET_A,
+                         "Deleting tablet data with delete state TABLET_DATA_DELETED"),
+            tserver_line("10:04:00", TABLET_A, "Rolled over log segment to 12"),
+        ]);
+        let formatted = |tablet_id: &str, node: &str| -> Vec<String> {
+            events[&tablet(tablet_id)][node].iter().map(format_event).collect()
+        };
+        assert_eq!(events.len(), 3);
+        assert_eq!(formatted(TABLET_A, "m1"), ["10:00:00 Created"]);
+        assert_eq!(formatted(TABLET_A, "n1"), [
+            String::from("10:00:01 Created"),
+            format!("10:01:00 Split into {}, {}", TABLET_B, TABLET_C),
+            String::from("10:02:00 Tombstoned"),
+            String::from("10:03:00 Deleted"),
+        ]);
+        assert_eq!(formatted(TABLET_B, "n1"),
+                   [format!("10:01:00 CreatedBySplit of {}", TABLET_A)]);
+        assert_eq!(formatted(TABLET_C, "n1"),
+                   [format!("10:01:00 CreatedBySplit of {}", TABLET_A)]);
+    }
+
+    #[test]
+    fn remote_bootstraps_are_paired() {
+        let events = events(&[
+            tserver_line("10:00:00", TABLET_A, "Beginning remote bootstrap session from remote \
+                                                peer at address 10.0.0.1:9100"),
+            // Started again before the first one finished.
+            tserver_line("10:00:10", TABLET_A, "Beginning remote bootstrap session from remote \
+                                                peer at address 10.0.0.2:9100"),
+            tserver_line("10:00:15", TABLET_A,
+                         "Remote bootstrap complete. Replacing tablet superblock."),
+            // A failure without a start.
+            tserver_line("10:01:00", TABLET_A, "Remote bootstrap failed: Timed out"),
+            tserver_line("10:02:00", TABLET_A, "Beginning remote bootstrap session from remote \
+                                                peer at address 10.0.0.1:9100"),
+        ]);
+        let bootstraps = remote_bootstraps(
+            tablet(TABLET_A), "n1", &events[&tablet(TABLET_A)]["n1"]);
+        let summary: Vec<_> = bootstraps.iter()
+            .map(|bootstrap| (bootstrap.started_at.time().to_string(),
+                              bootstrap.duration().map(|duration| duration.num_seconds()),
+                              &bootstrap.outcome))
+            .collect();
+        assert_eq!(summary, [
+            (String::from("10:00:00"), None, &BootstrapOutcome::Incomplete),
+            (String::from("10:00:10"), Some(5), &BootstrapOutcome::Succeeded),
+            (String::from("10:01:00"), Some(0), &BootstrapOutcome::Failed),
+            (String::from("10:02:00"), None, &BootstrapOutcome::Incomplete),
+        ]);
+    }
+}
@@ -0,0 +1,55 @@
+// ------------------------------------------------------------------------------------------------
+// Test helpers: log sources and lines built from verbatim log lines, for the reports' tests.
+// ------------------------------------------------------------------------------------------------
+
+use std::sync::Arc;
+
+use chrono::Duration;
+
+use yblp::{parse_glog_file_name, LogLineRef, RegexHolder};
+
+use crate::external_sort::MergedLines;
+use crate::{LogSource, ProcessInfo, YBLogLine};
+
+/// A glog file such as `yb-tserver.host.user.log.INFO.20210408-100000.1234` on `node`, with the
+/// program and pid taken from the file name and no preamble.
+pub(crate) fn log_source(index: usize, node: &str, file_name: &str) -> LogSource {
+    let (program, pid) = parse_glog_file_name(file_name);
+    LogSource {
+        node: Some(String::from(node)),
+        path: format!("/logs/{}/{}", node, file_name),
+        index,
+        process: ProcessInfo { program, pid, started_at: None, build: None },
+        clock_offset: Duration::zero(),
+    }
+}
+
+pub(crate) fn source(index: usize, node: &str, file_name: &str) -> Arc<LogSource> {
+    Arc::new(log_source(index, node, file_name))
+}
+
+/// Parses a verbatim glog line, taking the year to be 2021. Lines after the first one are
+/// continuation lines of its message.
+pub(crate) fn glog_line(source: &Arc<LogSource>, text: &str) -> YBLogLine {
+    let mut text_lines = text.split('\n');
+    let header = text_lines.next().unwrap();
+    let regexes = RegexHolder::new();
+    let parsed = LogLineRef::parse(header, &regexes)
+        .unwrap_or_else(|| panic!("Not a glog line: {}", header));
+    let mut line = YBLogLine::from_ref(&parsed, parsed.timestamp.with_year(2021), source, 0);
+    for continuation_line in text_lines {
+        line.message.push('\n');
+        line.message.push_str(continuation_line);
+    }
+    line
+}
+
+/// Merges lines the way `load_lines` returns them. Each line's file offset is set to its position
+/// in `lines`, so that lines with equal timestamps keep their order within a source.
+pub(crate) fn merged_lines(sources: &[Arc<LogSource>], mut lines: Vec<YBLogLine>) -> MergedLines {
+    for (file_offset, line) in lines.iter_mut().enumerate() {
+        line.file_offset = file_offset as u64;
+    }
+    lines.sort_by(|a, b| a.ordering_key().cmp(&b.ordering_key()));
+    MergedLines::new(Vec::new(), vec![lines], sources.to_vec()).unwrap()
+}
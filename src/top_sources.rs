@@ -0,0 +1,170 @@
+// ------------------------------------------------------------------------------------------------
+// Top source locations: line counts by the file:line of the logging statement.
+// ------------------------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+use yblp::bucket_start;
+
+use crate::processes::without_severity_copies;
+use crate::YBLogLine;
+
+const MAX_SAMPLE_LEN: usize = 120;
+
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SourceLocationKey {
+    bucket: Option<NaiveDateTime>,
+    node: Option<String>,
+    file_name: String,
+    line_number: i32,
+    log_level: char,
+}
+
+struct SourceLocationStats {
+    count: u64,
+    sample_message: String,
+}
+
+/// Returns the first line of a message, shortened to at most `MAX_SAMPLE_LEN` characters.
+pub(crate) fn sample_message(message: &str) -> String {
+    let first_line = message.lines().next().unwrap_or("");
+    match first_line.char_indices().nth(MAX_SAMPLE_LEN) {
+        Some((end, _)) => format!("{}...", &first_line[..end]),
+        None => String::from(first_line),
+    }
+}
+
+/// Counts lines per (file_name, line_number, level), optionally split by node and time bucket.
+/// Rows are ordered by bucket, then most frequent first. Also returns the number of lines.
+fn count_by_location<I: Iterator<Item = YBLogLine>>(
+        lines: I,
+        per_node: bool,
+        bucket: Option<Duration>) -> (Vec<(SourceLocationKey, SourceLocationStats)>, u64) {
+    let mut stats_by_location: HashMap<SourceLocationKey, SourceLocationStats> = HashMap::new();
+    let mut num_lines: u64 = 0;
+    for line in lines {
+        num_lines += 1;
+        let key = SourceLocationKey {
+            bucket: bucket.map(|bucket| bucket_start(line.timestamp, bucket)),
+            node: if per_node { Some(line.source.node.clone().unwrap_or_default()) } else { None },
+            file_name: line.file_name,
+            line_number: line.line_number,
+            log_level: line.log_level,
+        };
+        let message = &line.message;
+        stats_by_location.entry(key).or_insert_with(|| SourceLocationStats {
+            count: 0,
+            sample_message: sample_message(message),
+        }).count += 1;
+    }
+
+    let mut rows: Vec<(SourceLocationKey, SourceLocationStats)> =
+        stats_by_location.into_iter().collect();
+    rows.sort_by(|a, b| a.0.bucket.cmp(&b.0.bucket)
+        .then_with(|| b.1.count.cmp(&a.1.count))
+        .then_with(|| a.0.cmp(&b.0)));
+    (rows, num_lines)
+}
+
+/// Prints the `top` source locations (per bucket, if bucketed) with a sample message for each.
+/// Lines that glog copies into the log files of lower severity are counted once.
+pub(crate) fn print_top_sources_report<I: Iterator<Item = YBLogLine>>(
+        lines: I,
+        top: usize,
+        per_node: bool,
+        bucket: Option<Duration>) {
+    let mut lines = without_severity_copies(lines);
+    let (rows, num_lines) = count_by_location(&mut lines, per_node, bucket);
+
+    lines.print_note();
+    println!("Found {} source locations in {} lines", rows.len(), num_lines);
+    println!("{}{}{:>10}  level  {:<40}  sample message",
+             if bucket.is_some() { format!("{:<21}", "bucket") } else { String::new() },
+             if per_node { format!("{:<24}", "node") } else { String::new() },
+             "count",
+             "source");
+    let mut current_bucket: Option<NaiveDateTime> = None;
+    let mut rows_in_bucket: usize = 0;
+    for (key, stats) in &rows {
+        if key.bucket != current_bucket {
+            current_bucket = key.bucket;
+            rows_in_bucket = 0;
+        }
+        if rows_in_bucket >= top {
+            continue;
+        }
+        rows_in_bucket += 1;
+        println!("{}{}{:>10}  {:<5}  {:<40}  {}",
+                 match key.bucket {
+                     Some(bucket_ts) => format!("{:<21}", bucket_ts.to_string()),
+                     None => String::new(),
+                 },
+                 match &key.node {
+                     Some(node) => format!("{:<24}", node),
+                     None => String::new(),
+                 },
+                 stats.count,
+                 key.log_level,
+                 format!("{}:{}", key.file_name, key.line_number),
+                 stats.sample_message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_lines::{glog_line, source};
+
+    #[test]
+    fn sample_messages_are_first_lines_cut_short() {
+        assert_eq!(sample_message("Check failed: s.ok()\n    @ 0x7f1234 yb::Foo()"),
+                   "Check failed: s.ok()");
+        let long_message = "x".repeat(MAX_SAMPLE_LEN + 1);
+        assert_eq!(sample_message(&long_message),
+                   format!("{}...", "x".repeat(MAX_SAMPLE_LEN)));
+        assert_eq!(sample_message(&"é".repeat(MAX_SAMPLE_LEN)), "é".repeat(MAX_SAMPLE_LEN));
+    }
+
+    #[test]
+    fn lines_are_counted_per_location_node_and_bucket() {
+        let info = source(0, "n1", "yb-tserver.n1.yugabyte.log.INFO.20210408-100000.1234");
+        let warning = source(1, "n1", "yb-tserver.n1.yugabyte.log.WARNING.20210408-100000.1234");
+        let n2 = source(2, "n2", "yb-tserver.n2.yugabyte.log.INFO.20210408-100000.5678");
+        let slow_write = "W0408 10:00:01.000000  1234 yb_rpc.cc:400] \
+            Call yb.tserver.TabletServerService.Write from 10.1.2.3:45678 took 1234ms";
+        let lines = vec![
+            glog_line(&warning, slow_write),
+            glog_line(&info, slow_write),
+            glog_line(&info, "I0408 10:00:02.000000  1234 log.cc:100] \
+                              Rolled over log segment to 12 (size 67108864)"),
+            glog_line(&n2, "I0408 10:01:30.000000  5678 log.cc:100] \
+                            Rolled over log segment to 7 (size 67108864)"),
+            glog_line(&n2, "I0408 10:01:31.000000  5678 log.cc:100] \
+                            Rolled over log segment to 8 (size 67108864)"),
+        ];
+
+        let (rows, num_lines) =
+            count_by_location(without_severity_copies(lines.clone().into_iter()), false, None);
+        assert_eq!(num_lines, 4);
+        let counts: Vec<(&str, i32, char, u64)> = rows.iter()
+            .map(|(key, stats)| (key.file_name.as_str(), key.line_number, key.log_level,
+                                 stats.count))
+            .collect();
+        assert_eq!(counts, [("log.cc", 100, 'I', 3), ("yb_rpc.cc", 400, 'W', 1)]);
+        assert_eq!(rows[0].1.sample_message, "Rolled over log segment to 12 (size 67108864)");
+
+        let (rows, _) = count_by_location(
+            without_severity_copies(lines.into_iter()), true, Some(Duration::minutes(1)));
+        let keys: Vec<(String, Option<&str>, &str, u64)> = rows.iter()
+            .map(|(key, stats)| (key.bucket.unwrap().to_string(), key.node.as_deref(),
+                                 key.file_name.as_str(), stats.count))
+            .collect();
+        assert_eq!(keys, [
+            (String::from("2021-04-08 10:00:00"), Some("n1"), "log.cc", 1),
+            (String::from("2021-04-08 10:00:00"), Some("n1"), "yb_rpc.cc", 1),
+            (String::from("2021-04-08 10:01:00"), Some("n2"), "log.cc", 2),
+        ]);
+    }
+}